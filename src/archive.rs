@@ -0,0 +1,133 @@
+use std::io;
+use std::io::prelude::*;
+use std::fs::File;
+use bincode;
+use saving::SaverLoader;
+use inspect::{save_kinds,save_kind_for};
+use utils::funcs::fnv1a_64;
+use engine::server_game::JOURNAL_NAME;
+
+/*
+A whole world's authoritative server state in one portable file. Only save keys the
+game actually reads are packed (see inspect::save_kinds), so machine-specific things
+like temp_assets/ and client caches stay behind.
+On disk: the magic bytes, then a bincode WorldArchive.
+*/
+
+const ARCHIVE_MAGIC : &'static [u8] = b"MPSWORLD";
+const ARCHIVE_VERSION : u32 = 1;
+
+#[derive(Serialize,Deserialize,Debug)]
+struct ManifestEntry {
+    save_key : String,
+    type_name : String,
+    len : u64,
+    checksum : u64,
+}
+
+//manifest[i] describes blobs[i]
+#[derive(Serialize,Deserialize,Debug)]
+struct WorldArchive {
+    version : u32,
+    manifest : Vec<ManifestEntry>,
+    blobs : Vec<Vec<u8>>,
+}
+
+fn invalid(msg : String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//returns the number of save keys packed
+pub fn export(sl : &SaverLoader, archive_path : &str) -> Result<usize,io::Error> {
+    if sl.load_raw(JOURNAL_NAME).map(|j| !j.is_empty()).unwrap_or(false) {
+        return Err(io::Error::new(io::ErrorKind::Other,
+            "the journal holds changes that aren't in a save yet. Start and stop the server once first"));
+    }
+    let kinds = save_kinds();
+    let mut archive = WorldArchive {
+        version : ARCHIVE_VERSION,
+        manifest : vec![],
+        blobs : vec![],
+    };
+    for save_key in sl.list_save_keys()? {
+        let kind = match save_kind_for(&kinds, &save_key) {
+            Some(kind) if kind.authoritative => kind,
+            _ => continue,
+        };
+        let bytes = sl.load_raw(&save_key)?;
+        if let Err(e) = kind.decode(&bytes) {
+            return Err(invalid(format!("`{}` is corrupt, not exporting: {}", save_key, e)));
+        }
+        println!("Packing {} ({} bytes)", save_key, bytes.len());
+        archive.manifest.push(ManifestEntry {
+            save_key : save_key,
+            type_name : kind.type_name.to_owned(),
+            len : bytes.len() as u64,
+            checksum : fnv1a_64(&bytes),
+        });
+        archive.blobs.push(bytes);
+    }
+    let mut f = File::create(archive_path)?;
+    f.write_all(ARCHIVE_MAGIC)?;
+    f.write_all(
+        & bincode::serialize(&archive, bincode::Infinite)
+        .expect("couldn't serialize world archive!")
+    )?;
+    Ok(archive.manifest.len())
+}
+
+fn read_archive(archive_path : &str) -> Result<WorldArchive,io::Error> {
+    let mut buffer = vec![];
+    File::open(archive_path)?.read_to_end(&mut buffer)?;
+    if ! buffer.starts_with(ARCHIVE_MAGIC) {
+        return Err(invalid(format!("{} is not a world archive", archive_path)));
+    }
+    let archive : WorldArchive = bincode::deserialize(&buffer[ARCHIVE_MAGIC.len()..])
+        .map_err(|e| invalid(format!("archive is damaged: {}", e)))?;
+    if archive.version != ARCHIVE_VERSION {
+        return Err(invalid(format!("archive has version {}. This build reads version {}",
+            archive.version, ARCHIVE_VERSION)));
+    }
+    if archive.manifest.len() != archive.blobs.len() {
+        return Err(invalid("archive manifest doesn't match its contents".to_owned()));
+    }
+    Ok(archive)
+}
+
+/*
+Unpacks into the (locked) save dir. Everything is validated before anything is written:
+sizes, checksums, the type each key should hold, and that each blob decodes as that type.
+Refuses to overwrite a dir that already holds a world.
+Returns the number of save keys unpacked
+*/
+pub fn import(sl : &SaverLoader, archive_path : &str) -> Result<usize,io::Error> {
+    let archive = read_archive(archive_path)?;
+    let kinds = save_kinds();
+    for (entry, blob) in archive.manifest.iter().zip(archive.blobs.iter()) {
+        if entry.save_key.contains('/') || entry.save_key.contains('\\') || entry.save_key.starts_with('.') {
+            return Err(invalid(format!("archive has a bad save key `{}`", entry.save_key)));
+        }
+        if blob.len() as u64 != entry.len || fnv1a_64(blob) != entry.checksum {
+            return Err(invalid(format!("`{}` fails its checksum", entry.save_key)));
+        }
+        match save_kind_for(&kinds, &entry.save_key) {
+            Some(kind) if kind.authoritative && kind.type_name == entry.type_name => {
+                if let Err(e) = kind.decode(blob) {
+                    return Err(invalid(format!("`{}` doesn't decode as {}: {}", entry.save_key, kind.type_name, e)));
+                }
+            },
+            _ => return Err(invalid(format!("`{}` can't hold a {}", entry.save_key, entry.type_name))),
+        }
+    }
+    for save_key in sl.list_save_keys()? {
+        if save_kind_for(&kinds, &save_key).map(|k| k.authoritative).unwrap_or(false) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                format!("save dir already holds a world (found `{}`). Import into an empty dir", save_key)));
+        }
+    }
+    for (entry, blob) in archive.manifest.iter().zip(archive.blobs.iter()) {
+        sl.save_raw(&entry.save_key, blob)?;
+        println!("Unpacked {} ({} bytes)", entry.save_key, blob.len());
+    }
+    Ok(archive.manifest.len())
+}
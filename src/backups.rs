@@ -0,0 +1,179 @@
+use std::io;
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::time::{Duration,SystemTime,UNIX_EPOCH};
+use saving::{SaverLoader,LOCK_FILE_NAME};
+
+/*
+Timestamped snapshots of a whole save dir. Each snapshot is a plain copy of the dir,
+named `backup_<unix seconds>` inside the backup dir. Pruning keeps the newest snapshot of
+each of the last `keep_hourly` hours and each of the last `keep_daily` days.
+*/
+
+const BACKUP_PREFIX : &'static str = "backup_";
+const PARTIAL_SUFFIX : &'static str = ".partial";
+const SECS_PER_HOUR : u64 = 60 * 60;
+const SECS_PER_DAY : u64 = 24 * SECS_PER_HOUR;
+
+#[derive(Debug,Clone)]
+pub struct BackupPolicy {
+    pub dir : PathBuf,
+    pub interval : Duration,
+    pub keep_hourly : usize,
+    pub keep_daily : usize,
+}
+
+impl BackupPolicy {
+    pub const DEFAULT_INTERVAL_MINS : u64 = 60;
+    pub const DEFAULT_KEEP_HOURLY : usize = 24;
+    pub const DEFAULT_KEEP_DAILY : usize = 7;
+
+    // eg "./maymay/" backs up into "./maymay_backups/"
+    pub fn default_dir_for(save_dir : &str) -> PathBuf {
+        let trimmed = save_dir.trim_right_matches(|c| c == '/' || c == '\\');
+        PathBuf::from(format!("{}_backups", trimmed))
+    }
+}
+
+fn unix_secs_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+    .expect("clock is before 1970??")
+    .as_secs()
+}
+
+fn backup_name(secs : u64) -> String {
+    format!("{}{}", BACKUP_PREFIX, secs)
+}
+
+fn parse_backup_name(name : &str) -> Option<u64> {
+    if name.starts_with(BACKUP_PREFIX) {
+        name[BACKUP_PREFIX.len()..].parse().ok()
+    } else {
+        None
+    }
+}
+
+//returns bytes copied. Lock files belong to whoever has the dir open, so they are never copied
+fn copy_dir_recursive(from : &Path, to : &Path) -> io::Result<u64> {
+    fs::create_dir_all(to)?;
+    let mut bytes_copied = 0;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name().to_str() == Some(LOCK_FILE_NAME) {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            bytes_copied += copy_dir_recursive(&entry.path(), &target)?;
+        } else {
+            bytes_copied += fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(bytes_copied)
+}
+
+/*
+Copies the whole save dir into a new snapshot. The copy is made under a temporary name
+and only renamed once complete, so a crash mid-copy never leaves a half backup behind.
+Caller should make sure nothing is writing to the save dir meanwhile.
+*/
+pub fn snapshot(sl : &SaverLoader, backup_dir : &Path) -> io::Result<PathBuf> {
+    let secs = unix_secs_now();
+    let final_path = backup_dir.join(backup_name(secs));
+    let partial_path = backup_dir.join(format!("{}{}", backup_name(secs), PARTIAL_SUFFIX));
+    if partial_path.exists() {
+        fs::remove_dir_all(&partial_path)?;
+    }
+    let bytes_copied = copy_dir_recursive(sl.save_dir(), &partial_path)?;
+    fs::rename(&partial_path, &final_path)?;
+    println!("Backed up {} bytes to {:?}", bytes_copied, &final_path);
+    Ok(final_path)
+}
+
+//complete backups in `backup_dir`, oldest first
+pub fn list_backups(backup_dir : &Path) -> io::Result<Vec<(u64,PathBuf)>> {
+    let mut backups = vec![];
+    if ! backup_dir.exists() {
+        return Ok(backups);
+    }
+    for entry in fs::read_dir(backup_dir)? {
+        let entry = entry?;
+        if ! entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(secs) = entry.file_name().to_str().and_then(parse_backup_name) {
+            backups.push((secs, entry.path()));
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+//the timestamps to keep. newest per bucket, for the newest `keep` buckets
+fn newest_per_bucket(stamps : &[u64], bucket_secs : u64, keep : usize) -> Vec<u64> {
+    let mut kept : Vec<u64> = vec![];
+    //newest first
+    for &secs in stamps.iter().rev() {
+        if kept.len() >= keep {
+            break;
+        }
+        let bucket = secs / bucket_secs;
+        if kept.last().map(|&k| k / bucket_secs) != Some(bucket) {
+            kept.push(secs);
+        }
+    }
+    kept
+}
+
+//deletes backups that no retention rule keeps. returns how many were removed
+pub fn prune(policy : &BackupPolicy) -> io::Result<usize> {
+    let backups = list_backups(&policy.dir)?;
+    let stamps : Vec<u64> = backups.iter().map(|b| b.0).collect();
+    let mut keep = newest_per_bucket(&stamps, SECS_PER_HOUR, policy.keep_hourly);
+    keep.extend(newest_per_bucket(&stamps, SECS_PER_DAY, policy.keep_daily));
+    let mut removed = 0;
+    for (secs, path) in backups {
+        if ! keep.contains(&secs) {
+            println!("Pruning old backup {:?}", &path);
+            fs::remove_dir_all(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+pub fn print_backups(backup_dir : &Path) {
+    match list_backups(backup_dir) {
+        Ok(ref backups) if backups.is_empty() => println!("No backups in {:?}", backup_dir),
+        Ok(backups) => {
+            println!("Backups in {:?}, oldest first:", backup_dir);
+            for (secs, _) in backups {
+                println!("\t{}", backup_name(secs));
+            }
+        },
+        Err(e) => println!("Couldn't read backups in {:?}: {}", backup_dir, e),
+    }
+}
+
+/*
+Swaps the backup `name` into place as the save dir. The current save dir is moved aside
+to `<save_dir>.before_restore_<unix seconds>` rather than deleted.
+The server using `save_dir` must be stopped!
+*/
+pub fn restore(backup_dir : &Path, name : &str, save_dir : &Path) -> io::Result<()> {
+    if parse_backup_name(name).is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a backup name. expected backup_<secs>"));
+    }
+    let backup_path = backup_dir.join(name);
+    if ! backup_path.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no such backup"));
+    }
+    if save_dir.exists() {
+        let aside = PathBuf::from(format!("{}.before_restore_{}", save_dir.display(), unix_secs_now()));
+        fs::rename(save_dir, &aside)?;
+        println!("Moved current save dir aside to {:?}", &aside);
+    }
+    let bytes_copied = copy_dir_recursive(&backup_path, save_dir)?;
+    println!("Restored {} bytes from {:?} into {:?}", bytes_copied, &backup_path, save_dir);
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::time::{Instant,Duration};
+use rand::{Rng,SeedableRng,Isaac64Rng};
+use ::points::DPoint2;
+use ::utils::quadtree::QuadTree;
+
+/*
+Times the QuadTree against scanning a plain list of points, the way Location looked things
+up before it had one. Items are scattered over a square the size of a large location.
+*/
+
+const SIDE : i32 = 512;
+const QUERIES : usize = 1000;
+//about what a screen or an area of interest covers, in cells
+const RANGE_RADIUS : i32 = 20;
+
+fn random_pt(rng : &mut Isaac64Rng) -> DPoint2 {
+    DPoint2::new(rng.gen_range(0, SIDE), rng.gen_range(0, SIDE))
+}
+
+fn per_query(d : Duration) -> f64 {
+    let micros = d.as_secs() as f64 * 1_000_000.0 + d.subsec_nanos() as f64 / 1_000.0;
+    micros / QUERIES as f64
+}
+
+fn report(what : &str, scan : Duration, tree : Duration) {
+    println!("  {:<10} scan {:>10.3}us  quadtree {:>10.3}us", what, per_query(scan), per_query(tree));
+}
+
+pub fn run() {
+    for &n in [1_000, 10_000, 100_000].iter() {
+        let mut rng = Isaac64Rng::from_seed(&[n as u64]);
+        let items : Vec<(DPoint2,u32)> = (0..n).map(|i| (random_pt(&mut rng), i as u32)).collect();
+        let queries : Vec<DPoint2> = (0..QUERIES).map(|_| random_pt(&mut rng)).collect();
+
+        let build_start = Instant::now();
+        let mut tree = QuadTree::new(DPoint2::new(0, 0), DPoint2::new(SIDE, SIDE));
+        for &(pt, id) in items.iter() {
+            tree.insert(pt, id);
+        }
+        println!("{} items: built in {:?}", tree.len(), build_start.elapsed());
+        //sums keep the optimizer from dropping the work. they must agree
+        let (mut scan_sum, mut tree_sum) = (0u64, 0u64);
+
+        let start = Instant::now();
+        for q in queries.iter() {
+            scan_sum += items.iter().filter(|&&(pt, _)| pt == *q).count() as u64;
+        }
+        let scan = start.elapsed();
+        let start = Instant::now();
+        for q in queries.iter() {
+            tree_sum += tree.at(*q).count() as u64;
+        }
+        report("point", scan, start.elapsed());
+
+        let start = Instant::now();
+        for q in queries.iter() {
+            scan_sum += items.iter().filter(|&&(pt, _)| {
+                (pt.x - q.x).abs() <= RANGE_RADIUS && (pt.y - q.y).abs() <= RANGE_RADIUS
+            }).count() as u64;
+        }
+        let scan = start.elapsed();
+        let start = Instant::now();
+        for q in queries.iter() {
+            tree_sum += tree.within(*q, RANGE_RADIUS).len() as u64;
+        }
+        report("range", scan, start.elapsed());
+
+        let dist_sq = |a : DPoint2, b : DPoint2| {
+            let (dx, dy) = ((a.x - b.x) as i64, (a.y - b.y) as i64);
+            dx * dx + dy * dy
+        };
+        let start = Instant::now();
+        for q in queries.iter() {
+            if let Some(&(pt, _)) = items.iter().min_by_key(|&&(pt, _)| dist_sq(pt, *q)) {
+                scan_sum += dist_sq(pt, *q) as u64;
+            }
+        }
+        let scan = start.elapsed();
+        let start = Instant::now();
+        for q in queries.iter() {
+            if let Some((pt, _)) = tree.nearest(*q) {
+                tree_sum += dist_sq(pt, *q) as u64;
+            }
+        }
+        report("nearest", scan, start.elapsed());
+
+        //moving everything is what a busy tick does to entities
+        let start = Instant::now();
+        for &(pt, id) in items.iter().take(QUERIES) {
+            let to = DPoint2::new((pt.x + 1) % SIDE, pt.y);
+            tree.move_item(pt, to, &id);
+        }
+        println!("  {:<10} quadtree {:>10.3}us", "move", per_query(start.elapsed()));
+
+        if scan_sum != tree_sum {
+            println!("  MISMATCH! scans found {} but the quadtree found {}", scan_sum, tree_sum);
+        }
+    }
+}
@@ -6,9 +6,10 @@ use std::time::{Instant,Duration};
 use std::collections::{HashMap,HashSet};
 use ::engine::game_state::locations::{Location,LocationPrimitive};
 use ::engine::game_state::worlds::{World,WorldPrimitive};
+use ::engine::game_state::universe::Universe;
 use saving::SaverLoader;
 use engine::objects::*;
-use engine::entities::*;
+use engine::components::{EntityComponents,ComponentDiff};
 use utils::traits::*;
 use std::hash::Hash;
 use std::sync::{Arc,Mutex};
@@ -64,7 +65,13 @@ pub struct ClientResources {
 	worlds: HashMap<WorldID, World>,
 	world_prims: HashMap<WorldID, WorldPrimitive>,
 	objects: HashMap<ObjectID, ObjectData>,
-	entities: HashMap<EntityID, EntityData>,
+	entities: HashMap<EntityID, EntityComponents>,
+	//entities whose components were asked for, and when
+	entities_requested: HashMap<EntityID, Instant>,
+	//likewise for objects
+	objects_requested: HashMap<ObjectID, Instant>,
+	zone_locations: HashMap<(WorldID,ZoneID), LocationID>,
+	universe: Option<Universe>,
 
 	to_acquire: Arc<Mutex<ToAcquire>>,
 
@@ -83,6 +90,10 @@ impl ClientResources {
 			world_prims: HashMap::new(),
 			objects: HashMap::new(),
 			entities: HashMap::new(),
+			entities_requested: HashMap::new(),
+			objects_requested: HashMap::new(),
+			zone_locations: HashMap::new(),
+			universe: None,
 			last_req_at: Instant::now(),
 			req_pause_time: req_pause_time,
             to_acquire: Arc::new(Mutex::new(ToAcquire::new())),
@@ -200,7 +211,12 @@ impl ClientResources {
 			true
 		} else {
 			let now = Instant::now();
-			if self.last_req_at + self.req_pause_time < now {
+			let due = match self.objects_requested.get(&oid) {
+				Some(at) => *at + self.req_pause_time < now,
+				None => true,
+			};
+			if due {
+				self.objects_requested.insert(oid, now);
 				self.client_out.lock_push_notify (
 					MsgToServer::RequestObjectData(oid)
 				);
@@ -209,18 +225,21 @@ impl ClientResources {
 		}
 	}
 
+	//components change as the game goes on. they aren't cached on disk
 	pub fn fast_entity_populate(&mut self, eid: EntityID) -> bool {
 		if self.entities.contains_key(&eid) {
 			//.1
 			true
-		} else if let Ok(ed) = self.sl.load_with_key::<EntityData,EntityID>(eid) {
-			self.entities.insert(eid, ed);
-			true
 		} else {
 			let now = Instant::now();
-			if self.last_req_at + self.req_pause_time < now {
+			let due = match self.entities_requested.get(&eid) {
+				Some(at) => *at + self.req_pause_time < now,
+				None => true,
+			};
+			if due {
+				self.entities_requested.insert(eid, now);
 				self.client_out.lock_push_notify (
-					MsgToServer::RequestEntityData(eid)
+					MsgToServer::RequestEntityComponents(eid)
 				);
 			}
 			false
@@ -229,6 +248,34 @@ impl ClientResources {
 
 	///////////////////////////// PUBLIC ///////////////////////
 
+	// the star systems and their worlds. asks the server if it isn't known yet
+	pub fn get_universe(&mut self) -> Result<&Universe,()> {
+		if self.universe.is_none() {
+			let now = Instant::now();
+			if self.last_req_at + self.req_pause_time < now {
+				self.client_out.lock_push_notify (
+					MsgToServer::RequestUniverse
+				);
+			}
+			return Err(());
+		}
+		Ok(self.universe.as_ref().unwrap())
+	}
+
+	// the location of a world's zone. asks the server if it isn't known yet
+	pub fn get_zone_location(&mut self, wid: WorldID, zone_id: ZoneID) -> Result<LocationID,()> {
+		if let Some(lid) = self.zone_locations.get(&(wid,zone_id)) {
+			return Ok(*lid);
+		}
+		let now = Instant::now();
+		if self.last_req_at + self.req_pause_time < now {
+			self.client_out.lock_push_notify (
+				MsgToServer::RequestZoneLocation(wid, zone_id)
+			);
+		}
+		Err(())
+	}
+
 	pub fn server_sent_data(&mut self, msg: MsgToClient) {
 		match msg {
 			MsgToClient::GiveLocationPrimitive(lid, lp) => {
@@ -238,10 +285,18 @@ impl ClientResources {
 				self.world_prims.insert(wid,wp);
 			},
 			MsgToClient::GiveObjectData(oid, od) => {
+				self.objects_requested.remove(&oid);
 				self.objects.insert(oid,od);
 			},
-			MsgToClient::GiveEntityData(eid, ed) => {
-				self.entities.insert(eid,ed);
+			MsgToClient::GiveEntityComponents(eid, components) => {
+				self.entities_requested.remove(&eid);
+				self.entities.insert(eid, EntityComponents::from_components(components));
+			},
+			MsgToClient::GiveZoneLocation(wid, zone_id, lid) => {
+				self.zone_locations.insert((wid,zone_id), lid);
+			},
+			MsgToClient::GiveUniversePrimitive(up) => {
+				self.universe = Some(Universe::new(up));
 			},
 			m => {
 				println!("Client resources got unexpected msg! {:?}", m);
@@ -289,7 +344,7 @@ impl ClientResources {
 	// 	}
 	// }
 
-	// pub fn get_entity(&mut self, eid: EntityID) -> Result<&EntityData,()> {
+	// pub fn get_entity(&mut self, eid: EntityID) -> Result<&EntityComponents,()> {
 	// 	if self.fast_entity_populate(eid) {
 	// 		Ok(self.entities.get(&eid).unwrap())
 	// 	} else {
@@ -342,7 +397,7 @@ impl ClientResources {
         }
     }
 
-    pub fn try_get_entity(&self, eid: EntityID) -> Option<&EntityData> {
+    pub fn try_get_entity(&self, eid: EntityID) -> Option<&EntityComponents> {
         if let Some(x) = self.entities.get(&eid) {
             Some(x)
         } else {
@@ -385,6 +440,35 @@ impl ClientResources {
 		}
 	}
 
+	//a diff to components the server sent before. one that doesn't fit drops them, to be asked for anew
+	pub fn apply_component_diff(&mut self, eid: EntityID, diff: ComponentDiff) {
+		let fits = match self.entities.get_mut(&eid) {
+			Some(components) => components.apply_diff(diff).is_ok(),
+			//the snapshot is still on its way, and will include this
+			None => true,
+		};
+		if ! fits {
+			println!("Component diff doesn't fit entity {:?}. Asking for its components again", eid);
+			self.entities.remove(&eid);
+		}
+	}
+
+	//asks the server about entities that couldn't be drawn for want of their components
+	pub fn acquire_entities(&mut self) {
+		let v : Vec<EntityID> = self.to_acquire.lock().unwrap().entities.drain().collect();
+		for eid in v {
+			self.fast_entity_populate(eid);
+		}
+	}
+
+	//asks the server about objects that couldn't be drawn for want of their data
+	pub fn acquire_objects(&mut self) {
+		let v : Vec<ObjectID> = self.to_acquire.lock().unwrap().objects.drain().collect();
+		for oid in v {
+			self.fast_object_populate(oid);
+		}
+	}
+
 	pub fn perform_acquisitions(&mut self) {
         // Call periodically to acquire things that have been requested but couldn't be returned
         // let mut t_o = ;
@@ -0,0 +1,132 @@
+use std::collections::{HashMap,VecDeque};
+use std::time::{Instant,Duration};
+use ::identity::*;
+use ::points::*;
+use super::game_state::{UPDATES_PER_SEC,TICKS_PER_MOVE_STEP};
+
+/*
+Smooths out the movement of entities the server moves, so they glide between cells instead
+of jumping as diffs arrive. Each entity keeps a short history of cells, stamped with the server
+tick they were reached in, and is drawn where it was a little while ago: between the two cells
+around that moment.
+How long ago adapts to the connection. Ticks arriving later than the earliest any tick has
+arrived measure the jitter; the delay is a move step plus twice that, so the next cell is
+usually in by the time it is needed.
+Cells more than a step apart (portals, spawns) are jumped to, not slid across.
+*/
+
+//history kept per entity. plenty for any sensible delay
+const MAX_HISTORY : usize = 16;
+//weight of the newest lateness in the jitter estimate
+const JITTER_SMOOTHING : f64 = 0.1;
+//never draw further behind than this
+const MAX_DELAY_SECS : f64 = 1.0;
+//moving further than this many cells at once is a teleport
+const TELEPORT_CELLS : f32 = 1.5;
+
+#[derive(Debug)]
+pub struct Interpolator {
+    histories : HashMap<EntityID,VecDeque<(f64,DPoint2)>>,
+    //a tick, and when it would have arrived with no jitter at all
+    reference : Option<(Tick,Instant)>,
+    jitter_secs : f64,
+    secs_per_tick : f64,
+}
+
+impl Interpolator {
+    pub fn new() -> Interpolator {
+        Interpolator {
+            histories : HashMap::new(),
+            reference : None,
+            jitter_secs : 0.0,
+            secs_per_tick : 1.0 / UPDATES_PER_SEC as f64,
+        }
+    }
+
+    //server seconds since the reference tick
+    fn server_secs(&self, tick : Tick) -> f64 {
+        match self.reference {
+            Some((ref_tick, _)) => (tick as f64 - ref_tick as f64) * self.secs_per_tick,
+            None => 0.0,
+        }
+    }
+
+    //call with the tick of every timed message, as it arrives
+    pub fn observe_tick(&mut self, tick : Tick) {
+        let now = Instant::now();
+        let (ref_tick, ref_at) = match self.reference {
+            Some(r) => r,
+            None => {
+                self.reference = Some((tick, now));
+                return;
+            },
+        };
+        let expected = self.server_secs(tick);
+        let arrived = secs(now.duration_since(ref_at));
+        if arrived < expected {
+            //faster than ever before. that is the new baseline
+            let earlier = Duration::from_millis(((expected - arrived) * 1000.0) as u64);
+            self.reference = Some((ref_tick, ref_at - earlier));
+        } else {
+            let lateness = arrived - expected;
+            self.jitter_secs += (lateness - self.jitter_secs) * JITTER_SMOOTHING;
+        }
+    }
+
+    //how far behind the server entities are drawn
+    pub fn delay(&self) -> Duration {
+        let step_secs = TICKS_PER_MOVE_STEP as f64 * self.secs_per_tick;
+        let delay_secs = (step_secs + 2.0 * self.jitter_secs).min(MAX_DELAY_SECS);
+        Duration::from_millis((delay_secs * 1000.0) as u64)
+    }
+
+    //`eid` reached `pt` in `tick`. `teleported` if it didn't walk there
+    pub fn record(&mut self, eid : EntityID, pt : DPoint2, tick : Tick, teleported : bool) {
+        let at = self.server_secs(tick);
+        let history = self.histories.entry(eid).or_insert_with(VecDeque::new);
+        let jumped = teleported || match history.back() {
+            Some(&(_, last)) => last.continuous().dist_to(pt.continuous()) > TELEPORT_CELLS,
+            None => true,
+        };
+        if jumped {
+            history.clear();
+        }
+        history.push_back((at, pt));
+        while history.len() > MAX_HISTORY {
+            history.pop_front();
+        }
+    }
+
+    pub fn forget(&mut self, eid : EntityID) {
+        self.histories.remove(&eid);
+    }
+
+    //for when the entities were replaced wholesale
+    pub fn forget_all(&mut self) {
+        self.histories.clear();
+    }
+
+    //where to draw `eid` right now. None if it has no history; draw it at its cell then
+    pub fn position_of(&self, eid : EntityID) -> Option<CPoint2> {
+        let history = self.histories.get(&eid)?;
+        let ref_at = self.reference?.1;
+        let render_at = secs(ref_at.elapsed()) - secs(self.delay());
+        let mut before = *history.front()?;
+        for &(at, pt) in history.iter() {
+            if at > render_at {
+                if at <= before.0 {
+                    return Some(pt.continuous());
+                }
+                let t = ((render_at - before.0) / (at - before.0)) as f32;
+                let from = before.1.continuous();
+                return Some(from + (pt.continuous() - from).scale(t.max(0.0)));
+            }
+            before = (at, pt);
+        }
+        Some(before.1.continuous())
+    }
+}
+
+fn secs(d : Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1_000_000_000.0
+}
@@ -9,6 +9,8 @@ mod view;
 mod asset_manager;
 // mod cache_manager;
 mod client_resources;
+mod prediction;
+mod interpolation;
 
 use self::client_resources::ClientResources;
 
@@ -16,16 +18,19 @@ use self::client_resources::ClientResources;
 // use std::collections::HashMap;
 use self::asset_manager::{AssetManager,HardcodedAssets};
 use self::view::{View,ViewPerspective};
+use self::prediction::Predictor;
+use self::interpolation::Interpolator;
 use std::sync::{Arc};
 use super::super::network::{ProtectedQueue};
 use super::ClientID;
-use super::super::network::messaging::{MsgToClient,MsgToServer};
+use super::super::network::messaging::{MsgToClient,MsgToServer,Diff};
 use super::super::identity::*;
 use std::time::{Instant,Duration};
 use super::game_state::locations::{Location};
 use super::game_state::worlds::{WorldPrimitive,World};
 use super::entities::{EntityDataSet};
 use super::objects::{ObjectDataSet};
+use super::items;
 use ::utils::traits::*;
 use ::points::*;
 use std::path::Path;
@@ -51,6 +56,10 @@ struct MyData {
     wid: WorldID,
     longitude: f64,
     viewing_map: bool,
+    //latest server tick heard of
+    server_tick: Tick,
+    //inventory slot that item keys act on
+    selected_slot: usize,
 }
 
 // pub struct Dataset {
@@ -77,12 +86,16 @@ pub fn game_loop(client_in : Arc<ProtectedQueue<MsgToClient>>,
         wid: 0,
         longitude: 0.0,
         viewing_map: false,
+        server_tick: 0,
+        selected_slot: 0,
     };
     // let mut remote_info = RemoteInfo::new();
 
     // let mut cache_manager = CacheManager::new(sl.clone());
     let hardcoded_assets = HardcodedAssets::new(&mut window.factory);
     let mut asset_manager = AssetManager::new(&window.factory, sl);
+    let mut predictor = Predictor::new();
+    let mut interpolator = Interpolator::new();
 
 
     outgoing_request_cache.push(
@@ -112,6 +125,8 @@ pub fn game_loop(client_in : Arc<ProtectedQueue<MsgToClient>>,
                             &mut window,
                             &mut client_resources,
                             &mut asset_manager,
+                            &interpolator,
+                            my_data.selected_slot,
                         );
                     }
             }
@@ -141,16 +156,22 @@ pub fn game_loop(client_in : Arc<ProtectedQueue<MsgToClient>>,
                     if let Some(ref mut v) = my_data.view;
                     if let Some(loc) = client_resources.try_get_location(v.lid);
                     if let Some(m) = mouse_at;
-                    if let Some((eid, _)) = my_data.controlling;
+                    if let Some((eid, lid)) = my_data.controlling;
                     if let Some(pt) = v.translate_screenpt(CPoint2::new(m[0] as f32, m[1] as f32), loc);
                     then {
                         outgoing_request_cache.push(
-                            MsgToServer::ControlMoveTo(my_data.controlling.unwrap().1, eid, pt)
+                            predictor.order(lid, eid, pt, loc)
                         );
                     }
                 }
             } else if button == Button::Keyboard(Key::M) {
                 my_data.viewing_map = !my_data.viewing_map;
+            } else if let Button::Keyboard(key) = button {
+                if let Some(slot) = slot_for_key(key) {
+                    my_data.selected_slot = slot;
+                } else if let Some(msg) = item_request(key, &my_data, &client_resources) {
+                    outgoing_request_cache.push(msg);
+                }
             }
         }
 
@@ -173,7 +194,16 @@ pub fn game_loop(client_in : Arc<ProtectedQueue<MsgToClient>>,
                 &mut outgoing_request_cache,
                 // &mut cache_manager,
                 &mut client_resources,
+                &mut predictor,
+                &mut interpolator,
             );
+            if let Some(ref v) = my_data.view {
+                if let Ok(loc) = client_resources.get_mut_location(v.lid) {
+                    predictor.advance(loc);
+                }
+            }
+            client_resources.acquire_entities();
+            client_resources.acquire_objects();
         }
     }
 }
@@ -185,12 +215,22 @@ fn synchronize(client_in : &Arc<ProtectedQueue<MsgToClient>>,
                outgoing_request_cache : &mut Vec<MsgToServer>,
                // cache_manager : &mut CacheManager,
                client_resources: &mut ClientResources,
+               predictor : &mut Predictor,
+               interpolator : &mut Interpolator,
                // entity_data : &mut EntityDataSet,
               ) {
     //comment
     if let Some(drained) = client_in.impatient_drain() {
-        //these are all updates from the server
+        //these are all updates from the server, in the order they were sent
         for d in drained {
+            if let Some(tick) = d.tick() {
+                interpolator.observe_tick(tick);
+                if tick < my_data.server_tick {
+                    println!("Got a message from tick {} after one from tick {}", tick, my_data.server_tick);
+                } else {
+                    my_data.server_tick = tick;
+                }
+            }
             use MsgToClient::*;
             match d {
                 GiveWorldPrimitive(wid, world_prim) => {
@@ -202,16 +242,88 @@ fn synchronize(client_in : &Arc<ProtectedQueue<MsgToClient>>,
                     client_resources.server_sent_data(GiveObjectData(oid,data));
                     // dataset.object_dataset.insert(oid,data);
                 },
-                GiveEntityData(eid,data) => {
-                    client_resources.server_sent_data(GiveEntityData(eid,data));
-                    // dataset.entity_dataset.insert(eid,data);
+                GiveEntityComponents(eid,components) => {
+                    client_resources.server_sent_data(GiveEntityComponents(eid,components));
+                },
+                ApplyComponentDiff(eid,diff,_) => {
+                    client_resources.apply_component_diff(eid, diff);
                 },
-                ApplyLocationDiff(lid,diff) => {
+                ApplyLocationDiff(lid,diff,tick) => {
                     if let Some(ref mut view) = my_data.view {
-                        if let Some((c_eid, c_lid)) = my_data.controlling {
+                        if let Some((_, c_lid)) = my_data.controlling {
                             if c_lid == lid {
                                 if let Ok(loc) = client_resources.get_mut_location(view.lid) {
-                                    loc.apply_diff(diff);
+                                    //the server already checked it. only predictions can be in the way
+                                    if predictor.apply_server_diff(lid, diff, loc).is_err() {
+                                        outgoing_request_cache.push(
+                                            MsgToServer::RequestLocationSnapshot(lid)
+                                        );
+                                    }
+                                    match diff {
+                                        Diff::MoveEntityTo(eid,pt) => interpolator.record(eid, pt, tick, false),
+                                        Diff::PlaceInside(eid,pt) => interpolator.record(eid, pt, tick, true),
+                                        Diff::RemoveEntity(eid) |
+                                        Diff::OutOfInterest(eid) => interpolator.forget(eid),
+                                        _ => (),
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                LocationDigest(lid,digest,_) => {
+                    if let Some(ref view) = my_data.view {
+                        //predicted steps aren't on the server yet. the digest can't match
+                        if view.lid == lid && ! predictor.is_predicting(view.eid, lid) {
+                            if let Some(loc) = client_resources.try_get_location(lid) {
+                                if loc.entity_digest() != digest {
+                                    println!("LID {:?} is out of sync. Requesting snapshot", lid);
+                                    outgoing_request_cache.push(
+                                        MsgToServer::RequestLocationSnapshot(lid)
+                                    );
+                                }
+                            }
+                        }
+                    }
+                },
+                LocationSnapshot(lid,snapshot,_) => {
+                    if let Some(ref view) = my_data.view {
+                        if view.lid == lid {
+                            if let Ok(loc) = client_resources.get_mut_location(lid) {
+                                predictor.forget();
+                                interpolator.forget_all();
+                                loc.replace_entities(&snapshot);
+                            }
+                        }
+                    }
+                },
+                LocationSnapshotMore(lid,snapshot,_) => {
+                    if let Some(ref view) = my_data.view {
+                        if view.lid == lid {
+                            if let Ok(loc) = client_resources.get_mut_location(lid) {
+                                loc.extend_entities(&snapshot);
+                            }
+                        }
+                    }
+                },
+                GiveZoneLocation(wid, zone_id, lid) => {
+                    client_resources.server_sent_data(GiveZoneLocation(wid, zone_id, lid));
+                },
+                GiveUniversePrimitive(up) => {
+                    client_resources.server_sent_data(GiveUniversePrimitive(up));
+                },
+                ControlMoveAccepted(..) => {
+                    //its steps follow as diffs, confirming or correcting the prediction
+                },
+                ControlMoveRejected(lid, eid, pt, seq) => {
+                    println!("Server says {:?} can't get to {:?} in LID {:?}", eid, pt, lid);
+                    if let Some(ref view) = my_data.view {
+                        if view.lid == lid {
+                            if let Ok(loc) = client_resources.get_mut_location(lid) {
+                                if predictor.rejected(seq, loc).is_err() {
+                                    outgoing_request_cache.push(
+                                        MsgToServer::RequestLocationSnapshot(lid)
+                                    );
                                 }
                             }
                         }
@@ -221,7 +333,7 @@ fn synchronize(client_in : &Arc<ProtectedQueue<MsgToClient>>,
                     client_resources.server_sent_data(GiveLocationPrimitive(lid, loc_prim));
                     //TODO 
                 },
-                GiveControlling(eid, lid) => {
+                GiveControlling(eid, lid, _) => {
                     let mut going_to_new_loc = false;
                     if let Some((_, my_lid)) = my_data.controlling {
                         // I am already controlling something!
@@ -234,6 +346,10 @@ fn synchronize(client_in : &Arc<ProtectedQueue<MsgToClient>>,
                         going_to_new_loc = true;
                     }
                     my_data.controlling = Some((eid,lid));
+                    predictor.forget();
+                    if going_to_new_loc {
+                        interpolator.forget_all();
+                    }
                     if going_to_new_loc {
                         my_data.view = None; //subsequent message will populate this
                         outgoing_request_cache.push(
@@ -253,6 +369,65 @@ fn synchronize(client_in : &Arc<ProtectedQueue<MsgToClient>>,
     }
 }
 
+//number keys pick inventory slots, left to right
+fn slot_for_key(key : Key) -> Option<usize> {
+    match key {
+        Key::D1 => Some(0),
+        Key::D2 => Some(1),
+        Key::D3 => Some(2),
+        Key::D4 => Some(3),
+        Key::D5 => Some(4),
+        Key::D6 => Some(5),
+        Key::D7 => Some(6),
+        Key::D8 => Some(7),
+        Key::D9 => Some(8),
+        Key::D0 => Some(9),
+        _ => None,
+    }
+}
+
+/*
+What `key` asks the server to do with items, if anything:
+G picks up the nearest pile in reach, Q drops the selected stack,
+E holds the selected item (or puts it away), T hands the selected stack to whoever is nearest in reach
+*/
+fn item_request(key : Key, my_data : &MyData, client_resources : &ClientResources) -> Option<MsgToServer> {
+    let (eid, lid) = my_data.controlling?;
+    let loc = client_resources.try_get_location(lid)?;
+    let at = loc.point_of(eid)?;
+    let components = client_resources.try_get_entity(eid)?;
+    let slot = my_data.selected_slot;
+    let selected = components.inventory().and_then(|inv| inv.slot(slot));
+    let in_reach = |pt : DPoint2| (pt.x - at.x).abs() <= 1 && (pt.y - at.y).abs() <= 1;
+    match key {
+        Key::G => {
+            loc.objects_within(DPoint2::new(at.x - 1, at.y - 1), DPoint2::new(at.x + 1, at.y + 1)).into_iter()
+            .filter_map(|(oid, pt)| items::item_of_oid(oid).map(|iid| (pt, iid)))
+            .min_by_key(|&(pt, _)| (pt.x - at.x).abs() + (pt.y - at.y).abs())
+            .map(|(pt, iid)| MsgToServer::PickUp(lid, eid, pt, iid))
+        },
+        Key::Q => selected.map(|stack| MsgToServer::DropItems(lid, eid, slot, stack.count)),
+        Key::E => {
+            let stack = selected?;
+            if components.equipped() == Some(stack.iid) {
+                Some(MsgToServer::Equip(eid, None))
+            } else {
+                Some(MsgToServer::Equip(eid, Some(slot)))
+            }
+        },
+        Key::T => {
+            let stack = selected?;
+            let (to, to_at) = loc.nearest_entity(at, Some(eid))?;
+            if in_reach(to_at) {
+                Some(MsgToServer::GiveItems(lid, eid, slot, stack.count, to))
+            } else {
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
 fn am_controlling(eid : EntityID, my_data : &MyData) -> bool {
     if let Some((cntl_eid, _)) = my_data.controlling {
         cntl_eid == eid
@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::time::{Instant,Duration};
+use ::identity::*;
+use ::points::DPoint2;
+use ::network::messaging::{MsgToServer,Diff};
+use super::Location;
+use super::game_state::{UPDATES_PER_SEC,TICKS_PER_MOVE_STEP};
+
+/*
+Predicts the walks of the controlled entity so clicks don't wait on the server.
+An order moves the entity in the local Location right away, along the path the client finds
+itself, at the pace the server walks. The server's own steps then either confirm the cells
+walked ahead of it, or pull the entity over onto its track if they went elsewhere.
+A rejected order snaps the entity back to where the server last had it.
+*/
+
+#[derive(Debug)]
+struct PredictedWalk {
+    seq : MoveSeq,
+    goal : DPoint2,
+    //cells still to step onto
+    path : VecDeque<DPoint2>,
+    next_step_at : Instant,
+}
+
+#[derive(Debug)]
+struct Subject {
+    eid : EntityID,
+    lid : LocationID,
+    //where the server last had the entity
+    confirmed : DPoint2,
+}
+
+#[derive(Debug)]
+pub struct Predictor {
+    next_seq : MoveSeq,
+    //Some while the local Location may disagree with the server about the entity
+    subject : Option<Subject>,
+    walk : Option<PredictedWalk>,
+    //cells stepped onto ahead of the server, oldest first
+    trail : VecDeque<DPoint2>,
+    time_between_steps : Duration,
+}
+
+impl Predictor {
+    pub fn new() -> Predictor {
+        Predictor {
+            next_seq : 0,
+            subject : None,
+            walk : None,
+            trail : VecDeque::new(),
+            time_between_steps : Duration::from_millis(1000 * TICKS_PER_MOVE_STEP / UPDATES_PER_SEC),
+        }
+    }
+
+    //true if `eid` in `lid` may be somewhere the server doesn't have it yet
+    pub fn is_predicting(&self, eid : EntityID, lid : LocationID) -> bool {
+        match self.subject {
+            Some(ref s) => s.eid == eid && s.lid == lid,
+            None => false,
+        }
+    }
+
+    /*
+    Starts walking `eid` to `goal` locally and returns the order to send.
+    If the client can't find a way, the order is still sent: the server may know better
+    */
+    pub fn order(&mut self, lid : LocationID, eid : EntityID, goal : DPoint2, loc : &Location) -> MsgToServer {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        if ! self.is_predicting(eid, lid) {
+            self.forget();
+            if let Some(at) = loc.point_of(eid) {
+                self.subject = Some(Subject {eid : eid, lid : lid, confirmed : at});
+            }
+        }
+        self.walk = match (self.subject.is_some(), loc.path_for(eid, goal)) {
+            (true, Some(path)) => Some(PredictedWalk {
+                seq : seq,
+                goal : goal,
+                path : path.into_iter().collect(),
+                next_step_at : Instant::now(),
+            }),
+            _ => None,
+        };
+        self.settle();
+        MsgToServer::ControlMoveTo(lid, eid, goal, seq)
+    }
+
+    //takes whatever predicted steps are due by now
+    pub fn advance(&mut self, loc : &mut Location) {
+        let eid = match self.subject {
+            Some(ref s) => s.eid,
+            None => return,
+        };
+        let mut blocked = false;
+        if let Some(ref mut walk) = self.walk {
+            while walk.next_step_at <= Instant::now() {
+                let next = match walk.path.pop_front() {
+                    Some(next) => next,
+                    None => break,
+                };
+                if loc.apply_diff(Diff::MoveEntityTo(eid, next)).is_err() {
+                    //something is in the way. the server will find a way around, or not
+                    blocked = true;
+                    break;
+                }
+                self.trail.push_back(next);
+                walk.next_step_at += self.time_between_steps;
+            }
+            if walk.path.is_empty() {
+                blocked = true;
+            }
+        }
+        if blocked {
+            self.walk = None;
+        }
+        self.settle();
+    }
+
+    /*
+    Applies a diff from the server to `loc`, the local copy of `lid`, reconciling it with
+    whatever was predicted there. Err if the two can't be reconciled; ask for a snapshot then
+    */
+    pub fn apply_server_diff(&mut self, lid : LocationID, diff : Diff, loc : &mut Location) -> Result<(),()> {
+        let predicted = match self.subject {
+            Some(ref s) if s.lid == lid => s.eid,
+            _ => return loc.apply_authoritative_diff(diff),
+        };
+        match diff {
+            Diff::MoveEntityTo(eid, pt) if eid == predicted => self.server_moved(pt, loc),
+            Diff::PlaceInside(eid, _) | Diff::RemoveEntity(eid) if eid == predicted => {
+                self.snap_back(loc)?;
+                loc.apply_authoritative_diff(diff)
+            },
+            _ => {
+                if loc.apply_authoritative_diff(diff).is_ok() {
+                    return Ok(());
+                }
+                //maybe the predicted entity stands where the server put someone else
+                self.snap_back(loc)?;
+                loc.apply_authoritative_diff(diff)
+            },
+        }
+    }
+
+    //the server moved the predicted entity to `pt`. brings the local Location in line with it
+    fn server_moved(&mut self, pt : DPoint2, loc : &mut Location) -> Result<(),()> {
+        let eid = match self.subject {
+            Some(ref mut s) => {
+                s.confirmed = pt;
+                s.eid
+            },
+            None => return Err(()),
+        };
+        if let Some(i) = self.trail.iter().position(|p| *p == pt) {
+            //we were there first
+            self.trail.drain(..(i+1));
+            self.settle();
+            return Ok(());
+        }
+        self.trail.clear();
+        let ahead_by = self.walk.as_ref().and_then(|w| w.path.iter().position(|p| *p == pt));
+        let result = loc.apply_authoritative_diff(Diff::MoveEntityTo(eid, pt));
+        let mut lost = result.is_err();
+        if let Some(ref mut walk) = self.walk {
+            match ahead_by {
+                //the server got further along our path than we did
+                Some(i) => {walk.path.drain(..(i+1));},
+                //it went another way. carry on from where it is
+                None => match loc.path_for(eid, walk.goal) {
+                    Some(path) => walk.path = path.into_iter().collect(),
+                    None => lost = true,
+                },
+            }
+        }
+        if lost {
+            self.walk = None;
+        }
+        self.settle();
+        result
+    }
+
+    //the server turned down order `seq`
+    pub fn rejected(&mut self, seq : MoveSeq, loc : &mut Location) -> Result<(),()> {
+        let current = match self.walk {
+            Some(ref walk) => walk.seq == seq,
+            //only the latest order can have left the entity off where the server has it
+            None => seq.wrapping_add(1) == self.next_seq,
+        };
+        if current {
+            self.snap_back(loc)
+        } else {
+            Ok(())
+        }
+    }
+
+    //stops predicting, putting the entity back where the server last had it
+    pub fn snap_back(&mut self, loc : &mut Location) -> Result<(),()> {
+        let result = match self.subject {
+            Some(ref s) if ! self.trail.is_empty() => {
+                loc.apply_authoritative_diff(Diff::MoveEntityTo(s.eid, s.confirmed))
+            },
+            _ => Ok(()),
+        };
+        self.forget();
+        result
+    }
+
+    //stops predicting without touching the Location. for when it was replaced wholesale
+    pub fn forget(&mut self) {
+        self.subject = None;
+        self.walk = None;
+        self.trail.clear();
+    }
+
+    //done predicting once nothing is left to walk or to be confirmed
+    fn settle(&mut self) {
+        if self.walk.is_none() && self.trail.is_empty() {
+            self.subject = None;
+        }
+    }
+}
@@ -2,13 +2,15 @@ use super::{EntityID,WIDTH,HEIGHT,Location,MyData};
 use super::ClientResources;
 use super::AssetManager;
 use ::points::*;
-use super::piston_window::{PistonWindow,GenericEvent,clear,image,Transformed};
+use super::piston_window::{PistonWindow,GenericEvent,clear,image,rectangle,Transformed};
 use std::time::{Instant,Duration};
 use ::identity::*;
 use ::network::messaging::MsgToServer;
 use super::asset_manager::HardcodedAssets;
+use super::interpolation::Interpolator;
 use super::piston_window::{G2dTexture,Texture,TextureSettings,Flip};
 use super::piston_window::ImageSize;
+use ::engine::items::{item_def,INVENTORY_SLOTS};
 
 pub struct View {
     pub eid : EntityID,
@@ -26,6 +28,9 @@ impl ViewPerspective {
     };
 }
 
+//side of an inventory slot on screen, in pixels
+const SLOT_PIXELS : f64 = 28.0;
+
 lazy_static! {
     static ref SCREEN_MIDDLE : CPoint2 = CPoint2::new(WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0);
 }
@@ -66,12 +71,17 @@ impl View {
     }
 
     pub fn translate_pt_relative_to(&self, pt : DPoint2, center : DPoint2, loc: &Location) -> CPoint2 {
+        self.translate_cpt_relative_to(pt.continuous(), center, loc)
+    }
+
+    //like translate_pt_relative_to, for points between cells
+    pub fn translate_cpt_relative_to(&self, pt : CPoint2, center : DPoint2, loc: &Location) -> CPoint2 {
         let prim = loc.get_location_primitive();
         // let rel_pt = [pt[0] - center[0], pt[1] - center[1]];
-        let rel_pt = pt - center;
+        let rel_pt = pt - center.continuous();
         let meter_to_pixels : f64 = WIDTH / self.vp.screen_meter_width;
         let cells_to_pixels : f64 = prim.cell_to_meters * meter_to_pixels;
-        *SCREEN_MIDDLE + (rel_pt.continuous().scale(cells_to_pixels as f32))
+        *SCREEN_MIDDLE + (rel_pt.scale(cells_to_pixels as f32))
         // [
         //     (WIDTH / 2.0) + (rel_pt[0] as f64 * cells_to_pixels),
         //     (HEIGHT / 2.0) + (rel_pt[1] as f64 * cells_to_pixels),
@@ -83,6 +93,38 @@ impl View {
         .map(|center| self.translate_pt_relative_to(pt, center, loc))
     }
 
+    //one square per cell on screen, coloured by its material and darker where it lies lower
+    pub fn render_location_terrain<E>(
+                       &self,
+                       event : &E,
+                       window : &mut PistonWindow,
+                       loc: &Location,
+    ) where E : GenericEvent {
+        if let Some(center) = loc.point_of(self.eid) {
+            let meter_to_pixels : f64 = WIDTH / self.vp.screen_meter_width;
+            let cell_pixels : f64 = loc.get_location_primitive().cell_to_meters * meter_to_pixels;
+            let tl = self.translate_screenpt_relative_to(CPoint2::new(0.0, 0.0), center, loc);
+            let br = self.translate_screenpt_relative_to(CPoint2::new(WIDTH as f32, HEIGHT as f32), center, loc);
+            window.draw_2d(event, |c, g| {
+                for y in (tl.y-1)..(br.y+2) {
+                    for x in (tl.x-1)..(br.x+2) {
+                        let pt = DPoint2::new(x, y);
+                        if let Some(cell) = loc.cell_data(pt) {
+                            let screen_pt = self.translate_pt_relative_to(pt, center, loc);
+                            let color = cell.mat.rgba(0.25 + (1.0 - cell.height) * 0.4);
+                            rectangle(color, [
+                                screen_pt.x as f64,
+                                screen_pt.y as f64,
+                                cell_pixels,
+                                cell_pixels,
+                            ], c.transform, g);
+                        }
+                    }
+                }
+            });
+        }
+    }
+
     pub fn render_location_objects<E>(
                        &self,
                        event : &E,
@@ -93,37 +135,40 @@ impl View {
     ) where E : GenericEvent {
         if let Some(center) = loc.point_of(self.eid) {
             let mut missing_oid_assets : Vec<ObjectID> = vec![];
+            let tl = self.translate_screenpt_relative_to(CPoint2::new(0.0, 0.0), center, loc);
+            let br = self.translate_screenpt_relative_to(CPoint2::new(WIDTH as f32, HEIGHT as f32), center, loc);
+            //only look at objects around the screen. a cell of margin covers rounding at the edges
+            let on_screen = loc.objects_within(DPoint2::new(tl.x-1, tl.y-1), DPoint2::new(br.x+1, br.y+1));
             window.draw_2d(event, |c, g| {
-                for (oid, pt_set) in loc.object_iterator() {
+                for &(oid, pt) in on_screen.iter() {
                     if missing_oid_assets.contains(&oid) {continue}
-                    if let Some(object_data) = client_resources.try_get_object(*oid) {
+                    if let Some(object_data) = client_resources.try_get_object(oid) {
                         let zoom = calc_zoom(
                             asset_manager.get_tex_width(object_data.aid),
                             self.vp.screen_meter_width,
                             object_data.width_meters,
                         );
                         let tex = asset_manager.get_texture_for(object_data.aid);
-                        object_data.width_meters;
-                        for screen_pt in pt_set.iter()
-                        .map(|pt| self.translate_pt_relative_to(*pt, center, loc))
-                        .filter(is_on_screen)
-                        {
+                        let screen_pt = self.translate_pt_relative_to(pt, center, loc);
+                        if is_on_screen(&screen_pt) {
                             image(tex, c.transform.trans(screen_pt.x as f64, screen_pt.y as f64).zoom(zoom), g);
                         }
                     } else {
-                        missing_oid_assets.push(*oid);
+                        missing_oid_assets.push(oid);
                     }
                 }
             });
         }
     }
 
+    //entities moved by the server are drawn where the interpolator has them. ours is drawn at its cell
     pub fn render_location_entities<E>(
                        &self,
                        event : &E,
                        window : &mut PistonWindow,
                        client_resources: &ClientResources,
                        asset_manager: &mut AssetManager,
+                       interpolator: &Interpolator,
                        loc: &Location,
     ) where E : GenericEvent {
         if let Some(center) = loc.point_of(self.eid) {
@@ -133,17 +178,37 @@ impl View {
                     if missing_eid_assets.contains(&eid) {
                         continue;
                     }
-                    if let Some(entity_data) = client_resources.try_get_entity(*eid) {
+                    if let Some(components) = client_resources.try_get_entity(*eid) {
+                        let appearance = match components.appearance() {
+                            Some(a) => a,
+                            //nothing to draw
+                            None => continue,
+                        };
                         let zoom = calc_zoom(
-                            asset_manager.get_tex_width(entity_data.aid),
+                            asset_manager.get_tex_width(appearance.aid),
                             self.vp.screen_meter_width,
-                            entity_data.width_meters,
+                            appearance.width_meters,
                         );
-                        let tex = asset_manager.get_texture_for(entity_data.aid);
-                        let screen_pt = self.translate_pt_relative_to(*pt, center, loc);
+                        let tex = asset_manager.get_texture_for(appearance.aid);
+                        let drawn_at = match interpolator.position_of(*eid) {
+                            Some(cpt) if *eid != self.eid => cpt,
+                            _ => pt.continuous(),
+                        };
+                        let screen_pt = self.translate_cpt_relative_to(drawn_at, center, loc);
                         if is_on_screen(&screen_pt) {
                             image(tex, c.transform
                                 .trans(screen_pt.x as f64, screen_pt.y as f64).zoom(zoom), g);
+                            //what it holds, off its right hand side
+                            if let Some(def) = components.equipped().and_then(item_def) {
+                                let held_zoom = calc_zoom(
+                                    asset_manager.get_tex_width(def.aid),
+                                    self.vp.screen_meter_width,
+                                    def.width_meters,
+                                );
+                                let held_x = screen_pt.x as f64 + WIDTH * appearance.width_meters / self.vp.screen_meter_width;
+                                image(asset_manager.get_texture_for(def.aid), c.transform
+                                    .trans(held_x, screen_pt.y as f64).zoom(held_zoom), g);
+                            }
                         }
                     } else {
                         missing_eid_assets.push(*eid);
@@ -153,6 +218,50 @@ impl View {
         }
     }
 
+    //the inventory of our entity along the bottom of the screen. the selected slot is lighter
+    pub fn render_inventory<E>(
+                       &self,
+                       event : &E,
+                       window : &mut PistonWindow,
+                       client_resources: &ClientResources,
+                       asset_manager: &mut AssetManager,
+                       selected_slot : usize,
+    ) where E : GenericEvent {
+        let components = match client_resources.try_get_entity(self.eid) {
+            Some(c) => c,
+            None => return,
+        };
+        let inventory = match components.inventory() {
+            Some(i) => i,
+            None => return,
+        };
+        let equipped = components.equipped();
+        let left = (WIDTH - SLOT_PIXELS * INVENTORY_SLOTS as f64) / 2.0;
+        let top = HEIGHT - SLOT_PIXELS - 4.0;
+        window.draw_2d(event, |c, g| {
+            for (i, slot) in inventory.slots().iter().enumerate() {
+                let x = left + i as f64 * SLOT_PIXELS;
+                let shade = if i == selected_slot {[0.6, 0.6, 0.6, 0.8]} else {[0.2, 0.2, 0.2, 0.8]};
+                rectangle(shade, [x + 1.0, top + 1.0, SLOT_PIXELS - 2.0, SLOT_PIXELS - 2.0], c.transform, g);
+                let (stack, def) = match *slot {
+                    Some(stack) => match item_def(stack.iid) {
+                        Some(def) => (stack, def),
+                        None => continue,
+                    },
+                    None => continue,
+                };
+                let zoom = (SLOT_PIXELS - 8.0) / asset_manager.get_tex_width(def.aid) as f64;
+                image(asset_manager.get_texture_for(def.aid), c.transform.trans(x + 4.0, top + 4.0).zoom(zoom), g);
+                //how full the stack is
+                let full = stack.count as f64 / def.max_stack as f64;
+                rectangle([0.9, 0.9, 0.9, 1.0], [x + 3.0, top + SLOT_PIXELS - 5.0, (SLOT_PIXELS - 6.0) * full, 2.0], c.transform, g);
+                if equipped == Some(stack.iid) {
+                    rectangle([1.0, 0.8, 0.1, 1.0], [x + 3.0, top + 3.0, 4.0, 4.0], c.transform, g);
+                }
+            }
+        });
+    }
+
     pub fn clear_window<E>(event : &E, window : &mut PistonWindow) where E : GenericEvent {
         window.draw_2d(event, |_, g| { clear([0.0, 0.0, 0.0, 1.0], g); });
     }
@@ -163,10 +272,14 @@ impl View {
                        window : &mut PistonWindow,
                        client_resources: &ClientResources,
                        asset_manager: &mut AssetManager,
+                       interpolator: &Interpolator,
+                       selected_slot : usize,
     ) where E : GenericEvent {
         if let Some(loc) = client_resources.try_get_location(self.lid) {
+            self.render_location_terrain(event, window, loc);
             self.render_location_objects(event, window, client_resources, asset_manager, loc);
-            self.render_location_entities(event, window, client_resources, asset_manager, loc);
+            self.render_location_entities(event, window, client_resources, asset_manager, interpolator, loc);
+            self.render_inventory(event, window, client_resources, asset_manager, selected_slot);
         }
     }
 
@@ -0,0 +1,235 @@
+use ::identity::{AssetID,ItemID};
+use ::points::DPoint2;
+use ::engine::entities::EntityData;
+use ::engine::items::Inventory;
+use utils::traits::*;
+
+/*
+What an entity is, as a set of components: at most one of each kind.
+The server keeps every entity's components and saves them. Clients get a snapshot of those they
+may see (see Visibility) when they ask about an entity, and diffs of those as they change.
+*/
+
+#[derive(Serialize,Deserialize,Copy,Clone,Debug,PartialEq)]
+pub struct Appearance {
+    pub aid : AssetID,
+    pub width_meters : f64,
+}
+
+#[derive(Serialize,Deserialize,Copy,Clone,Debug,PartialEq)]
+pub struct Health {
+    pub current : u32,
+    pub max : u32,
+}
+
+//how a server-controlled entity gets around. see server_game::npcs
+#[derive(Serialize,Deserialize,Clone,Debug,PartialEq)]
+pub enum Behavior {
+    //steps to random free neighbouring cells
+    Wander,
+    //walks to each waypoint in turn, back to the first after the last
+    FollowPath(Vec<DPoint2>),
+    //steps away from players closer than `radius` cells. wanders otherwise
+    Flee {radius : i32},
+    //wanders, but never further than `radius` cells from `home`
+    StayNear {home : DPoint2, radius : i32},
+}
+
+#[derive(Serialize,Deserialize,Clone,Debug,PartialEq)]
+pub enum Component {
+    Appearance(Appearance),
+    Name(String),
+    Health(Health),
+    Behavior(Behavior),
+    Inventory(Inventory),
+    //the item held, for all to see. it is one the inventory has
+    Equipped(ItemID),
+}
+
+#[derive(Serialize,Deserialize,Copy,Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+pub enum ComponentKind {
+    Appearance,
+    Name,
+    Health,
+    Behavior,
+    Inventory,
+    Equipped,
+}
+
+//which clients hear about a kind of component
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Visibility {
+    //only the client controlling the entity
+    Owner,
+    //any client that asks about the entity
+    Everyone,
+    //no client
+    Server,
+}
+
+impl ComponentKind {
+    pub fn visibility(self) -> Visibility {
+        match self {
+            ComponentKind::Appearance => Visibility::Everyone,
+            ComponentKind::Name => Visibility::Everyone,
+            ComponentKind::Health => Visibility::Owner,
+            ComponentKind::Behavior => Visibility::Server,
+            ComponentKind::Inventory => Visibility::Owner,
+            ComponentKind::Equipped => Visibility::Everyone,
+        }
+    }
+}
+
+impl Component {
+    pub fn kind(&self) -> ComponentKind {
+        match *self {
+            Component::Appearance(_) => ComponentKind::Appearance,
+            Component::Name(_) => ComponentKind::Name,
+            Component::Health(_) => ComponentKind::Health,
+            Component::Behavior(_) => ComponentKind::Behavior,
+            Component::Inventory(_) => ComponentKind::Inventory,
+            Component::Equipped(_) => ComponentKind::Equipped,
+        }
+    }
+}
+
+//change to ONE entity's components
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub enum ComponentDiff {
+    //adds the component, or replaces the one of its kind
+    Set(Component),
+    Remove(ComponentKind),
+}
+
+impl ComponentDiff {
+    pub fn kind(&self) -> ComponentKind {
+        match *self {
+            ComponentDiff::Set(ref c) => c.kind(),
+            ComponentDiff::Remove(kind) => kind,
+        }
+    }
+}
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct EntityComponents {
+    //ordered by kind
+    components : Vec<Component>,
+}
+
+impl KnowsSavePrefix for EntityComponents {
+    fn get_save_prefix() -> String {
+        "entity_components".to_owned()
+    }
+}
+
+impl EntityComponents {
+    pub fn new() -> EntityComponents {
+        EntityComponents {
+            components : vec![],
+        }
+    }
+
+    pub fn from_components(components : Vec<Component>) -> EntityComponents {
+        let mut ec = EntityComponents::new();
+        for c in components {
+            ec.set(c);
+        }
+        ec
+    }
+
+    //entities saved before components existed were all looks
+    pub fn from_legacy(data : EntityData) -> EntityComponents {
+        EntityComponents::from_components(vec![
+            Component::Appearance(Appearance {aid : data.aid, width_meters : data.width_meters}),
+        ])
+    }
+
+    fn index_of(&self, kind : ComponentKind) -> Result<usize,usize> {
+        self.components.binary_search_by_key(&kind, |c| c.kind())
+    }
+
+    pub fn get(&self, kind : ComponentKind) -> Option<&Component> {
+        self.index_of(kind).ok().map(|i| &self.components[i])
+    }
+
+    //returns the component of the same kind it replaced, if any
+    pub fn set(&mut self, c : Component) -> Option<Component> {
+        match self.index_of(c.kind()) {
+            Ok(i) => Some(::std::mem::replace(&mut self.components[i], c)),
+            Err(i) => {
+                self.components.insert(i, c);
+                None
+            },
+        }
+    }
+
+    pub fn remove(&mut self, kind : ComponentKind) -> Option<Component> {
+        self.index_of(kind).ok().map(|i| self.components.remove(i))
+    }
+
+    //Err (changing nothing) if it removes a component the entity doesn't have
+    pub fn apply_diff(&mut self, diff : ComponentDiff) -> Result<(),()> {
+        match diff {
+            ComponentDiff::Set(c) => {
+                self.set(c);
+                Ok(())
+            },
+            ComponentDiff::Remove(kind) => self.remove(kind).map(|_| ()).ok_or(()),
+        }
+    }
+
+    //what a client may know of these. `is_owner` if it controls the entity
+    pub fn visible_to(&self, is_owner : bool) -> Vec<Component> {
+        self.components.iter()
+        .filter(|c| match c.kind().visibility() {
+            Visibility::Everyone => true,
+            Visibility::Owner => is_owner,
+            Visibility::Server => false,
+        })
+        .cloned()
+        .collect()
+    }
+
+    pub fn appearance(&self) -> Option<&Appearance> {
+        match self.get(ComponentKind::Appearance) {
+            Some(&Component::Appearance(ref a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match self.get(ComponentKind::Name) {
+            Some(&Component::Name(ref n)) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn health(&self) -> Option<&Health> {
+        match self.get(ComponentKind::Health) {
+            Some(&Component::Health(ref h)) => Some(h),
+            _ => None,
+        }
+    }
+
+    //None unless the server moves the entity
+    pub fn behavior(&self) -> Option<&Behavior> {
+        match self.get(ComponentKind::Behavior) {
+            Some(&Component::Behavior(ref b)) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn inventory(&self) -> Option<&Inventory> {
+        match self.get(ComponentKind::Inventory) {
+            Some(&Component::Inventory(ref i)) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn equipped(&self) -> Option<ItemID> {
+        match self.get(ComponentKind::Equipped) {
+            Some(&Component::Equipped(iid)) => Some(iid),
+            _ => None,
+        }
+    }
+}
@@ -2,6 +2,9 @@ use std::collections::HashMap;
 use ::identity::{EntityID,AssetID};
 use utils::traits::*;
 
+pub const PLAYER_AID : AssetID = 1;
+pub const NPC_AID : AssetID = 4;
+
 #[derive(Serialize,Deserialize,Debug)]
 pub struct EntityDataSet {
     map : HashMap<EntityID,EntityData>,
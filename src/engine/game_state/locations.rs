@@ -1,11 +1,14 @@
-// use std::collections::HashMap;
-use bidir_map::BidirMap;
 // use super::Point;
 use ::points::DPoint2;
 use std::collections::{HashSet,HashMap};
 use ::network::messaging::Diff;
 use ::rand::{SeedableRng,Isaac64Rng};
 use super::worlds::zones::Zone;
+use super::worlds::{Material,PointSampleData};
+use ::utils::grid::TotalGrid;
+use ::utils::quadtree::QuadTree;
+use ::utils::funcs::fnv1a_64;
+use ::bincode;
 
 use ::identity::*;
 use ::utils::noise::*;
@@ -13,6 +16,9 @@ use ::utils::traits::*;
 use ::identity::{SuperSeed,ObjectID};
 // use super::worlds::zones::Zone;
 use super::worlds::START_WORLD;
+use super::pathfinding;
+use super::walkability::{WalkGrid,Blocker};
+use ::engine::objects::{object_is_solid,TREE_OID,ROCK_OID};
 
 
 lazy_static! {
@@ -22,6 +28,34 @@ lazy_static! {
 
 
 
+/*
+A location is a zone seen up close. Each gap between neighbouring zone samples is split into
+CELLS_PER_SAMPLE cells, whose height and temperature blend those of the samples around them.
+Sample (x,y) of the zone lies on cell (x*CELLS_PER_SAMPLE, y*CELLS_PER_SAMPLE).
+*/
+pub const CELLS_PER_SAMPLE : i32 = 4;
+
+//the terrain of one location cell
+#[derive(Debug,Copy,Clone)]
+pub struct CellData {
+    pub height : f32,
+    pub temp : f32,
+    pub mat : Material,
+}
+
+pub fn sample_to_cell(coord : DPoint2) -> DPoint2 {
+    DPoint2::new(coord.x * CELLS_PER_SAMPLE, coord.y * CELLS_PER_SAMPLE)
+}
+
+//the zone sample lying on `pt`. None if `pt` lies between samples
+pub fn cell_to_sample(pt : DPoint2) -> Option<DPoint2> {
+    if pt.x % CELLS_PER_SAMPLE == 0 && pt.y % CELLS_PER_SAMPLE == 0 {
+        Some(DPoint2::new(pt.x / CELLS_PER_SAMPLE, pt.y / CELLS_PER_SAMPLE))
+    } else {
+        None
+    }
+}
+
 #[derive(Serialize,Deserialize,Debug,Copy,Clone)]
 pub struct LocationPrimitive {
     // pub world_zone: Zone,
@@ -53,9 +87,15 @@ impl LocationPrimitive {
 pub struct Location {
     world_zone: Zone,
     location_primitive : LocationPrimitive,
-    entities : BidirMap<EntityID, DPoint2>,
+    entities : HashMap<EntityID,DPoint2>,
     objects : HashMap<ObjectID,HashSet<DPoint2>>,
+    //the same entities and objects, found by where they are
+    entity_tree : QuadTree<EntityID>,
+    object_tree : QuadTree<ObjectID>,
     nfield_height : NoiseField,
+    cells : TotalGrid<CellData>,
+    //terrain and solid objects. kept up to date with object diffs
+    walk_grid : WalkGrid,
 }
 
 impl AppliesDiff<Diff> for Location {
@@ -64,32 +104,119 @@ impl AppliesDiff<Diff> for Location {
     }
 }
 
-fn generate_objects(nf : &NoiseField, loc_prim : &LocationPrimitive, cells_wide: i32, cells_high: i32,) -> HashMap<ObjectID,HashSet<DPoint2>> {
+//hash of a snapshot ordered like Location::entity_snapshot
+pub fn entity_digest_of(snapshot : &[(EntityID,DPoint2)]) -> u64 {
+    let bytes = bincode::serialize(snapshot, bincode::Infinite)
+    .expect("can't encode entity snapshot");
+    fnv1a_64(&bytes)
+}
+
+fn lerp(a : f32, b : f32, t : f32) -> f32 {
+    a + (b - a) * t
+}
+
+/*
+Blends the zone's samples into cells. Height and temperature are bilinear between the four
+samples around a cell. The material is that of the nearest sample, with the noise field
+nudging which one counts as nearest so borders between materials don't run straight.
+*/
+fn generate_cells(nf : &NoiseField, world_zone : &Zone) -> TotalGrid<CellData> {
+    let samples_wide = world_zone.get_samples_per_row();
+    let samples_high = world_zone.get_samples_per_col();
+    let dims = DPoint2::new(
+        ((samples_wide - 1) * CELLS_PER_SAMPLE + 1).max(0),
+        ((samples_high - 1) * CELLS_PER_SAMPLE + 1).max(0),
+    );
+    let sample = |x : i32, y : i32| -> (PointSampleData,Material) {
+        world_zone.sample_at(DPoint2::new(x.min(samples_wide-1), y.min(samples_high-1)))
+        .expect("zone sample missing")
+    };
+    TotalGrid::new_from_func(dims, &mut |x, y| {
+        let (x, y) = (x as i32, y as i32);
+        let (sx, sy) = (x / CELLS_PER_SAMPLE, y / CELLS_PER_SAMPLE);
+        let tx = (x % CELLS_PER_SAMPLE) as f32 / CELLS_PER_SAMPLE as f32;
+        let ty = (y % CELLS_PER_SAMPLE) as f32 / CELLS_PER_SAMPLE as f32;
+        let (tl, tl_mat) = sample(sx, sy);
+        let (tr, tr_mat) = sample(sx+1, sy);
+        let (bl, bl_mat) = sample(sx, sy+1);
+        let (br, br_mat) = sample(sx+1, sy+1);
+        let height = lerp(lerp(tl.height, tr.height, tx), lerp(bl.height, br.height, tx), ty);
+        let temp = lerp(lerp(tl.temp, tr.temp, tx), lerp(bl.temp, br.temp, tx), ty);
+        let jitter = nf.sample_2d(DPoint2::new(x, y).continuous().scale(0.3)) * 0.3;
+        let mat = match (tx + jitter >= 0.5, ty - jitter >= 0.5) {
+            (false, false) => tl_mat,
+            (true, false) => tr_mat,
+            (false, true) => bl_mat,
+            (true, true) => br_mat,
+        };
+        CellData {
+            height : height,
+            temp : temp,
+            mat : mat,
+        }
+    })
+}
+
+//trees grow in forests and here and there on grass. rocks lie about where the ground is rocky
+fn generate_objects(nf : &NoiseField, cells : &TotalGrid<CellData>) -> HashMap<ObjectID,HashSet<DPoint2>> {
     let mut v = HashMap::new();
-    let mut zero_set = HashSet::new();
-    for i in 0..cells_wide {
-        for j in 0..cells_high {
-            let pt : DPoint2 = DPoint2::new(i, j);
-            if nf.sample_2d(pt.continuous().scale(0.2)) > 0.01 {
-                 zero_set.insert(pt);
-            }
+    let mut trees = HashSet::new();
+    let mut rocks = HashSet::new();
+    for (pt, cell) in cells.into_iter() {
+        let tree_noise = nf.sample_2d(pt.continuous().scale(0.2));
+        let rock_noise = nf.sample_2d(pt.continuous().scale(0.7));
+        match cell.mat {
+            Material::Trees => if tree_noise > -0.2 {trees.insert(pt);},
+            Material::Grass => if tree_noise > 0.4 {trees.insert(pt);},
+            Material::DarkRock => if rock_noise > 0.1 {rocks.insert(pt);},
+            Material::Rock | Material::Snow => if rock_noise > 0.5 {rocks.insert(pt);},
+            Material::Water | Material::Ice | Material::Sand => (),
         }
     }
-    v.insert(0, zero_set);
+    v.insert(TREE_OID, trees);
+    v.insert(ROCK_OID, rocks);
     v
 }
 
+fn walk_grid_for(cells : &TotalGrid<CellData>, objects : &HashMap<ObjectID,HashSet<DPoint2>>) -> WalkGrid {
+    let mut walk_grid = WalkGrid::new(cells.get_width(), cells.get_height());
+    for (pt, cell) in cells.into_iter() {
+        walk_grid.set_terrain(pt, cell.mat);
+    }
+    for (oid, pt_set) in objects.iter() {
+        if object_is_solid(*oid) {
+            for pt in pt_set.iter() {
+                walk_grid.add_solid(*pt, *oid);
+            }
+        }
+    }
+    walk_grid
+}
+
 impl Location {
     pub fn generate_new(lp: LocationPrimitive, world_zone: Zone) -> Location {
         let mut rng = Isaac64Rng::from_seed(&[lp.super_seed]);
         let nf = NoiseField::generate(&mut rng, [0.2, 1.0], 2);
-        let objects = generate_objects(&nf, &lp, world_zone.get_samples_per_row(), world_zone.get_samples_per_col());
+        let cells = generate_cells(&nf, &world_zone);
+        let objects = generate_objects(&nf, &cells);
+        let walk_grid = walk_grid_for(&cells, &objects);
+        let bounds = DPoint2::new(cells.get_width(), cells.get_height());
+        let mut object_tree = QuadTree::new(DPoint2::new(0, 0), bounds);
+        for (oid, pt_set) in objects.iter() {
+            for pt in pt_set.iter() {
+                object_tree.insert(*pt, *oid);
+            }
+        }
         Location {
             world_zone: world_zone,
             location_primitive : lp,
-            entities : BidirMap::new(),
+            entities : HashMap::new(),
             objects : objects,
+            entity_tree : QuadTree::new(DPoint2::new(0, 0), bounds),
+            object_tree : object_tree,
             nfield_height : nf,
+            cells : cells,
+            walk_grid : walk_grid,
         }
     }
 
@@ -97,26 +224,44 @@ impl Location {
         &self.location_primitive
     }
 
-    pub fn point_is_free(&self, pt : DPoint2) -> bool {
-        if self.entities.get_by_second(&pt).is_some() {
-            return false;
+    //why nothing may step onto `pt` right now. None if something may
+    pub fn blocker_at(&self, pt : DPoint2) -> Option<Blocker> {
+        if let Some(b) = self.walk_grid.blocker_at(pt) {
+            Some(b)
+        } else if let Some(eid) = self.entity_at(pt) {
+            Some(Blocker::Entity(eid))
+        } else {
+            None
         }
-        for pt_set in self.objects.values() {
-            for p in pt_set.iter() {
-                if *p == pt {
-                    return true;
-                }
-            }
-        }
-        return false;
+    }
+
+    //true if an entity may be placed on or step onto `pt`
+    pub fn point_is_free(&self, pt : DPoint2) -> bool {
+        self.blocker_at(pt).is_none()
     }
 
     pub fn cells_wide(&self) -> i32 {
-        self.world_zone.get_samples_per_row()
+        self.cells.get_width()
     }
 
     pub fn cells_high(&self) -> i32 {
-        self.world_zone.get_samples_per_col()
+        self.cells.get_height()
+    }
+
+    pub fn cell_data(&self, pt : DPoint2) -> Option<&CellData> {
+        if self.cell_in_bounds(pt) {
+            self.cells.maybe_get(pt.x as usize, pt.y as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn cell_height(&self, pt : DPoint2) -> Option<f32> {
+        self.cell_data(pt).map(|c| c.height)
+    }
+
+    pub fn cell_material(&self, pt : DPoint2) -> Option<Material> {
+        self.cell_data(pt).map(|c| c.mat)
     }
 
     pub fn free_point(&self) -> Option<DPoint2> {
@@ -131,45 +276,158 @@ impl Location {
         None
     }
 
+    pub fn cell_in_bounds(&self, pt : DPoint2) -> bool {
+        self.walk_grid.in_bounds(pt)
+    }
+
+    //cells `eid` would step through to reach `goal`. None if it can't get there
+    pub fn path_for(&self, eid : EntityID, goal : DPoint2) -> Option<Vec<DPoint2>> {
+        let start = self.point_of(eid)?;
+        pathfinding::find_path(start, goal, |pt| self.point_is_free(pt))
+    }
+
+    //`pt` if it is free, else the nearest free cell around it
+    pub fn free_point_near(&self, pt : DPoint2) -> Option<DPoint2> {
+        let max_radius = ::std::cmp::max(self.cells_wide(), self.cells_high());
+        for radius in 0..max_radius {
+            for dy in -radius..(radius+1) {
+                for dx in -radius..(radius+1) {
+                    if dx.abs() != radius && dy.abs() != radius {
+                        continue; //not on this ring
+                    }
+                    let p = DPoint2::new(pt.x + dx, pt.y + dy);
+                    if self.point_is_free(p) {
+                        return Some(p)
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn insert_eid(&mut self, eid : EntityID, pt : DPoint2) {
+        self.entities.insert(eid, pt);
+        self.entity_tree.insert(pt, eid);
+    }
+
     fn remove_eid(&mut self, eid : EntityID) -> Option<DPoint2> {
-        self.entities.remove_by_first(&eid)
-        .map(|eid_pt| eid_pt.1)
+        let pt = self.entities.remove(&eid)?;
+        self.entity_tree.remove(pt, &eid);
+        Some(pt)
     }
 
     pub fn point_of(&self, eid : EntityID) -> Option<DPoint2> {
-        self.entities.get_by_first(&eid)
+        self.entities.get(&eid)
         .map(|pt| *pt)
     }
 
-    fn entity_at(&self, pt : DPoint2) -> Option<EntityID> {
-        self.entities.get_by_second(&pt)
+    pub fn entity_at(&self, pt : DPoint2) -> Option<EntityID> {
+        self.entity_tree.at(pt).next()
         .map(|ent| *ent)
     }
 
+    //entities in the square from `tl` to `br`, both inclusive
+    pub fn entities_within(&self, tl : DPoint2, br : DPoint2) -> Vec<(EntityID,DPoint2)> {
+        self.entity_tree.range(tl, br).into_iter()
+        .map(|(pt, eid)| (*eid, pt))
+        .collect()
+    }
+
+    //the entity closest to `pt`, other than `except`
+    pub fn nearest_entity(&self, pt : DPoint2, except : Option<EntityID>) -> Option<(EntityID,DPoint2)> {
+        self.entity_tree.nearest_where(pt, |eid| Some(*eid) != except)
+        .map(|(at, eid)| (*eid, at))
+    }
+
+    pub fn objects_at(&self, pt : DPoint2) -> Vec<ObjectID> {
+        self.object_tree.at(pt).cloned().collect()
+    }
+
+    //objects in the square from `tl` to `br`, both inclusive
+    pub fn objects_within(&self, tl : DPoint2, br : DPoint2) -> Vec<(ObjectID,DPoint2)> {
+        self.object_tree.range(tl, br).into_iter()
+        .map(|(pt, oid)| (*oid, pt))
+        .collect()
+    }
+
+    /*
+    Applies a new diff, enforcing walkability: entities only move to or get placed on free cells.
+    Err (changing nothing) if the diff doesn't fit the location
+    */
     pub fn apply_diff(&mut self, diff : Diff) -> Result<(),()> {
+        match diff {
+            Diff::MoveEntityTo(_,pt) | Diff::PlaceInside(_,pt) => {
+                if ! self.point_is_free(pt) {
+                    return Err(());
+                }
+            },
+            //only for what clients see. the server's locations always have everything
+            Diff::OutOfInterest(_) => return Err(()),
+            _ => (),
+        }
+        self.apply_authoritative_diff(diff)
+    }
+
+    /*
+    Applies a diff that was already accepted somewhere (saved diffs, the journal, or the server's
+    word on the client). Only checks that it makes sense, not that cells are walkable:
+    walkability rules may have changed since it was accepted.
+    */
+    pub fn apply_authoritative_diff(&mut self, diff : Diff) -> Result<(),()> {
         match diff {
             Diff::MoveEntityTo(eid,pt) => {
-                if let Some(old_pt) = self.remove_eid(eid) {
-                    if old_pt == pt {
-                        self.entities.insert(eid, pt);
-                        Err(())
-                    } else {
-                        self.entities.insert(eid, pt);
-                        Ok(())
-                    }
+                if self.entity_at(pt).is_some() {
+                    return Err(());
+                }
+                if self.remove_eid(eid).is_some() {
+                    self.insert_eid(eid, pt);
+                    Ok(())
                 } else {
                     Err(())
                 }
             },
             Diff::PlaceInside(eid,pt) => {
-                if self.entities.get_by_first(&eid) == None
-                &&  self.entities.get_by_second(&pt) == None {
-                    self.entities.insert(eid, pt);
+                if self.point_of(eid) == None
+                &&  self.entity_at(pt) == None {
+                    self.insert_eid(eid, pt);
                     Ok(())
                 } else {
                     Err(())
                 }
-            }
+            },
+            Diff::RemoveEntity(eid) | Diff::OutOfInterest(eid) => {
+                if self.remove_eid(eid).is_some() {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            },
+            Diff::PlaceObject(oid,pt) => {
+                if self.objects.entry(oid).or_insert_with(HashSet::new).insert(pt) {
+                    self.object_tree.insert(pt, oid);
+                    if object_is_solid(oid) {
+                        self.walk_grid.add_solid(pt, oid);
+                    }
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            },
+            Diff::RemoveObject(oid,pt) => {
+                let removed = match self.objects.get_mut(&oid) {
+                    Some(pt_set) => pt_set.remove(&pt),
+                    None => false,
+                };
+                if removed {
+                    self.object_tree.remove(pt, &oid);
+                    if object_is_solid(oid) {
+                        self.walk_grid.remove_solid(pt, oid);
+                    }
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            },
         }
     }
 
@@ -189,9 +447,183 @@ impl Location {
         )
     }
 
+    //where every entity stands, ordered by EntityID
+    pub fn entity_snapshot(&self) -> Vec<(EntityID,DPoint2)> {
+        let mut v : Vec<(EntityID,DPoint2)> = self.entities.iter()
+        .map(|(eid, pt)| (*eid, *pt))
+        .collect();
+        v.sort_by_key(|&(eid, _)| eid);
+        v
+    }
+
+    //a server and client that agree on where entities are agree on this
+    pub fn entity_digest(&self) -> u64 {
+        entity_digest_of(&self.entity_snapshot())
+    }
+
+    //forgets all entities and takes those of `snapshot` instead
+    pub fn replace_entities(&mut self, snapshot : &[(EntityID,DPoint2)]) {
+        self.entities = HashMap::new();
+        self.entity_tree = QuadTree::new(DPoint2::new(0, 0), DPoint2::new(self.cells_wide(), self.cells_high()));
+        for &(eid, pt) in snapshot.iter() {
+            self.insert_eid(eid, pt);
+        }
+    }
+
+    //the rest of a snapshot that came in parts
+    pub fn extend_entities(&mut self, snapshot : &[(EntityID,DPoint2)]) {
+        for &(eid, pt) in snapshot.iter() {
+            self.insert_eid(eid, pt);
+        }
+    }
+
     pub fn entity_iterator<'a>(&'a self) -> Box<Iterator<Item=(&EntityID,&DPoint2)> + 'a> {
         Box::new(
             self.entities.iter()
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::walkability::material_is_walkable;
+    use ::engine::objects::{PORTAL_OID,ITEM_OID_BASE};
+
+    fn start_location() -> Location {
+        Location::generate_new(*START_LOC_PRIM, START_WORLD.get_zone(0).clone())
+    }
+
+    fn pt(x : i32, y : i32) -> DPoint2 {
+        DPoint2::new(x, y)
+    }
+
+    //just outside each edge
+    fn out_of_bounds(loc : &Location) -> Vec<DPoint2> {
+        let (w, h) = (loc.cells_wide(), loc.cells_high());
+        vec![pt(-1, 0), pt(0, -1), pt(w, 0), pt(0, h), pt(w, h), pt(-5, h / 2)]
+    }
+
+    //the first `n` free cells
+    fn free_cells(loc : &Location, n : usize) -> Vec<DPoint2> {
+        let mut v = vec![];
+        for y in 0..loc.cells_high() {
+            for x in 0..loc.cells_wide() {
+                if v.len() < n && loc.point_is_free(pt(x, y)) {
+                    v.push(pt(x, y));
+                }
+            }
+        }
+        assert_eq!(v.len(), n, "not enough free cells");
+        v
+    }
+
+    #[test]
+    fn terrain_blocks_where_it_is_unwalkable() {
+        let loc = start_location();
+        for y in 0..loc.cells_high() {
+            for x in 0..loc.cells_wide() {
+                let mat = loc.cell_material(pt(x, y)).unwrap();
+                match loc.blocker_at(pt(x, y)) {
+                    Some(Blocker::Terrain(m)) => assert_eq!(m, mat),
+                    _ => assert!(material_is_walkable(mat), "{:?} at {:?}", mat, pt(x, y)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn point_is_free() {
+        let mut loc = start_location();
+        for p in out_of_bounds(&loc) {
+            assert!(! loc.point_is_free(p));
+            assert_eq!(loc.blocker_at(p), Some(Blocker::OutOfBounds));
+        }
+        let cells = free_cells(&loc, 4);
+
+        loc.apply_authoritative_diff(Diff::PlaceObject(TREE_OID, cells[0])).unwrap();
+        assert_eq!(loc.blocker_at(cells[0]), Some(Blocker::Object(TREE_OID)));
+        loc.apply_authoritative_diff(Diff::RemoveObject(TREE_OID, cells[0])).unwrap();
+        assert!(loc.point_is_free(cells[0]));
+
+        loc.apply_diff(Diff::PlaceInside(5, cells[1])).unwrap();
+        assert_eq!(loc.blocker_at(cells[1]), Some(Blocker::Entity(5)));
+        loc.apply_diff(Diff::RemoveEntity(5)).unwrap();
+        assert!(loc.point_is_free(cells[1]));
+
+        //portals and item piles can be stepped on
+        loc.apply_authoritative_diff(Diff::PlaceObject(PORTAL_OID, cells[2])).unwrap();
+        loc.apply_authoritative_diff(Diff::PlaceObject(ITEM_OID_BASE, cells[3])).unwrap();
+        assert!(loc.point_is_free(cells[2]));
+        assert!(loc.point_is_free(cells[3]));
+    }
+
+    #[test]
+    fn free_point() {
+        let mut loc = start_location();
+        let first = loc.free_point().unwrap();
+        assert!(loc.cell_in_bounds(first));
+        assert!(loc.point_is_free(first));
+        loc.apply_diff(Diff::PlaceInside(1, first)).unwrap();
+        let second = loc.free_point().unwrap();
+        assert!(second != first);
+        assert!(loc.point_is_free(second));
+
+        assert_eq!(loc.free_point_near(second), Some(second));
+        assert_eq!(loc.free_point_near(first).map(|p| loc.point_is_free(p)), Some(true));
+        for p in out_of_bounds(&loc) {
+            let near = loc.free_point_near(p).unwrap();
+            assert!(loc.cell_in_bounds(near));
+            assert!(loc.point_is_free(near));
+        }
+    }
+
+    #[test]
+    fn apply_diff_keeps_entities_off_unfree_cells() {
+        let mut loc = start_location();
+        let cells = free_cells(&loc, 3);
+        let (home, occupied, blocked) = (cells[0], cells[1], cells[2]);
+        loc.apply_diff(Diff::PlaceInside(1, home)).unwrap();
+        loc.apply_diff(Diff::PlaceInside(2, occupied)).unwrap();
+        loc.apply_diff(Diff::PlaceObject(ROCK_OID, blocked)).unwrap();
+
+        let mut nowhere = out_of_bounds(&loc);
+        nowhere.push(occupied);
+        nowhere.push(blocked);
+        for &p in nowhere.iter() {
+            assert_eq!(loc.apply_diff(Diff::MoveEntityTo(1, p)), Err(()), "moved onto {:?}", p);
+            assert_eq!(loc.apply_diff(Diff::PlaceInside(3, p)), Err(()), "placed onto {:?}", p);
+            //and nothing changed
+            assert_eq!(loc.point_of(1), Some(home));
+            assert_eq!(loc.point_of(3), None);
+        }
+    }
+
+    #[test]
+    fn apply_authoritative_diff_ignores_walkability() {
+        let mut loc = start_location();
+        let cells = free_cells(&loc, 3);
+        let (home, occupied, blocked) = (cells[0], cells[1], cells[2]);
+        loc.apply_diff(Diff::PlaceInside(1, home)).unwrap();
+        loc.apply_diff(Diff::PlaceInside(2, occupied)).unwrap();
+        loc.apply_diff(Diff::PlaceObject(ROCK_OID, blocked)).unwrap();
+
+        let mut somewhere = out_of_bounds(&loc);
+        somewhere.push(blocked);
+        for (i, &p) in somewhere.iter().enumerate() {
+            loc.apply_authoritative_diff(Diff::MoveEntityTo(1, p)).unwrap();
+            assert_eq!(loc.point_of(1), Some(p));
+            let eid = 10 + i as EntityID;
+            //one entity per cell, however it got there
+            assert_eq!(loc.apply_authoritative_diff(Diff::PlaceInside(eid, p)), Err(()));
+            loc.apply_authoritative_diff(Diff::MoveEntityTo(1, home)).unwrap();
+            loc.apply_authoritative_diff(Diff::PlaceInside(eid, p)).unwrap();
+            assert_eq!(loc.point_of(eid), Some(p));
+            loc.apply_authoritative_diff(Diff::RemoveEntity(eid)).unwrap();
+        }
+        //an occupied cell is the one thing it still refuses
+        assert_eq!(loc.apply_authoritative_diff(Diff::MoveEntityTo(1, occupied)), Err(()));
+        assert_eq!(loc.apply_authoritative_diff(Diff::PlaceInside(3, occupied)), Err(()));
+        assert_eq!(loc.point_of(1), Some(home));
+    }
+}
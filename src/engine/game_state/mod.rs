@@ -1,5 +1,10 @@
 
 pub const UPDATES_PER_SEC : u64 = 32;
+//a walking entity advances one cell every this many ticks
+pub const TICKS_PER_MOVE_STEP : u64 = 5;
 
 pub mod locations;
 pub mod worlds;
+pub mod universe;
+pub mod pathfinding;
+pub mod walkability;
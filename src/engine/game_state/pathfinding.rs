@@ -0,0 +1,223 @@
+use std::collections::{BinaryHeap,HashMap};
+use std::cmp::Ordering;
+use ::points::DPoint2;
+
+/*
+A* over the cells of a location. Moves to all 8 neighbours; diagonals may not cut
+past a blocked corner. Costs are 10 per straight step and 14 per diagonal step.
+*/
+
+const STRAIGHT_COST : u32 = 10;
+const DIAGONAL_COST : u32 = 14;
+
+//searches give up after expanding this many cells
+pub const MAX_EXPANDED : usize = 4096;
+
+#[derive(Copy,Clone,PartialEq,Eq)]
+struct Open {
+    estimate : u32,
+    cost : u32,
+    pt : DPoint2,
+}
+
+impl Ord for Open {
+    //BinaryHeap is a max heap. smallest estimate first
+    fn cmp(&self, other : &Open) -> Ordering {
+        other.estimate.cmp(&self.estimate)
+        .then_with(|| self.cost.cmp(&other.cost))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other : &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+//octile distance. never overestimates with the costs above
+fn heuristic(a : DPoint2, b : DPoint2) -> u32 {
+    let dx = (a.x - b.x).abs() as u32;
+    let dy = (a.y - b.y).abs() as u32;
+    let (small, big) = if dx < dy {(dx, dy)} else {(dy, dx)};
+    DIAGONAL_COST * small + STRAIGHT_COST * (big - small)
+}
+
+/*
+Cells to step through to get from `start` to `goal`, excluding `start` and including `goal`.
+`passable` says which cells may be stepped on; `start` needn't be.
+None if there is no way through (or finding one took too long)
+*/
+pub fn find_path<F>(start : DPoint2, goal : DPoint2, passable : F) -> Option<Vec<DPoint2>>
+where F: Fn(DPoint2) -> bool {
+    if start == goal {
+        return Some(vec![]);
+    }
+    if ! passable(goal) {
+        return None;
+    }
+    let mut open = BinaryHeap::new();
+    let mut came_from : HashMap<DPoint2,DPoint2> = HashMap::new();
+    let mut best_cost : HashMap<DPoint2,u32> = HashMap::new();
+    best_cost.insert(start, 0);
+    open.push(Open {estimate : heuristic(start, goal), cost : 0, pt : start});
+    let mut expanded = 0;
+    while let Some(Open {cost, pt, ..}) = open.pop() {
+        if pt == goal {
+            let mut path = vec![goal];
+            let mut at = goal;
+            while let Some(prev) = came_from.get(&at) {
+                if *prev == start {
+                    break;
+                }
+                path.push(*prev);
+                at = *prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if best_cost.get(&pt).map(|c| *c < cost).unwrap_or(false) {
+            continue; //stale entry
+        }
+        expanded += 1;
+        if expanded > MAX_EXPANDED {
+            return None;
+        }
+        for dy in -1..2 {
+            for dx in -1..2 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let next = DPoint2::new(pt.x + dx, pt.y + dy);
+                if ! passable(next) {
+                    continue;
+                }
+                let step_cost = if dx != 0 && dy != 0 {
+                    if ! passable(pt.shift_x(dx)) || ! passable(pt.shift_y(dy)) {
+                        continue; //cuts a corner
+                    }
+                    DIAGONAL_COST
+                } else {
+                    STRAIGHT_COST
+                };
+                let next_cost = cost + step_cost;
+                if best_cost.get(&next).map(|c| *c <= next_cost).unwrap_or(false) {
+                    continue;
+                }
+                best_cost.insert(next, next_cost);
+                came_from.insert(next, pt);
+                open.push(Open {estimate : next_cost + heuristic(next, goal), cost : next_cost, pt : next});
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use ::points::DPoint2;
+    use super::*;
+
+    fn pt(x : i32, y : i32) -> DPoint2 {
+        DPoint2::new(x, y)
+    }
+
+    //a `w` by `h` room with `walls` in it
+    fn room(w : i32, h : i32, walls : &[DPoint2]) -> impl Fn(DPoint2) -> bool {
+        let walls : HashSet<DPoint2> = walls.iter().cloned().collect();
+        move |p : DPoint2| p.x >= 0 && p.y >= 0 && p.x < w && p.y < h && ! walls.contains(&p)
+    }
+
+    //each step goes to a passable neighbour without cutting a corner, ending at `goal`
+    fn assert_walkable<F>(start : DPoint2, goal : DPoint2, path : &[DPoint2], passable : &F)
+    where F : Fn(DPoint2) -> bool {
+        assert_eq!(path.last(), Some(&goal));
+        let mut at = start;
+        for &next in path {
+            let (dx, dy) = (next.x - at.x, next.y - at.y);
+            assert!(dx.abs() <= 1 && dy.abs() <= 1 && (dx, dy) != (0, 0), "{:?} -> {:?} isn't a step", at, next);
+            assert!(passable(next), "stepped on {:?}", next);
+            if dx != 0 && dy != 0 {
+                assert!(passable(at.shift_x(dx)) && passable(at.shift_y(dy)), "{:?} -> {:?} cuts a corner", at, next);
+            }
+            at = next;
+        }
+    }
+
+    #[test]
+    fn straight_path() {
+        let passable = room(10, 10, &[]);
+        let path = find_path(pt(1, 4), pt(7, 4), &passable).unwrap();
+        assert_eq!(path, (2..8).map(|x| pt(x, 4)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn path_around_wall() {
+        //a wall across x = 5, open only at the bottom row
+        let walls : Vec<DPoint2> = (0..9).map(|y| pt(5, y)).collect();
+        let passable = room(10, 10, &walls);
+        let (start, goal) = (pt(2, 2), pt(8, 2));
+        let path = find_path(start, goal, &passable).unwrap();
+        assert_walkable(start, goal, &path, &passable);
+        assert!(path.contains(&pt(5, 9)));
+        //7 down to beside the gap, through it without clipping the wall's end, and 7 back up
+        assert_eq!(path.len(), 16);
+    }
+
+    #[test]
+    fn diagonals_dont_cut_corners() {
+        let passable = room(3, 3, &[pt(1, 0)]);
+        let path = find_path(pt(0, 0), pt(1, 1), &passable).unwrap();
+        assert_eq!(path, vec![pt(0, 1), pt(1, 1)]);
+
+        //both corners blocked: no diagonal squeeze, and no other way
+        let passable = room(2, 2, &[pt(1, 0), pt(0, 1)]);
+        assert_eq!(find_path(pt(0, 0), pt(1, 1), &passable), None);
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let ring : Vec<DPoint2> = [(4, 4), (5, 4), (6, 4), (4, 5), (6, 5), (4, 6), (5, 6), (6, 6)].iter()
+            .map(|&(x, y)| pt(x, y))
+            .collect();
+        let passable = room(10, 10, &ring);
+        assert_eq!(find_path(pt(0, 0), pt(5, 5), &passable), None);
+        //a goal that can't be stepped on at all
+        assert_eq!(find_path(pt(0, 0), pt(4, 4), &passable), None);
+        assert_eq!(find_path(pt(0, 0), pt(-1, 0), &passable), None);
+    }
+
+    #[test]
+    fn gives_up_after_max_expanded() {
+        //endless open ground around an enclosed goal: only the cutoff stops the search
+        let ring : HashSet<DPoint2> = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)].iter()
+            .map(|&(x, y)| pt(x, y))
+            .collect();
+        let goal = pt(0, 0);
+        assert_eq!(find_path(pt(30, 30), goal, |p| ! ring.contains(&p)), None);
+
+        //reachable, but further than MAX_EXPANDED cells allow
+        let far = MAX_EXPANDED as i32 * 2;
+        let passable = room(far + 1, 1, &[]);
+        assert_eq!(find_path(pt(0, 0), pt(far, 0), &passable), None);
+        //while a bit nearer is fine
+        assert!(find_path(pt(0, 0), pt(far / 4, 0), &passable).is_some());
+    }
+
+    #[test]
+    fn goal_on_start() {
+        let passable = room(10, 10, &[pt(3, 3)]);
+        assert_eq!(find_path(pt(2, 2), pt(2, 2), &passable), Some(vec![]));
+        //even where the start can't be stepped on, or is nowhere near passable ground
+        assert_eq!(find_path(pt(3, 3), pt(3, 3), &passable), Some(vec![]));
+        assert_eq!(find_path(pt(-5, -5), pt(-5, -5), &passable), Some(vec![]));
+    }
+
+    #[test]
+    fn start_needn_t_be_passable() {
+        let passable = room(10, 10, &[pt(3, 3)]);
+        let path = find_path(pt(3, 3), pt(6, 3), &passable).unwrap();
+        assert_walkable(pt(3, 3), pt(6, 3), &path, &passable);
+        assert_eq!(path.len(), 3);
+    }
+}
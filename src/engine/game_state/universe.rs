@@ -0,0 +1,117 @@
+use ::points::*;
+use ::rand::{SeedableRng,Rng,Isaac64Rng};
+use utils::traits::*;
+use ::identity::*;
+use super::worlds::{WorldPrimitive,START_WORLD_PRIM};
+
+/*
+Everything above worlds. The universe is a scatter of star systems, each with a few worlds
+orbiting it. Worlds of one system share a star, so their star_energy (and with it, size)
+falls off together with their distance from it.
+All of it derives from the UniversePrimitive. Only that is saved and sent.
+*/
+
+#[derive(Serialize,Deserialize,Debug,Copy,Clone)]
+pub struct UniversePrimitive {
+    pub super_seed: SuperSeed,
+}
+
+impl KnowsSavePrefix for UniversePrimitive {
+    fn get_save_prefix() -> String {
+        "universe_prim".to_owned()
+    }
+}
+
+impl UniversePrimitive {
+    pub fn new(super_seed: SuperSeed) -> UniversePrimitive {
+        UniversePrimitive {
+            super_seed: super_seed,
+        }
+    }
+}
+
+pub type SystemID = u32;
+
+//the start world orbits the first star
+pub const START_SYSTEM : SystemID = 0;
+
+#[derive(Debug,Clone)]
+pub struct StarSystem {
+    //position on the star map. within [0,1] in both axes
+    pub pt: CPoint2,
+    pub luminosity: f32,
+    //nearest to the star first
+    pub worlds: Vec<WorldID>,
+}
+
+#[derive(Debug)]
+pub struct Universe {
+    up: UniversePrimitive,
+    systems: Vec<StarSystem>,
+    //index is the WorldID
+    world_prims: Vec<WorldPrimitive>,
+    world_systems: Vec<SystemID>,
+}
+
+impl Universe {
+    pub fn new(up: UniversePrimitive) -> Universe {
+        let mut rng = Isaac64Rng::from_seed(&[up.super_seed]);
+        let num_systems = 6 + rng.gen::<u32>() % 7;
+        let mut u = Universe {
+            up: up,
+            systems: vec![],
+            world_prims: vec![],
+            world_systems: vec![],
+        };
+        for sid in 0..num_systems {
+            let luminosity = 0.5 + rng.gen::<f32>();
+            let num_worlds = 1 + rng.gen::<usize>() % 5;
+            let mut system = StarSystem {
+                pt: CPoint2::new(rng.gen::<f32>(), rng.gen::<f32>()),
+                luminosity: luminosity,
+                worlds: vec![],
+            };
+            let mut orbit = 0.0;
+            for _ in 0..num_worlds {
+                orbit += 0.4 + rng.gen::<f32>() * 0.8;
+                let star_energy = (luminosity / (orbit * orbit) * 0.5).min(1.0);
+                let wid = u.world_prims.len() as WorldID;
+                u.world_prims.push(WorldPrimitive::new(rng.gen(), star_energy));
+                u.world_systems.push(sid);
+                system.worlds.push(wid);
+            }
+            u.systems.push(system);
+        }
+        //world 0 is always the start world, whatever the seed
+        u.world_prims[0] = *START_WORLD_PRIM;
+        println!("{} star systems, {} worlds", u.systems.len(), u.world_prims.len());
+        u
+    }
+
+    pub fn get_universe_primitive(&self) -> &UniversePrimitive {
+        &self.up
+    }
+
+    pub fn num_worlds(&self) -> usize {
+        self.world_prims.len()
+    }
+
+    pub fn world_primitive(&self, wid: WorldID) -> Option<WorldPrimitive> {
+        self.world_prims.get(wid as usize).cloned()
+    }
+
+    pub fn system_of(&self, wid: WorldID) -> Option<SystemID> {
+        self.world_systems.get(wid as usize).cloned()
+    }
+
+    pub fn get_system(&self, sid: SystemID) -> Option<&StarSystem> {
+        self.systems.get(sid as usize)
+    }
+
+    pub fn system_iter<'a>(&'a self) -> Box<Iterator<Item=(SystemID,&StarSystem)> + 'a> {
+        Box::new(
+            self.systems.iter().enumerate()
+            .map(|(sid, s)| (sid as SystemID, s))
+        )
+    }
+}
@@ -0,0 +1,194 @@
+use ::points::DPoint2;
+use ::identity::{ObjectID,EntityID};
+use super::worlds::Material;
+
+/*
+What may stand where inside a location. A cell can be walked onto unless it is
+outside the location, its terrain can't be walked on, a solid object is on it,
+or another entity stands on it.
+WalkGrid holds the terrain and object part. It knows nothing of entities,
+as those move all the time; Location asks its entities separately.
+*/
+
+//why a cell can't be walked onto
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Blocker {
+    OutOfBounds,
+    Terrain(Material),
+    Object(ObjectID),
+    Entity(EntityID),
+}
+
+pub fn material_is_walkable(mat : Material) -> bool {
+    match mat {
+        Material::Water | Material::Ice => false,
+        _ => true,
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct WalkGrid {
+    width : i32,
+    height : i32,
+    //row-major. Some if the terrain there can't be walked on
+    terrain : Vec<Option<Material>>,
+    //row-major. the solid objects on each cell
+    solids : Vec<Vec<ObjectID>>,
+}
+
+impl WalkGrid {
+    //all cells walkable
+    pub fn new(width : i32, height : i32) -> WalkGrid {
+        let cells = (width.max(0) * height.max(0)) as usize;
+        WalkGrid {
+            width : width,
+            height : height,
+            terrain : vec![None; cells],
+            solids : vec![vec![]; cells],
+        }
+    }
+
+    fn index(&self, pt : DPoint2) -> Option<usize> {
+        if self.in_bounds(pt) {
+            Some((pt.y * self.width + pt.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn in_bounds(&self, pt : DPoint2) -> bool {
+        pt.x >= 0 && pt.y >= 0 && pt.x < self.width && pt.y < self.height
+    }
+
+    pub fn set_terrain(&mut self, pt : DPoint2, mat : Material) {
+        if let Some(i) = self.index(pt) {
+            self.terrain[i] = if material_is_walkable(mat) {None} else {Some(mat)};
+        }
+    }
+
+    pub fn add_solid(&mut self, pt : DPoint2, oid : ObjectID) {
+        if let Some(i) = self.index(pt) {
+            self.solids[i].push(oid);
+        }
+    }
+
+    pub fn remove_solid(&mut self, pt : DPoint2, oid : ObjectID) {
+        if let Some(i) = self.index(pt) {
+            if let Some(pos) = self.solids[i].iter().position(|o| *o == oid) {
+                self.solids[i].swap_remove(pos);
+            }
+        }
+    }
+
+    //what blocks `pt`, not counting entities
+    pub fn blocker_at(&self, pt : DPoint2) -> Option<Blocker> {
+        match self.index(pt) {
+            None => Some(Blocker::OutOfBounds),
+            Some(i) => {
+                if let Some(mat) = self.terrain[i] {
+                    Some(Blocker::Terrain(mat))
+                } else if let Some(oid) = self.solids[i].first() {
+                    Some(Blocker::Object(*oid))
+                } else {
+                    None
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_MATERIALS : [Material; 8] = [
+        Material::Rock, Material::Trees, Material::Grass, Material::Water,
+        Material::Ice, Material::Snow, Material::DarkRock, Material::Sand,
+    ];
+
+    fn pt(x : i32, y : i32) -> DPoint2 {
+        DPoint2::new(x, y)
+    }
+
+    #[test]
+    fn new_grid_is_walkable_inside_only() {
+        let grid = WalkGrid::new(4, 3);
+        for y in 0..3 {
+            for x in 0..4 {
+                assert_eq!(grid.blocker_at(pt(x, y)), None);
+            }
+        }
+        for &p in [pt(-1, 0), pt(0, -1), pt(4, 0), pt(0, 3), pt(4, 3)].iter() {
+            assert!(! grid.in_bounds(p));
+            assert_eq!(grid.blocker_at(p), Some(Blocker::OutOfBounds));
+        }
+        //no cells at all
+        assert_eq!(WalkGrid::new(0, 5).blocker_at(pt(0, 0)), Some(Blocker::OutOfBounds));
+        assert_eq!(WalkGrid::new(-2, 5).blocker_at(pt(0, 0)), Some(Blocker::OutOfBounds));
+    }
+
+    #[test]
+    fn water_and_ice_block() {
+        let mut grid = WalkGrid::new(8, 1);
+        for (x, mat) in ALL_MATERIALS.iter().enumerate() {
+            grid.set_terrain(pt(x as i32, 0), *mat);
+        }
+        for (x, mat) in ALL_MATERIALS.iter().enumerate() {
+            let expected = match *mat {
+                Material::Water | Material::Ice => Some(Blocker::Terrain(*mat)),
+                _ => None,
+            };
+            assert_eq!(material_is_walkable(*mat), expected.is_none());
+            assert_eq!(grid.blocker_at(pt(x as i32, 0)), expected, "{:?}", mat);
+        }
+    }
+
+    #[test]
+    fn terrain_can_change_back() {
+        let mut grid = WalkGrid::new(2, 2);
+        grid.set_terrain(pt(1, 1), Material::Ice);
+        assert_eq!(grid.blocker_at(pt(1, 1)), Some(Blocker::Terrain(Material::Ice)));
+        grid.set_terrain(pt(1, 1), Material::Sand);
+        assert_eq!(grid.blocker_at(pt(1, 1)), None);
+    }
+
+    #[test]
+    fn solid_objects_block_until_all_removed() {
+        let mut grid = WalkGrid::new(3, 3);
+        grid.add_solid(pt(1, 2), 7);
+        grid.add_solid(pt(1, 2), 9);
+        assert!(grid.blocker_at(pt(1, 2)).is_some());
+        assert_eq!(grid.blocker_at(pt(2, 1)), None);
+        grid.remove_solid(pt(1, 2), 7);
+        assert_eq!(grid.blocker_at(pt(1, 2)), Some(Blocker::Object(9)));
+        //not there: changes nothing
+        grid.remove_solid(pt(1, 2), 7);
+        grid.remove_solid(pt(0, 0), 9);
+        assert_eq!(grid.blocker_at(pt(1, 2)), Some(Blocker::Object(9)));
+        grid.remove_solid(pt(1, 2), 9);
+        assert_eq!(grid.blocker_at(pt(1, 2)), None);
+    }
+
+    #[test]
+    fn terrain_is_reported_before_objects() {
+        let mut grid = WalkGrid::new(1, 1);
+        grid.add_solid(pt(0, 0), 3);
+        grid.set_terrain(pt(0, 0), Material::Water);
+        assert_eq!(grid.blocker_at(pt(0, 0)), Some(Blocker::Terrain(Material::Water)));
+        grid.set_terrain(pt(0, 0), Material::Grass);
+        assert_eq!(grid.blocker_at(pt(0, 0)), Some(Blocker::Object(3)));
+    }
+
+    #[test]
+    fn changes_out_of_bounds_are_ignored() {
+        let mut grid = WalkGrid::new(2, 2);
+        grid.set_terrain(pt(-1, 0), Material::Water);
+        grid.add_solid(pt(2, 0), 3);
+        grid.remove_solid(pt(0, 5), 3);
+        for y in 0..2 {
+            for x in 0..2 {
+                assert_eq!(grid.blocker_at(pt(x, y)), None);
+            }
+        }
+    }
+}
@@ -51,6 +51,11 @@ impl Material {
     fn is_land(self) -> bool {
         !(self == Material::Water)
     }
+    //colour to draw this terrain with, darkened by `shade` in [0,1]
+    pub fn rgba(&self, shade: f32) -> [f32;4] {
+        let c = px_shade(self.col(), shade);
+        [px_bound(c[0]), px_bound(c[1]), px_bound(c[2]), 1.0]
+    }
     fn col(&self) -> FloatPixel {
         match self {
             &Material::Rock => [0.5, 0.37, 0.24],
@@ -107,6 +112,14 @@ impl WorldPrimitive {
             star_energy: star_energy,
         }
     }
+
+    pub fn super_seed(&self) -> SuperSeed {
+        self.super_seed
+    }
+
+    pub fn star_energy(&self) -> f32 {
+        self.star_energy
+    }
 }
 
 impl KnowsSavePrefix for WorldPrimitive {
@@ -148,6 +161,13 @@ impl World {
 
     pub fn get_size(&self) -> f32 {self.size}
 
+    //the zone and cell a link takes you to from `coord` in `zone_id`, if any link ends there
+    pub fn link_from(&self, zone_id: ZoneID, coord: DPoint2) -> Option<(ZoneID,DPoint2)> {
+        self.links.iter()
+        .filter_map(|l| l.other_end(zone_id, coord))
+        .next()
+    }
+
     fn gen_between<R:Rng>(lower: f32, upper: f32, w: Weighting, rng: &mut R) -> f32 {
         let betweenyness = match w {
             self::Weighting::Equal => {
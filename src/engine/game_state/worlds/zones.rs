@@ -2,6 +2,7 @@
 use super::{Material,PointSampleData,World,pt_wider_dist};
 use super::grid::{TotalGrid,TotalGridBuilder};
 use ::points::*;
+use ::identity::ZoneID;
 use::rand::{Rng};
 use std::collections::{HashMap};
 
@@ -51,6 +52,15 @@ impl Zone {
         self.samples.get_height()
     }
 
+    //data and terrain of the sample at `coord`
+    pub fn sample_at(&self, coord: DPoint2) -> Option<(PointSampleData,Material)> {
+        if coord.x < 0 || coord.y < 0 || coord.x >= self.samples.get_width() {
+            return None;
+        }
+        self.samples.maybe_get(coord.x as usize, coord.y as usize)
+        .map(|s| (s.data, s.mat))
+    }
+
     pub fn close_to_cell(&self, pt: CPoint2) -> bool {
         for (k, v) in (self.samples).into_iter() {
             if v.pt.skewed_dist_to(pt, 2.0, 1.0) < 0.003 {
@@ -88,8 +98,10 @@ impl Zone {
 
     fn shortest_sample_link(
         &self,
+        self_id: ZoneID,
         self_taken: &[DPoint2],
         other: &Zone,
+        other_id: ZoneID,
         other_taken: &[DPoint2],
         zones: &Vec<Zone>,
         w: &World,
@@ -112,6 +124,8 @@ impl Zone {
                     if previous.length() < dist {continue}
                 }
                 shortest = Some(WorldLink {
+                    zone_a: self_id,
+                    zone_b: other_id,
                     zone_a_coord: m_coord,
                     zone_b_coord: t_coord,
                     world_a_pt: my_sample.pt,
@@ -241,6 +255,9 @@ pub fn generate_zones_for<R:Rng>(w: &World, rng: &mut R) -> Vec<Zone> {
 
 #[derive(Debug,Copy,Clone,PartialEq)]
 pub struct WorldLink {
+    zone_a: ZoneID,
+    zone_b: ZoneID,
+    //the location cell in either zone that the link ends at
     zone_a_coord: DPoint2,
     zone_b_coord: DPoint2,
     world_a_pt: CPoint2,
@@ -267,6 +284,17 @@ impl WorldLink {
     pub fn get_world_b_pt(&self) -> CPoint2 {self.world_b_pt}
     pub fn get_mat_a(&self) -> Material{self.mat_a}
     pub fn get_mat_b(&self) -> Material{self.mat_b}
+
+    //where you end up when standing on `coord` in `zone_id`, if it's one of this link's ends
+    pub fn other_end(&self, zone_id: ZoneID, coord: DPoint2) -> Option<(ZoneID,DPoint2)> {
+        if self.zone_a == zone_id && self.zone_a_coord == coord {
+            Some((self.zone_b, self.zone_b_coord))
+        } else if self.zone_b == zone_id && self.zone_b_coord == coord {
+            Some((self.zone_a, self.zone_a_coord))
+        } else {
+            None
+        }
+    }
 }
 
 pub fn generate_links_for<R:Rng>(zones: &Vec<Zone>, rng: &mut R, w : &World) -> Vec<WorldLink> {
@@ -277,7 +305,7 @@ pub fn generate_links_for<R:Rng>(zones: &Vec<Zone>, rng: &mut R, w : &World) ->
     for (i, zone_i) in zones.iter().enumerate() {
         'pair_loop: for (j, zone_j) in zones.iter().enumerate().skip(i+1) {
             if let Some(shortest) = zone_i.shortest_sample_link(
-                &taken_samples[i], zone_j, &taken_samples[j], zones, w
+                i, &taken_samples[i], zone_j, j, &taken_samples[j], zones, w
             ) {
                 if rng.gen_weighted_bool(6) {
                     // ignore connections randomly
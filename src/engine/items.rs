@@ -0,0 +1,140 @@
+use ::identity::{ItemID,ObjectID,AssetID};
+use ::engine::objects::{ObjectData,ITEM_OID_BASE};
+
+/*
+Things entities carry around. Every item of a kind is the same, so an entity's Inventory only
+counts how many of each it has, in stacks of up to max_stack.
+Items lying in a location are a pile per kind and cell (see server_game::ground_items), which
+shows up in the location as the item's object.
+*/
+
+#[derive(Copy,Clone,Debug)]
+pub struct ItemDef {
+    pub name : &'static str,
+    pub aid : AssetID,
+    //most of it one inventory slot holds
+    pub max_stack : u32,
+    //can be held for everyone to see. see components::Component::Equipped
+    pub equippable : bool,
+    pub width_meters : f64,
+}
+
+//indexed by ItemID. only ever append: saves refer to items by index
+const ITEM_DEFS : [ItemDef; 3] = [
+    ItemDef {name : "Stick", aid : 5, max_stack : 20, equippable : true, width_meters : 0.5},
+    ItemDef {name : "Stone", aid : 6, max_stack : 10, equippable : true, width_meters : 0.3},
+    ItemDef {name : "Berries", aid : 7, max_stack : 50, equippable : false, width_meters : 0.3},
+];
+
+pub fn item_def(iid : ItemID) -> Option<&'static ItemDef> {
+    ITEM_DEFS.get(iid as usize)
+}
+
+pub fn item_ids() -> Box<Iterator<Item=ItemID>> {
+    Box::new((0..ITEM_DEFS.len()).map(|i| i as ItemID))
+}
+
+//the object showing a pile of `iid` on the ground
+pub fn item_oid(iid : ItemID) -> ObjectID {
+    ITEM_OID_BASE + iid as ObjectID
+}
+
+//the item whose pile `oid` shows, if it does
+pub fn item_of_oid(oid : ObjectID) -> Option<ItemID> {
+    if oid < ITEM_OID_BASE {
+        return None;
+    }
+    let iid = (oid - ITEM_OID_BASE) as ItemID;
+    item_def(iid).map(|_| iid)
+}
+
+pub fn item_object_data(def : &ItemDef) -> ObjectData {
+    ObjectData::new(def.aid, def.width_meters)
+}
+
+#[derive(Serialize,Deserialize,Copy,Clone,Debug,PartialEq)]
+pub struct ItemStack {
+    pub iid : ItemID,
+    pub count : u32,
+}
+
+pub const INVENTORY_SLOTS : usize = 10;
+
+#[derive(Serialize,Deserialize,Clone,Debug,PartialEq)]
+pub struct Inventory {
+    //always INVENTORY_SLOTS long. None for empty slots
+    slots : Vec<Option<ItemStack>>,
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory {
+            slots : vec![None; INVENTORY_SLOTS],
+        }
+    }
+
+    pub fn slots(&self) -> &[Option<ItemStack>] {
+        &self.slots
+    }
+
+    pub fn slot(&self, slot : usize) -> Option<ItemStack> {
+        self.slots.get(slot).and_then(|s| *s)
+    }
+
+    pub fn count_of(&self, iid : ItemID) -> u32 {
+        self.slots.iter()
+        .filter_map(|s| *s)
+        .filter(|s| s.iid == iid)
+        .map(|s| s.count)
+        .sum()
+    }
+
+    /*
+    Puts up to `count` of `iid` in, topping up stacks of it before starting new ones.
+    Returns how many fit
+    */
+    pub fn add(&mut self, iid : ItemID, count : u32) -> u32 {
+        let max_stack = match item_def(iid) {
+            Some(def) => def.max_stack,
+            None => return 0,
+        };
+        let mut left = count;
+        for slot in self.slots.iter_mut() {
+            if let Some(ref mut stack) = *slot {
+                if stack.iid == iid {
+                    let moved = ::std::cmp::min(left, max_stack - stack.count);
+                    stack.count += moved;
+                    left -= moved;
+                }
+            }
+        }
+        for slot in self.slots.iter_mut() {
+            if left == 0 {
+                break;
+            }
+            if slot.is_none() {
+                let moved = ::std::cmp::min(left, max_stack);
+                *slot = Some(ItemStack {iid : iid, count : moved});
+                left -= moved;
+            }
+        }
+        count - left
+    }
+
+    //takes up to `count` from the stack in `slot`. None if the slot is empty
+    pub fn take(&mut self, slot : usize, count : u32) -> Option<ItemStack> {
+        let stack = self.slots.get_mut(slot)?;
+        let taken = match *stack {
+            Some(ref mut s) if count > 0 => {
+                let taken = ::std::cmp::min(count, s.count);
+                s.count -= taken;
+                ItemStack {iid : s.iid, count : taken}
+            },
+            _ => return None,
+        };
+        if stack.map(|s| s.count) == Some(0) {
+            *stack = None;
+        }
+        Some(taken)
+    }
+}
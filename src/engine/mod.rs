@@ -1,8 +1,10 @@
 pub mod game_state;
 mod client_game;
-mod server_game;
+pub mod server_game;
 pub mod entities;
+pub mod components;
 pub mod objects;
+pub mod items;
 // pub mod server_game_state;
 
 use std::sync::{Arc,Mutex};
@@ -12,6 +14,7 @@ use network::userbase::UserBase;
 use super::identity::ClientID;
 
 use super::saving::SaverLoader;
+use super::setup::ServerSettings;
 
 
 /*
@@ -22,8 +25,9 @@ pub fn server_engine(serv_in : Arc<ProtectedQueue<MsgFromClient>>,
                     serv_out : Arc<ProtectedQueue<MsgToClientSet>>,
                     userbase : Arc<Mutex<UserBase>>,
                     sl : SaverLoader,
+                    settings : ServerSettings,
                 ) {
-    server_game::game_loop(serv_in, serv_out, userbase, sl);
+    server_game::game_loop(serv_in, serv_out, userbase, sl, settings);
 }
 
 /*
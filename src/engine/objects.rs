@@ -37,6 +37,23 @@ impl ObjectData {
     }
 }
 
+//generated by location terrain. see locations::generate_objects
+pub const TREE_OID : ObjectID = 0;
+pub const ROCK_OID : ObjectID = 2;
+pub const ROCK_AID : AssetID = 3;
+
+//the object marking a portal entrance
+pub const PORTAL_OID : ObjectID = 1;
+pub const PORTAL_AID : AssetID = 2;
+
+//items lying on the ground. see items::item_oid
+pub const ITEM_OID_BASE : ObjectID = 100;
+
+//solid objects can't be walked through. portals have to be stepped on, items stepped over
+pub fn object_is_solid(oid : ObjectID) -> bool {
+    oid != PORTAL_OID && oid < ITEM_OID_BASE
+}
+
 impl KnowsSavePrefix for ObjectData {
     fn get_save_prefix() -> String {
         "object".to_owned()
@@ -0,0 +1,128 @@
+use std::time::{Duration,SystemTime,UNIX_EPOCH};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use rand::Isaac64Rng;
+use ::identity::{LocationID,EntityID};
+use ::engine::components::EntityComponents;
+use ::engine::game_state::locations::Location;
+use ::network::messaging::Diff;
+use ::utils::traits::*;
+use super::server_game_state::loc_guard::LocationGuard;
+
+/*
+Locations without subscribers get evicted, so nothing in them runs.
+When one loads again, every registered CatchUp is stepped forward over the time it was away,
+so the location looks like it kept going (NPCs wandered off, things regrew...).
+While a location is loaded, the same systems are stepped every server tick instead.
+What catching up changed is journaled (JournalEntry::CaughtUp) like any other accepted change,
+so a crash before the next save replays it rather than catching up all over again.
+*/
+
+//simulated time per step
+pub const CATCH_UP_STEP_SECS : u64 = 1;
+//a location away for longer only catches up this much. bounds the cost of a load
+pub const MAX_CATCH_UP_STEPS : u32 = 600;
+
+/*
+When each evicted location was evicted, in seconds since the unix epoch.
+Saved, so locations evicted before a restart still catch up when they load after it
+*/
+#[derive(Debug,Serialize,Deserialize)]
+pub struct EvictionTimes {
+    evicted_at : HashMap<LocationID,u64>,
+    //true if changed since the last save
+    #[serde(skip)]
+    dirty : bool,
+}
+
+impl KnowsSavePrefix for EvictionTimes {
+    fn get_save_prefix() -> String {
+        "eviction_times".to_owned()
+    }
+}
+
+fn unix_secs_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
+}
+
+impl EvictionTimes {
+    pub fn new() -> EvictionTimes {
+        EvictionTimes {
+            evicted_at : HashMap::new(),
+            dirty : false,
+        }
+    }
+
+    pub fn evicted_now(&mut self, lid : LocationID) {
+        self.evicted_at.insert(lid, unix_secs_now());
+        self.dirty = true;
+    }
+
+    //how long ago `lid` was evicted, if it was. forgets the eviction
+    pub fn consume(&mut self, lid : LocationID) -> Option<Duration> {
+        let at = self.evicted_at.remove(&lid)?;
+        self.dirty = true;
+        //a clock set back since counts as no time away
+        Some(Duration::from_secs(unix_secs_now().saturating_sub(at)))
+    }
+
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    #[inline]
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+
+    #[cfg(test)]
+    pub fn evicted_secs_ago(&mut self, lid : LocationID, secs : u64) {
+        self.evicted_at.insert(lid, unix_secs_now() - secs);
+        self.dirty = true;
+    }
+}
+
+pub trait CatchUp : Debug {
+    /*
+    Returns what changes in `loc` over `step` of simulated time.
+    `entities` has the components of (at least) every entity in `loc`.
+    Diffs that no longer apply by the time they are reached are dropped.
+    */
+    fn step(&self, lid : LocationID, loc : &Location, entities : &HashMap<EntityID,EntityComponents>,
+            step : Duration, rng : &mut Isaac64Rng) -> Vec<Diff>;
+}
+
+/*
+Runs all `systems` over `away` in bounded steps. Returns the diffs that applied, in order.
+Nothing is journaled here: the caller journals the returned diffs once it can
+*/
+pub fn catch_up(loc_guard : &mut LocationGuard,
+                lid : LocationID,
+                away : Duration,
+                systems : &[Box<CatchUp>],
+                entities : &HashMap<EntityID,EntityComponents>,
+                rng : &mut Isaac64Rng,
+            ) -> Vec<Diff> {
+    let mut applied = vec![];
+    if systems.is_empty() {
+        return applied;
+    }
+    let step = Duration::from_secs(CATCH_UP_STEP_SECS);
+    let steps = ::std::cmp::min(away.as_secs() / CATCH_UP_STEP_SECS, MAX_CATCH_UP_STEPS as u64) as u32;
+    for _ in 0..steps {
+        for system in systems.iter() {
+            for diff in system.step(lid, loc_guard.borrow_location(), entities, step, rng) {
+                if loc_guard.apply_diff_unjournaled(diff).is_ok() {
+                    applied.push(diff);
+                }
+            }
+        }
+    }
+    if steps > 0 {
+        println!("LID {:?} caught up {} steps after {:?} away", lid, steps, away);
+    }
+    applied
+}
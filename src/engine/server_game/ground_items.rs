@@ -0,0 +1,92 @@
+use std::collections::{HashMap,HashSet};
+use rand::{Rng,SeedableRng,Isaac64Rng};
+use ::identity::*;
+use ::points::DPoint2;
+use ::utils::traits::*;
+use ::engine::items::{self,item_def};
+use ::engine::game_state::locations::Location;
+
+/*
+Items lying in locations: how many of each item there are at each cell.
+Each pile shows up in its location as the item's object (see items::item_oid). The objects are
+placed and removed with location diffs as piles come and go.
+*/
+#[derive(Debug,Serialize,Deserialize)]
+pub struct GroundItems {
+    #[serde(with = "::utils::serde_pairs")]
+    piles: HashMap<(LocationID,DPoint2,ItemID),u32>,
+    //true if changed since the last save
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl KnowsSavePrefix for GroundItems {
+    fn get_save_prefix() -> String {
+         "ground_items".to_owned()
+    }
+}
+
+//a newly registered location gets up to this many piles
+const MAX_GENERATED_PILES : u32 = 6;
+//keeps the item rng of a location apart from its other seeded rngs
+const ITEM_SEED_SALT : u64 = 0x4954_454d;
+
+impl GroundItems {
+    pub fn new() -> GroundItems {
+        GroundItems {
+            piles: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn count(&self, lid: LocationID, pt: DPoint2, iid: ItemID) -> u32 {
+        self.piles.get(&(lid, pt, iid)).cloned().unwrap_or(0)
+    }
+
+    // a count of 0 clears the pile
+    pub fn set(&mut self, lid: LocationID, pt: DPoint2, iid: ItemID, count: u32) {
+        if count == 0 {
+            self.piles.remove(&(lid, pt, iid));
+        } else {
+            self.piles.insert((lid, pt, iid), count);
+        }
+        self.dirty = true;
+    }
+
+    // the objects showing the piles in `lid`
+    pub fn objects_in(&self, lid: LocationID) -> Vec<(ObjectID,DPoint2)> {
+        self.piles.keys()
+        .filter(|&&(p_lid, _, _)| p_lid == lid)
+        .map(|&(_, pt, iid)| (items::item_oid(iid), pt))
+        .collect()
+    }
+
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    #[inline]
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+}
+
+// the piles a location starts with when it is registered, derived from its seed
+pub fn generated_piles(loc: &Location) -> Vec<(DPoint2,ItemID,u32)> {
+    let seed = loc.get_location_primitive().super_seed;
+    let mut rng = Isaac64Rng::from_seed(&[seed, ITEM_SEED_SALT]);
+    let kinds: Vec<ItemID> = items::item_ids().collect();
+    let mut used = HashSet::new();
+    let mut piles = vec![];
+    for _ in 0..rng.gen_range(0, MAX_GENERATED_PILES + 1) {
+        let pt = DPoint2::new(rng.gen_range(0, loc.cells_wide()), rng.gen_range(0, loc.cells_high()));
+        let iid = *rng.choose(&kinds).expect("no items defined");
+        if ! loc.point_is_free(pt) || ! used.insert(pt) {
+            continue;
+        }
+        let max_stack = item_def(iid).expect("you said..").max_stack;
+        piles.push((pt, iid, rng.gen_range(1, max_stack / 2 + 2)));
+    }
+    piles
+}
@@ -0,0 +1,208 @@
+use std::collections::{HashMap,HashSet};
+use ::identity::*;
+use ::points::DPoint2;
+use ::engine::game_state::locations::{Location,entity_digest_of};
+use ::network::messaging::{MsgToClient,MsgToClientSet,Diff};
+use super::subscription_manager::SubscriptionManager;
+
+/*
+Narrows what subscribers of a location hear about its entities down to those near their own.
+Each client's area of interest is a square around the entity it controls; clients subscribed
+to a location they have no entity in see all of it.
+The server remembers which entities each client was told about. An entity crossing into a
+client's area arrives as PlaceInside, one crossing out leaves as OutOfInterest, and moves
+outside it aren't sent at all. Who is near whom is looked up in the location's own quadtree.
+Hidden entities are out of everyone's area. Object diffs aren't filtered.
+*/
+
+pub struct InterestManager {
+    radius_meters : f64,
+    //entities each client was told are in a location
+    known : HashMap<(ClientID,LocationID),HashSet<EntityID>>,
+    hidden : HashSet<EntityID>,
+}
+
+impl InterestManager {
+    pub fn new(radius_meters : f64) -> InterestManager {
+        InterestManager {
+            radius_meters : radius_meters,
+            known : HashMap::new(),
+            hidden : HashSet::new(),
+        }
+    }
+
+    fn radius_cells(&self, loc : &Location) -> i32 {
+        let cell_to_meters = loc.get_location_primitive().cell_to_meters;
+        ((self.radius_meters / cell_to_meters).ceil() as i32).max(1)
+    }
+
+    //forget what `cid` was told about `lid`. call when it unsubscribes
+    pub fn forget_client(&mut self, cid : ClientID, lid : LocationID) {
+        self.known.remove(&(cid, lid));
+    }
+
+    //keeps `eid` from every client's view. call refresh_subscribers for its location after
+    pub fn hide(&mut self, eid : EntityID) {
+        self.hidden.insert(eid);
+    }
+
+    //lets `eid` be seen again. call refresh_subscribers for its location after
+    pub fn unhide(&mut self, eid : EntityID) {
+        self.hidden.remove(&eid);
+    }
+
+    //brings every subscriber of `lid` up to date with who is hidden now
+    pub fn refresh_subscribers(&mut self, lid : LocationID, loc : &Location, tick : Tick,
+                               subscription_manager : &SubscriptionManager,
+                               controlling : &HashMap<ClientID,(EntityID,LocationID)>,
+                               outgoing_updates : &mut Vec<MsgToClientSet>) {
+        for cid in subscription_manager.iter_subs_for(lid) {
+            self.refresh_client(cid, lid, loc, false, tick, controlling, outgoing_updates);
+        }
+    }
+
+    //the entities `cid` should see in `lid`, if it has an area there. None means all of them
+    fn area_of(&self, cid : ClientID, lid : LocationID, loc : &Location,
+               controlling : &HashMap<ClientID,(EntityID,LocationID)>) -> Option<HashSet<EntityID>> {
+        let radius = self.radius_cells(loc);
+        match controlling.get(&cid) {
+            Some(&(eid, c_lid)) if c_lid == lid => {
+                loc.point_of(eid).map(|center| {
+                    loc.entities_within(
+                        DPoint2::new(center.x - radius, center.y - radius),
+                        DPoint2::new(center.x + radius, center.y + radius),
+                    ).into_iter().map(|(eid, _)| eid).collect()
+                })
+            },
+            _ => None,
+        }
+    }
+
+    /*
+    Makes what `cid` knows of `lid` match its area of interest, sending the differences.
+    With `fresh`, the client is assumed to know nothing yet (it just asked for the location)
+    */
+    fn refresh_client(&mut self, cid : ClientID, lid : LocationID, loc : &Location, fresh : bool, tick : Tick,
+                      controlling : &HashMap<ClientID,(EntityID,LocationID)>,
+                      outgoing_updates : &mut Vec<MsgToClientSet>) {
+        let mut visible = match self.area_of(cid, lid, loc, controlling) {
+            Some(area) => area,
+            None => loc.entity_iterator().map(|(eid, _)| *eid).collect(),
+        };
+        for eid in self.hidden.iter() {
+            visible.remove(eid);
+        }
+        let known = self.known.entry((cid, lid)).or_insert_with(HashSet::new);
+        if fresh {
+            known.clear();
+        }
+        for eid in known.difference(&visible) {
+            outgoing_updates.push(MsgToClientSet::Only(
+                MsgToClient::ApplyLocationDiff(lid, Diff::OutOfInterest(*eid), tick), cid,
+            ));
+        }
+        for eid in visible.difference(known) {
+            let pt = loc.point_of(*eid).expect("visible entity has no point");
+            outgoing_updates.push(MsgToClientSet::Only(
+                MsgToClient::ApplyLocationDiff(lid, Diff::PlaceInside(*eid, pt), tick), cid,
+            ));
+        }
+        *known = visible;
+    }
+
+    //tells `cid`, which just subscribed to `lid`, about the entities in its area
+    pub fn initial_view(&mut self, cid : ClientID, lid : LocationID, loc : &Location, tick : Tick,
+                        controlling : &HashMap<ClientID,(EntityID,LocationID)>,
+                        outgoing_updates : &mut Vec<MsgToClientSet>) {
+        self.refresh_client(cid, lid, loc, true, tick, controlling, outgoing_updates);
+    }
+
+    /*
+    Sends `diff`, just applied to `loc` (of `lid`), to the subscribers who should hear of it.
+    Entities entering or leaving someone's area because of it are sent as such
+    */
+    pub fn broadcast(&mut self, lid : LocationID, diff : Diff, tick : Tick, loc : &Location,
+                     subscription_manager : &SubscriptionManager,
+                     controlling : &HashMap<ClientID,(EntityID,LocationID)>,
+                     outgoing_updates : &mut Vec<MsgToClientSet>) {
+        let subscribers = subscription_manager.get_subs_for(lid);
+        if subscribers.is_empty() {
+            return;
+        }
+        let eid = match diff {
+            Diff::MoveEntityTo(eid,_) | Diff::PlaceInside(eid,_) | Diff::RemoveEntity(eid) => eid,
+            _ => {
+                outgoing_updates.push(MsgToClientSet::Subset(
+                    MsgToClient::ApplyLocationDiff(lid, diff, tick), subscribers,
+                ));
+                return;
+            },
+        };
+        let mut hear_diff = ClientIDSet::new();
+        for cid in subscribers.iter_set_pos() {
+            let moved_self = controlling.get(&cid) == Some(&(eid, lid));
+            if moved_self {
+                hear_diff.set(cid, true);
+                match diff {
+                    //it left. nothing here is its business anymore
+                    Diff::RemoveEntity(_) => {self.known.remove(&(cid, lid));},
+                    //its whole area moved along with it
+                    _ => {
+                        self.known.entry((cid, lid)).or_insert_with(HashSet::new).insert(eid);
+                        self.refresh_client(cid, lid, loc, false, tick, controlling, outgoing_updates);
+                    },
+                }
+                continue;
+            }
+            let sees = match diff {
+                Diff::RemoveEntity(_) => false,
+                _ if self.hidden.contains(&eid) => false,
+                _ => match self.area_of(cid, lid, loc, controlling) {
+                    Some(area) => area.contains(&eid),
+                    None => true,
+                },
+            };
+            let known = self.known.entry((cid, lid)).or_insert_with(HashSet::new);
+            match (known.contains(&eid), sees) {
+                (true, true) => hear_diff.set(cid, true),
+                (false, true) => {
+                    known.insert(eid);
+                    let pt = loc.point_of(eid).expect("placed entity has no point");
+                    outgoing_updates.push(MsgToClientSet::Only(
+                        MsgToClient::ApplyLocationDiff(lid, Diff::PlaceInside(eid, pt), tick), cid,
+                    ));
+                },
+                (true, false) => {
+                    known.remove(&eid);
+                    let gone = match diff {
+                        Diff::RemoveEntity(_) => diff,
+                        _ => Diff::OutOfInterest(eid),
+                    };
+                    outgoing_updates.push(MsgToClientSet::Only(
+                        MsgToClient::ApplyLocationDiff(lid, gone, tick), cid,
+                    ));
+                },
+                (false, false) => (),
+            }
+        }
+        if ! hear_diff.is_empty() {
+            outgoing_updates.push(MsgToClientSet::Subset(
+                MsgToClient::ApplyLocationDiff(lid, diff, tick), hear_diff,
+            ));
+        }
+    }
+
+    //what `cid` should have of `lid`'s entities, ordered like Location::entity_snapshot
+    pub fn snapshot_for(&self, cid : ClientID, lid : LocationID, loc : &Location) -> Vec<(EntityID,DPoint2)> {
+        let mut v : Vec<(EntityID,DPoint2)> = match self.known.get(&(cid, lid)) {
+            Some(known) => known.iter().filter_map(|eid| loc.point_of(*eid).map(|pt| (*eid, pt))).collect(),
+            None => vec![],
+        };
+        v.sort_by_key(|&(eid, _)| eid);
+        v
+    }
+
+    pub fn digest_for(&self, cid : ClientID, lid : LocationID, loc : &Location) -> u64 {
+        entity_digest_of(&self.snapshot_for(cid, lid, loc))
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use rand::{Rng,SeedableRng,Isaac64Rng};
+use ::identity::*;
+use ::utils::traits::*;
+use super::server_game_state::START_LOCATION_LID;
+
+/*
+Every zone of every world can be a location. The registry hands out a LocationID for a zone
+the first time anyone asks for it, and keeps the mapping stable from then on.
+The location's prim isn't stored here; it's derived from the world seed (see location_seed).
+*/
+#[derive(Serialize,Deserialize,Debug)]
+pub struct LocationRegistry {
+    next_lid : LocationID,
+    #[serde(with = "::utils::serde_pairs")]
+    zone_to_lid : HashMap<(WorldID,ZoneID),LocationID>,
+    //true if changed since the last save
+    #[serde(skip)]
+    dirty : bool,
+}
+
+impl KnowsSavePrefix for LocationRegistry {
+    fn get_save_prefix() -> String {
+        "location_registry".to_owned()
+    }
+}
+
+// the seed of the location at `zone_id`. same world seed, same locations
+pub fn location_seed(world_seed : SuperSeed, zone_id : ZoneID) -> SuperSeed {
+    Isaac64Rng::from_seed(&[world_seed, zone_id as u64]).gen()
+}
+
+impl LocationRegistry {
+    // a new game. only the start location exists
+    pub fn new() -> LocationRegistry {
+        let mut zone_to_lid = HashMap::new();
+        zone_to_lid.insert((0,0), START_LOCATION_LID);
+        LocationRegistry {
+            next_lid : START_LOCATION_LID + 1,
+            zone_to_lid : zone_to_lid,
+            dirty : true,
+        }
+    }
+
+    pub fn lid_of(&self, wid : WorldID, zone_id : ZoneID) -> Option<LocationID> {
+        self.zone_to_lid.get(&(wid,zone_id)).cloned()
+    }
+
+    pub fn zone_of(&self, lid : LocationID) -> Option<(WorldID,ZoneID)> {
+        self.zone_to_lid.iter()
+        .find(|&(_, l)| *l == lid)
+        .map(|(zone, _)| *zone)
+    }
+
+    // returns the zone's LocationID, and TRUE if it was only just registered
+    pub fn register(&mut self, wid : WorldID, zone_id : ZoneID) -> (LocationID, bool) {
+        if let Some(lid) = self.lid_of(wid, zone_id) {
+            return (lid, false);
+        }
+        let lid = self.next_lid;
+        self.restore(wid, zone_id, lid);
+        (lid, true)
+    }
+
+    // re-registers a mapping made before (eg. read back from the journal)
+    pub fn restore(&mut self, wid : WorldID, zone_id : ZoneID, lid : LocationID) {
+        self.zone_to_lid.insert((wid,zone_id), lid);
+        if lid >= self.next_lid {
+            self.next_lid = lid + 1;
+        }
+        self.dirty = true;
+    }
+
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    #[inline]
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use ::identity::*;
+use ::points::DPoint2;
+use ::utils::traits::*;
+use ::engine::game_state::UPDATES_PER_SEC;
+
+/*
+What becomes of the entity a client controls while the client is logged out.
+Keep leaves it standing where it was. Despawn removes it from its location once the client has
+been gone for the grace period, remembering where it stood. Hide leaves it where it was, holding
+its cell, but out of sight of every other client.
+Whatever the policy, logging back in brings the entity back as it was.
+*/
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum LogoutPolicy {
+    Keep,
+    Despawn(Duration),
+    Hide,
+}
+
+impl LogoutPolicy {
+    // eg "keep", "hide" or "despawn:30" for a grace period of 30 seconds
+    pub fn parse(s : &str) -> Result<LogoutPolicy,String> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("keep"), None) => Ok(LogoutPolicy::Keep),
+            (Some("hide"), None) => Ok(LogoutPolicy::Hide),
+            (Some("despawn"), secs) => {
+                let secs = match secs {
+                    Some(secs) => secs.parse::<u64>()
+                        .map_err(|_| format!("`{}` isn't a number of seconds", secs))?,
+                    None => 0,
+                };
+                Ok(LogoutPolicy::Despawn(Duration::from_secs(secs)))
+            },
+            _ => Err(format!("`{}` isn't `keep`, `hide` or `despawn:<secs>`", s)),
+        }
+    }
+}
+
+//where the despawned entities of logged out clients stood
+#[derive(Debug,Serialize,Deserialize)]
+pub struct ParkedEntities {
+    spots : HashMap<ClientID,DPoint2>,
+    //true if changed since the last save
+    #[serde(skip)]
+    dirty : bool,
+}
+
+impl KnowsSavePrefix for ParkedEntities {
+    fn get_save_prefix() -> String {
+        "parked_entities".to_owned()
+    }
+}
+
+impl ParkedEntities {
+    pub fn new() -> ParkedEntities {
+        ParkedEntities {
+            spots : HashMap::new(),
+            dirty : false,
+        }
+    }
+
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    #[inline]
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+}
+
+pub struct LogoutManager {
+    policy : LogoutPolicy,
+    parked : ParkedEntities,
+    //clients whose entity gets despawned in the given tick, unless they log back in first
+    despawn_at : HashMap<ClientID,Tick>,
+}
+
+impl LogoutManager {
+    pub fn new(policy : LogoutPolicy, parked : ParkedEntities) -> LogoutManager {
+        LogoutManager {
+            policy : policy,
+            parked : parked,
+            despawn_at : HashMap::new(),
+        }
+    }
+
+    pub fn policy(&self) -> LogoutPolicy {
+        self.policy
+    }
+
+    pub fn get_parked(&self) -> &ParkedEntities {
+        &self.parked
+    }
+
+    pub fn get_mut_parked(&mut self) -> &mut ParkedEntities {
+        &mut self.parked
+    }
+
+    //`cid` just left. starts its grace period, if there is one
+    pub fn logged_out(&mut self, cid : ClientID, tick : Tick) {
+        if let LogoutPolicy::Despawn(grace) = self.policy {
+            self.despawn_at.insert(cid, tick + grace.as_secs() * UPDATES_PER_SEC);
+        }
+    }
+
+    //`cid` is back. None if its entity was left standing, else where it stood
+    pub fn logged_in(&mut self, cid : ClientID) -> Option<DPoint2> {
+        self.despawn_at.remove(&cid);
+        self.unpark(cid)
+    }
+
+    //clients whose grace period ran out by `tick`
+    pub fn due_despawns(&mut self, tick : Tick) -> Vec<ClientID> {
+        let due : Vec<ClientID> = self.despawn_at.iter()
+        .filter(|&(_, at)| *at <= tick)
+        .map(|(cid, _)| *cid)
+        .collect();
+        for cid in due.iter() {
+            self.despawn_at.remove(cid);
+        }
+        due
+    }
+
+    pub fn park(&mut self, cid : ClientID, pt : DPoint2) {
+        self.parked.spots.insert(cid, pt);
+        self.parked.dirty = true;
+    }
+
+    pub fn unpark(&mut self, cid : ClientID) -> Option<DPoint2> {
+        let spot = self.parked.spots.remove(&cid);
+        if spot.is_some() {
+            self.parked.dirty = true;
+        }
+        spot
+    }
+}
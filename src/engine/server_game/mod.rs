@@ -1,13 +1,31 @@
 mod server_game_state;
 mod server_resources;
 mod subscription_manager;
+pub mod catch_up;
+pub mod location_registry;
+pub mod portals;
+pub mod ground_items;
+mod movement;
+pub mod ticker;
+mod interest;
+pub mod logout;
+mod replication;
+mod npcs;
 
 use self::subscription_manager::SubscriptionManager;
+use self::movement::{MovementManager,Step};
+use self::ticker::{Ticker,LastTick};
+use self::interest::InterestManager;
+use self::logout::{LogoutManager,LogoutPolicy,ParkedEntities};
+use self::replication::ComponentReplicator;
+use self::npcs::NpcBehaviors;
 use super::game_state;
 use ::points::*;
-use super::entities::{EntityData};
-use super::super::identity::{EntityID,LocationID};
-use self::server_game_state::{LocationLoader,START_LOCATION_LID};
+use super::entities::{EntityData,PLAYER_AID,NPC_AID};
+use super::components::{Component,ComponentKind,ComponentDiff,EntityComponents,Appearance,Health,Behavior};
+use super::items::{self,Inventory};
+use super::super::identity::{EntityID,LocationID,WorldID,ZoneID,ObjectID,ItemID,UniquePoint,Tick};
+use self::server_game_state::START_LOCATION_LID;
 use self::server_resources::ServerResources;
 use rand::{Isaac64Rng,SeedableRng};
 
@@ -16,18 +34,23 @@ use std::time;
 use std::collections::HashMap;
 
 use utils::traits::*;
-use super::objects::{ObjectData};
-use ::network::messaging::{MsgToClientSet,MsgFromClient,MsgToClient,MsgToServer,Diff};
+use super::objects::{ObjectData,TREE_OID,ROCK_OID,ROCK_AID};
+use ::network::messaging::{MsgToClientSet,MsgFromClient,MsgToClient,MsgToServer,Diff,location_snapshot};
 use ::network::{ProtectedQueue};
 use ::network::userbase::{UserBase};
 use super::ClientID;
-use std::thread;
 use super::SaverLoader;
+use ::setup::ServerSettings;
+use ::backups;
+use ::journal::Journal;
 
 #[derive(Serialize,Deserialize,Debug)]
-struct ServerData {
+pub struct ServerData {
     next_eid : EntityID,
     cid_to_controlling : HashMap<ClientID, (EntityID,LocationID)>,
+    //true if changed since the last save
+    #[serde(skip)]
+    dirty : bool,
 }
 impl KnowsSavePrefix for ServerData {
     fn get_save_prefix() -> String {
@@ -36,73 +59,940 @@ impl KnowsSavePrefix for ServerData {
 }
 impl ServerData {
     fn use_next_eid(&mut self) -> EntityID {
+        self.dirty = true;
         self.next_eid += 1;
         self.next_eid - 1
     }
+
+    fn set_controlling(&mut self, cid : ClientID, eid : EntityID, lid : LocationID) {
+        self.dirty = true;
+        self.cid_to_controlling.insert(cid, (eid,lid));
+    }
+
+    //the client controlling `eid`, if any
+    fn controller_of(&self, eid : EntityID) -> Option<ClientID> {
+        self.cid_to_controlling.iter()
+        .find(|&(_, &(c_eid, _))| c_eid == eid)
+        .map(|(cid, _)| *cid)
+    }
+}
+
+/*
+Everything that changes persisted server state between two autosaves.
+Appended to the journal as it is accepted, and replayed on top of the last save at startup.
+*/
+#[derive(Serialize,Deserialize,Debug)]
+pub enum JournalEntry {
+    LocationDiff(LocationID,Diff),
+    NextEid(EntityID),
+    Controlling(ClientID,EntityID,LocationID),
+    //written before entities had components
+    DefineEntity(EntityID,EntityData),
+    ClientSetup(ClientID),
+    ZoneLocation(WorldID,ZoneID,LocationID),
+    Portal(UniquePoint,UniquePoint),
+    //where a logged out client's entity stood when it was despawned. None once it is back
+    Parked(ClientID,Option<DPoint2>),
+    DefineComponents(EntityID,EntityComponents),
+    ComponentDiff(EntityID,ComponentDiff),
+    //how many of the item now lie at the cell. its object is placed or removed with a LocationDiff
+    GroundPile(LocationID,DPoint2,ItemID,u32),
+    //the tick the entries before it (back to the previous Tick) were made in
+    Tick(Tick),
+    //the location's eviction time was used up catching it up. what that changed follows as LocationDiffs
+    CaughtUp(LocationID),
+}
+
+pub const JOURNAL_NAME : &'static str = "journal";
+
+const PLAYER_MAX_HEALTH : u32 = 100;
+const NPC_MAX_HEALTH : u32 = 40;
+
+fn replay_journal(entries : Vec<JournalEntry>,
+                  userbase : &Arc<Mutex<UserBase>>,
+                  server_data : &mut ServerData,
+                  sr : &mut ServerResources,
+                  logout_manager : &mut LogoutManager,
+              ) -> Option<Tick> {
+    println!("Replaying {} journal entries", entries.len());
+    let mut last_tick = None;
+    for entry in entries {
+        match entry {
+            JournalEntry::LocationDiff(lid, diff) => {
+                if sr.replay_location_diff(lid, diff).is_err() {
+                    println!("Journaled diff {:?} no longer applies to lid {:?}", diff, lid);
+                }
+            },
+            JournalEntry::NextEid(next_eid) => {
+                if next_eid > server_data.next_eid {
+                    server_data.next_eid = next_eid;
+                    server_data.dirty = true;
+                }
+            },
+            JournalEntry::Controlling(cid, eid, lid) => server_data.set_controlling(cid, eid, lid),
+            JournalEntry::DefineEntity(eid, data) => sr.define_entity(eid, EntityComponents::from_legacy(data)),
+            JournalEntry::DefineComponents(eid, components) => sr.define_entity(eid, components),
+            JournalEntry::ComponentDiff(eid, diff) => {
+                if sr.apply_component_diff(eid, diff.clone()).is_err() {
+                    println!("Journaled component diff {:?} no longer applies to eid {:?}", diff, eid);
+                }
+            },
+            JournalEntry::ClientSetup(cid) => userbase.lock().unwrap().set_client_setup_true(cid),
+            JournalEntry::ZoneLocation(wid, zone_id, lid) => sr.restore_zone_location(wid, zone_id, lid),
+            JournalEntry::Portal(entrance, exit) => {
+                sr.add_portal(entrance, exit);
+            },
+            JournalEntry::Parked(cid, Some(pt)) => logout_manager.park(cid, pt),
+            JournalEntry::Parked(cid, None) => {
+                logout_manager.unpark(cid);
+            },
+            JournalEntry::GroundPile(lid, pt, iid, count) => sr.set_ground_pile(lid, pt, iid, count),
+            JournalEntry::Tick(tick) => last_tick = ::std::cmp::max(last_tick, Some(tick)),
+            //so that loading it for the diffs that follow doesn't catch up again
+            JournalEntry::CaughtUp(lid) => {
+                sr.consume_time_since_last_sim(lid);
+            },
+        }
+    }
+    last_tick
 }
 
 pub fn game_loop(serv_in : Arc<ProtectedQueue<MsgFromClient>>,
                  serv_out : Arc<ProtectedQueue<MsgToClientSet>>,
                  userbase : Arc<Mutex<UserBase>>,
                  sl : SaverLoader,
+                 settings : ServerSettings,
              ) {
     println!("Server game loop");
     let mut subscription_manager = SubscriptionManager::new();
-    let mut server_data : ServerData = match sl.load_without_key() {
+    let mut movement_manager = MovementManager::new();
+    let mut interest_manager = InterestManager::new(settings.interest_radius_meters);
+    let mut replicator = ComponentReplicator::new();
+    let (mut server_data, fresh_server_data) : (ServerData, bool) = match sl.load_without_key() {
         Ok(x) => {
             println!("Successfully loaded server_data");
-            x
+            (x, false)
         },
         Err(_) => {
             println!("Failed to load server_data. Made fresh");
-            ServerData {
+            (ServerData {
                 next_eid : 0,
                 cid_to_controlling : HashMap::new(),
-            }
+                dirty : true,
+            }, true)
         }
     };
-    let mut sr = ServerResources::new(sl.clone(), Isaac64Rng::from_seed(&[3]));
+    let mut sr = ServerResources::new(sl.clone(), Isaac64Rng::from_seed(&[3]), settings.location_retention);
+    define_objects(&mut sr);
+    sr.register_catch_up(Box::new(NpcBehaviors));
+    sl.ensure_folder_exists(portals::CREATE_PATH);
+    let parked = sl.load_without_key::<ParkedEntities>().unwrap_or_else(|_| ParkedEntities::new());
+    let mut logout_manager = LogoutManager::new(settings.logout_policy, parked);
 
-    sr.define_object(0, ObjectData::new(0, 1.0));
+    let (mut journal, unsaved) = Journal::open(&sl, JOURNAL_NAME).expect("couldn't open journal!");
+    //nothing saved, nothing to replay: a new game
+    let new_game = fresh_server_data && unsaved.is_empty();
+    let mut last_tick = sl.load_without_key::<LastTick>().ok().map(|t| t.tick);
+    let replaying = ! unsaved.is_empty();
+    if replaying {
+        let replayed_tick = replay_journal(unsaved, &userbase, &mut server_data, &mut sr, &mut logout_manager);
+        last_tick = ::std::cmp::max(last_tick, replayed_tick);
+    }
 
-    let time_between_updates = time::Duration::from_millis(1000/game_state::UPDATES_PER_SEC);
+    let mut ticker = Ticker::new(game_state::UPDATES_PER_SEC, last_tick);
+    println!("Starting at tick {}", ticker.tick());
+    if replaying {
+        //last run ended before a full save. get that save done now
+        autosave(&sl, &userbase, &mut server_data, &mut sr, &mut logout_manager, &mut journal, ticker.tick());
+    }
+    if new_game {
+        //other locations get theirs when registered. the start location never is
+        let mut outgoing_updates = vec![];
+        populate_with_npcs(START_LOCATION_LID, ticker.tick(), &mut server_data, &mut sr, &subscription_manager,
+            &mut interest_manager, &mut journal, &mut outgoing_updates);
+        scatter_ground_items(START_LOCATION_LID, ticker.tick(), &server_data, &mut sr, &subscription_manager,
+            &mut interest_manager, &mut journal, &mut outgoing_updates);
+        serv_out.lock_pushall_notify(outgoing_updates.drain(..));
+    }
+    //nobody is logged in yet. their entities are treated like they just left
+    for (cid, &(eid, _)) in server_data.cid_to_controlling.iter() {
+        logout_manager.logged_out(*cid, ticker.tick());
+        if logout_manager.policy() == LogoutPolicy::Hide {
+            interest_manager.hide(eid);
+        }
+    }
 
-    // println!("TIME BETWEEN SYNCHFLOODS INFLATED FOR TESTING OK?", );
-    let time_between_syncfloods = time::Duration::from_millis(3000);
-    // let mut location_loader = LocationLoader::new(Duration::new(10,0), sl.clone());
     let mut last_syncflood_at = time::Instant::now();
+    let mut last_autosave_at = time::Instant::now();
+    let mut last_backup_at = time::Instant::now();
     loop {
         let update_start = time::Instant::now();
-        if last_syncflood_at.elapsed() > time_between_syncfloods {
+        if last_syncflood_at.elapsed() > settings.resync_interval {
             last_syncflood_at = update_start;
-            synchflood(&serv_out, &mut sr);
-
-            println!("SAVING FOR TESTING PURPOSES");
-            let u : &UserBase = &userbase.lock().unwrap();
-            sl.save_without_key(&server_data).expect("couldn't save server data!");
-            sl.save_without_key(u).expect("couldn't save user base!");
-            sr.save_all();
+            synchflood(&serv_out, &mut sr, &subscription_manager, &interest_manager, ticker.tick());
+        }
+        if last_autosave_at.elapsed() > settings.autosave_interval {
+            last_autosave_at = update_start;
+            //put files into this directory to create portals. checked as often as we save
+            let mut outgoing_updates = vec![];
+            for (entrance, exit, two_way) in portals::consume_portal_files(&sl.relative_path(portals::CREATE_PATH)) {
+                match create_portal(entrance, exit, two_way, ticker.tick(), &mut sr, &subscription_manager, &mut journal, &mut outgoing_updates) {
+                    Ok(()) => println!(":::Created portal {:?} -> {:?} (two way: {})", entrance, exit, two_way),
+                    Err(e) => println!(":::Failed to create portal {:?} -> {:?}: {}", entrance, exit, e),
+                }
+            }
+            serv_out.lock_pushall_notify(outgoing_updates.drain(..));
+            autosave(&sl, &userbase, &mut server_data, &mut sr, &mut logout_manager, &mut journal, ticker.tick());
+        }
+        if let Some(ref policy) = settings.backups {
+            if last_backup_at.elapsed() > policy.interval {
+                last_backup_at = update_start;
+                //disk must be up to date first. nothing else writes while this thread is busy
+                autosave(&sl, &userbase, &mut server_data, &mut sr, &mut logout_manager, &mut journal, ticker.tick());
+                match backups::snapshot(&sl, &policy.dir) {
+                    Ok(_) => {
+                        if let Err(e) = backups::prune(policy) {
+                            println!("Failed to prune backups: {}", e);
+                        }
+                    },
+                    Err(e) => println!("Failed to back up save dir: {}", e),
+                }
+            }
         }
 
         update_step(
             &serv_in,
             &serv_out,
-            // &mut location_loader,
             &userbase,
             &mut server_data,
             &mut sr,
             &mut subscription_manager,
+            &mut movement_manager,
+            &mut interest_manager,
+            &mut logout_manager,
+            &mut replicator,
+            &mut journal,
+            ticker.tick(),
         );
+        simulate_tick(
+            &serv_out,
+            &mut server_data,
+            &mut sr,
+            &mut subscription_manager,
+            &mut movement_manager,
+            &mut interest_manager,
+            &mut logout_manager,
+            &mut journal,
+            &ticker,
+        );
+        //whatever was accepted this tick must survive a crash, and so must the tick it was accepted in
+        sr.journal_caught_up(&mut journal);
+        if journal.has_pending() {
+            journal.append(&JournalEntry::Tick(ticker.tick()));
+        }
+        journal.flush().expect("couldn't flush journal!");
+        sr.refresh_residency(&subscription_manager);
+
+        ticker.wait_for_next();
+    }
+}
 
-        let since_update = update_start.elapsed();
-        if since_update < time_between_updates {
-            thread::sleep(time_between_updates - since_update);
+//the objects every location may hold. define before any location is loaded
+fn define_objects(sr : &mut ServerResources) {
+    sr.define_object(TREE_OID, ObjectData::new(0, 1.0));
+    sr.define_object(ROCK_OID, ObjectData::new(ROCK_AID, 0.8));
+    sr.define_object(portals::PORTAL_OID, ObjectData::new(portals::PORTAL_AID, 1.0));
+    for iid in items::item_ids() {
+        let def = items::item_def(iid).expect("you said..");
+        sr.define_object(items::item_oid(iid), items::item_object_data(def));
+    }
+}
+
+//writes only the state that changed since the last autosave
+fn autosave(sl : &SaverLoader,
+            userbase : &Arc<Mutex<UserBase>>,
+            server_data : &mut ServerData,
+            sr : &mut ServerResources,
+            logout_manager : &mut LogoutManager,
+            journal : &mut Journal<JournalEntry>,
+            tick : Tick,
+        ) {
+    let save_start = time::Instant::now();
+    let mut bytes_written = 0;
+    //later ticks are journaled, so the next run carries on after them
+    bytes_written += sl.save_without_key(&LastTick {tick : tick}).expect("couldn't save last tick!");
+    if server_data.dirty {
+        bytes_written += sl.save_without_key(server_data).expect("couldn't save server data!");
+        server_data.dirty = false;
+    }
+    if logout_manager.get_parked().is_dirty() {
+        bytes_written += sl.save_without_key(logout_manager.get_parked()).expect("couldn't save parked entities!");
+        logout_manager.get_mut_parked().mark_clean();
+    }
+    {
+        let mut u = userbase.lock().unwrap();
+        if u.is_dirty() {
+            bytes_written += sl.save_without_key(&*u).expect("couldn't save user base!");
+            u.mark_clean();
         }
     }
+    //saved along with the rest. the journal is only kept if something fails to save
+    sr.journal_caught_up(journal);
+    bytes_written += sr.save_dirty();
+    println!("Autosave wrote {} bytes in {:?}", bytes_written, save_start.elapsed());
+    if sr.is_dirty() {
+        println!("Some state failed to save. Keeping the journal");
+    } else if let Err(e) = journal.truncate() {
+        println!("Failed to truncate journal: {}", e);
+    } else {
+        //everything is on disk and nothing is left to replay. safe to evict
+        sr.unload_overdue_backgrounds();
+    }
 }
 
-fn synchflood(serv_out : &Arc<ProtectedQueue<MsgToClientSet>>, sr: &mut ServerResources) {
-    //TODO send entity updates to all
+/*
+Sends every subscriber a digest of the entities it should know of in its location.
+A client that finds its own digest differs has missed or misapplied a diff,
+and asks for a LocationSnapshot
+*/
+fn synchflood(serv_out : &Arc<ProtectedQueue<MsgToClientSet>>,
+              sr : &mut ServerResources,
+              subscription_manager : &SubscriptionManager,
+              interest_manager : &InterestManager,
+              tick : Tick,
+          ) {
+    let mut outgoing_updates = vec![];
+    for lid in subscription_manager.subscribed_lids() {
+        let loc = sr.get_location(lid);
+        for cid in subscription_manager.iter_subs_for(lid) {
+            outgoing_updates.push(
+                MsgToClientSet::Only (
+                    MsgToClient::LocationDigest(lid,interest_manager.digest_for(cid, lid, loc),tick),
+                    cid,
+                )
+            );
+        }
+    }
+    serv_out.lock_pushall_notify(outgoing_updates.drain(..));
+}
+
+// `cell` exists in `lid`, as far as bounds go
+fn cell_in_bounds(sr : &mut ServerResources, lid : LocationID, cell : DPoint2) -> bool {
+    if ! sr.location_exists(lid) {
+        return false;
+    }
+    let loc = sr.get_location(lid);
+    cell.x >= 0 && cell.y >= 0 && cell.x < loc.cells_wide() && cell.y < loc.cells_high()
+}
+
+/*
+Creates a portal from `entrance` to `exit`, and back again if `two_way`.
+Each entrance shows up as a PORTAL_OID object in its location.
+Fails (changing nothing, saying why) if a cell doesn't exist, or an entrance is taken or can't be stepped on
+*/
+fn create_portal(entrance : UniquePoint,
+                 exit : UniquePoint,
+                 two_way : bool,
+                 tick : Tick,
+                 sr : &mut ServerResources,
+                 subscription_manager : &SubscriptionManager,
+                 journal : &mut Journal<JournalEntry>,
+                 outgoing_updates : &mut Vec<MsgToClientSet>,
+             ) -> Result<(),String> {
+    let mut jumps = vec![(entrance, exit)];
+    if two_way {
+        jumps.push((exit, entrance));
+    }
+    for &(from, to) in jumps.iter() {
+        for p in [from, to].iter() {
+            if ! cell_in_bounds(sr, p.lid, p.cell()) {
+                return Err(format!("LID {:?} has no cell {:?}", p.lid, p.cell()));
+            }
+        }
+        if sr.get_portals().exit_of(from).is_some() {
+            return Err(format!("{:?} is a portal entrance already", from));
+        }
+        //an entrance nothing can step on would never take anyone anywhere
+        if ! sr.get_location(from.lid).point_is_free(from.cell()) {
+            return Err(format!("{:?} is blocked or occupied", from));
+        }
+    }
+    if two_way && entrance == exit {
+        return Err("a two-way portal can't lead to itself".to_owned());
+    }
+    for (from, to) in jumps {
+        sr.add_portal(from, to);
+        journal.append(&JournalEntry::Portal(from, to));
+        let diff = Diff::PlaceObject(portals::PORTAL_OID, from.cell());
+        if sr.apply_location_diff(from.lid, diff, journal).is_ok() {
+            outgoing_updates.push(
+                MsgToClientSet::Subset (
+                    MsgToClient::ApplyLocationDiff(from.lid,diff,tick),
+                    subscription_manager.get_subs_for(from.lid),
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+/*
+Creates a portal (see create_portal) in the save dir of a stopped server, saving it straight away.
+Refuses if the last run left a journal to replay: starting the server once sorts that out.
+No catch-up systems run, so the locations it loads keep their eviction times and catch up when
+the server next loads them
+*/
+pub fn create_portal_offline(sl : &SaverLoader,
+                             entrance : UniquePoint,
+                             exit : UniquePoint,
+                             two_way : bool,
+                         ) -> Result<(),String> {
+    let (mut journal, unsaved) = Journal::<JournalEntry>::open(sl, JOURNAL_NAME)
+        .map_err(|e| format!("couldn't open journal: {}", e))?;
+    if ! unsaved.is_empty() {
+        return Err(format!("the journal holds {} unsaved entries. Start and stop the server first", unsaved.len()));
+    }
+    let mut sr = ServerResources::new(sl.clone(), Isaac64Rng::from_seed(&[3]), time::Duration::from_secs(0));
+    define_objects(&mut sr);
+    //nobody is subscribed, so the updates have nowhere to go
+    let mut outgoing_updates = vec![];
+    let tick = sl.load_without_key::<LastTick>().map(|t| t.tick).unwrap_or(0);
+    create_portal(entrance, exit, two_way, tick, &mut sr, &SubscriptionManager::new(), &mut journal, &mut outgoing_updates)?;
+    sr.save_dirty();
+    if sr.is_dirty() {
+        journal.flush().map_err(|e| format!("some state failed to save, and so did the journal: {}", e))?;
+        return Err("some state failed to save. The journal keeps the portal for the next start".to_owned());
+    }
+    journal.truncate().map_err(|e| format!("couldn't truncate journal: {}", e))
+}
+
+/*
+Call when a zone's location was just registered. Journals the registration, gives the location
+its generated NPCs and items, and a generated portal to and from the start location if its seed says so
+*/
+fn zone_location_registered(lid : LocationID,
+                            tick : Tick,
+                            server_data : &mut ServerData,
+                            sr : &mut ServerResources,
+                            subscription_manager : &SubscriptionManager,
+                            interest_manager : &mut InterestManager,
+                            journal : &mut Journal<JournalEntry>,
+                            outgoing_updates : &mut Vec<MsgToClientSet>,
+                        ) {
+    let lp = *sr.get_location_primitive(lid);
+    println!("Registered LID {:?} for world {:?} zone {:?}", lid, lp.wid, lp.zone_id);
+    journal.append(&JournalEntry::ZoneLocation(lp.wid, lp.zone_id, lid));
+    if let Some((cell, start_cell)) = sr.generated_portal_for(lid) {
+        let entrance = UniquePoint::new(lid, cell);
+        let exit = UniquePoint::new(START_LOCATION_LID, start_cell);
+        match create_portal(entrance, exit, true, tick, sr, subscription_manager, journal, outgoing_updates) {
+            Ok(()) => println!("Generated portal {:?} <-> {:?}", entrance, exit),
+            Err(e) => println!("Couldn't generate a portal: {}", e),
+        }
+    }
+    populate_with_npcs(lid, tick, server_data, sr, subscription_manager, interest_manager, journal, outgoing_updates);
+    scatter_ground_items(lid, tick, server_data, sr, subscription_manager, interest_manager, journal, outgoing_updates);
+}
+
+//lays out the item piles generated for `lid`
+fn scatter_ground_items(lid : LocationID,
+                        tick : Tick,
+                        server_data : &ServerData,
+                        sr : &mut ServerResources,
+                        subscription_manager : &SubscriptionManager,
+                        interest_manager : &mut InterestManager,
+                        journal : &mut Journal<JournalEntry>,
+                        outgoing_updates : &mut Vec<MsgToClientSet>,
+                    ) {
+    for (pt, iid, count) in ground_items::generated_piles(sr.get_location(lid)) {
+        set_ground_pile(lid, pt, iid, count, tick, server_data, sr, subscription_manager, interest_manager,
+            journal, outgoing_updates);
+    }
+}
+
+//spawns the NPCs generated for `lid`
+fn populate_with_npcs(lid : LocationID,
+                      tick : Tick,
+                      server_data : &mut ServerData,
+                      sr : &mut ServerResources,
+                      subscription_manager : &SubscriptionManager,
+                      interest_manager : &mut InterestManager,
+                      journal : &mut Journal<JournalEntry>,
+                      outgoing_updates : &mut Vec<MsgToClientSet>,
+                  ) {
+    let npcs = npcs::generated_npcs(sr.get_location(lid));
+    let count = npcs.len();
+    for (pt, behavior) in npcs {
+        if spawn_npc(lid, pt, behavior, tick, server_data, sr, subscription_manager, interest_manager,
+            journal, outgoing_updates).is_err() {
+            println!("No room for an NPC in LID {:?}", lid);
+        }
+    }
+    if count > 0 {
+        println!("Spawned {} NPCs in LID {:?}", count, lid);
+    }
+}
+
+/*
+Puts a new NPC that behaves as `behavior` into `lid`, at the free cell nearest `near`.
+Fails (changing nothing) if the location is full
+*/
+fn spawn_npc(lid : LocationID,
+             near : DPoint2,
+             behavior : Behavior,
+             tick : Tick,
+             server_data : &mut ServerData,
+             sr : &mut ServerResources,
+             subscription_manager : &SubscriptionManager,
+             interest_manager : &mut InterestManager,
+             journal : &mut Journal<JournalEntry>,
+             outgoing_updates : &mut Vec<MsgToClientSet>,
+         ) -> Result<EntityID,()> {
+    let pt = sr.get_location(lid).free_point_near(near).ok_or(())?;
+    let eid = server_data.use_next_eid();
+    journal.append(&JournalEntry::NextEid(server_data.next_eid));
+    let components = EntityComponents::from_components(vec![
+        Component::Appearance(Appearance {aid : NPC_AID, width_meters : 0.6}),
+        Component::Name(npcs::name_for(&behavior).to_owned()),
+        Component::Health(Health {current : NPC_MAX_HEALTH, max : NPC_MAX_HEALTH}),
+        Component::Behavior(behavior),
+    ]);
+    journal.append(&JournalEntry::DefineComponents(eid, components.clone()));
+    sr.define_entity(eid, components);
+    let diff = Diff::PlaceInside(eid,pt);
+    sr.apply_location_diff(lid, diff, journal)
+    .expect("YOU SAID LOCATION WAS FREE");
+    interest_manager.broadcast(lid, diff, tick, sr.get_location(lid), subscription_manager,
+        &server_data.cid_to_controlling, outgoing_updates);
+    Ok(eid)
+}
+
+/*
+Moves a walking entity one cell along its path. If the path got blocked since it was computed,
+walks around. Stepping onto a portal or link end takes the entity through
+*/
+fn take_step(step : Step,
+             tick : Tick,
+             server_data : &mut ServerData,
+             sr : &mut ServerResources,
+             subscription_manager : &mut SubscriptionManager,
+             movement_manager : &mut MovementManager,
+             interest_manager : &mut InterestManager,
+             journal : &mut Journal<JournalEntry>,
+             outgoing_updates : &mut Vec<MsgToClientSet>,
+         ) {
+    let Step {eid, cid, seq, lid, goal, ..} = step;
+    if Some(&(eid,lid)) != server_data.cid_to_controlling.get(&cid) {
+        //left the location or changed hands since the order
+        movement_manager.cancel(eid);
+        return;
+    }
+    let mut to = step.to;
+    if ! sr.get_location(lid).point_is_free(to) {
+        match sr.get_location(lid).path_for(eid, goal) {
+            Some(path) => {
+                movement_manager.order(eid, cid, seq, lid, goal, path);
+                match movement_manager.due_now(eid) {
+                    Some(next) => to = next,
+                    None => return,
+                }
+            },
+            None => {
+                movement_manager.cancel(eid);
+                outgoing_updates.push(
+                    MsgToClientSet::Only(
+                        MsgToClient::ControlMoveRejected(lid,eid,goal,seq),
+                        cid,
+                    )
+                );
+                return;
+            },
+        }
+    }
+    let diff = Diff::MoveEntityTo(eid,to);
+    if sr.apply_location_diff(lid, diff, journal).is_err() {
+        movement_manager.cancel(eid);
+        return;
+    }
+    interest_manager.broadcast(lid, diff, tick, sr.get_location(lid), subscription_manager,
+        &server_data.cid_to_controlling, outgoing_updates);
+    if let Some(exit) = sr.get_portals().exit_of(UniquePoint::new(lid, to)) {
+        println!("Entity {:?} takes the portal from LID {:?} to {:?}", eid, lid, exit.lid);
+        movement_manager.cancel(eid);
+        if transfer_controlled(cid, eid, lid, exit.lid, exit.cell(), tick, server_data, sr,
+            subscription_manager, interest_manager, journal, outgoing_updates).is_err() {
+            println!("No room at the other end of the portal");
+        }
+    } else if let Some((to_lid, to_pt, is_new)) = sr.link_destination(lid, to) {
+        if is_new {
+            zone_location_registered(to_lid, tick, server_data, sr, subscription_manager, interest_manager,
+                journal, outgoing_updates);
+        }
+        println!("Entity {:?} takes the link from LID {:?} to {:?}", eid, lid, to_lid);
+        movement_manager.cancel(eid);
+        if transfer_controlled(cid, eid, lid, to_lid, to_pt, tick, server_data, sr,
+            subscription_manager, interest_manager, journal, outgoing_updates).is_err() {
+            println!("No room at the other end of the link");
+        }
+    }
+}
+
+/*
+Moves the entity `cid` controls from one location to another, to the free cell nearest `arrive_at`.
+Everyone watching either location is told, and the client's subscription follows its entity.
+Fails (changing nothing) if the destination is full
+*/
+fn transfer_controlled(cid : ClientID,
+                       eid : EntityID,
+                       from_lid : LocationID,
+                       to_lid : LocationID,
+                       arrive_at : DPoint2,
+                       tick : Tick,
+                       server_data : &mut ServerData,
+                       sr : &mut ServerResources,
+                       subscription_manager : &mut SubscriptionManager,
+                       interest_manager : &mut InterestManager,
+                       journal : &mut Journal<JournalEntry>,
+                       outgoing_updates : &mut Vec<MsgToClientSet>,
+                   ) -> Result<(),()> {
+    let pt = match sr.get_location(to_lid).free_point_near(arrive_at) {
+        Some(pt) => pt,
+        None => return Err(()),
+    };
+    let leave = Diff::RemoveEntity(eid);
+    sr.apply_location_diff(from_lid, leave, journal)?;
+    interest_manager.broadcast(from_lid, leave, tick, sr.get_location(from_lid), subscription_manager,
+        &server_data.cid_to_controlling, outgoing_updates);
+    let arrive = Diff::PlaceInside(eid,pt);
+    sr.apply_location_diff(to_lid, arrive, journal)
+    .expect("YOU SAID LOCATION WAS FREE");
+    server_data.set_controlling(cid, eid, to_lid);
+    journal.append(&JournalEntry::Controlling(cid, eid, to_lid));
+    interest_manager.broadcast(to_lid, arrive, tick, sr.get_location(to_lid), subscription_manager,
+        &server_data.cid_to_controlling, outgoing_updates);
+    subscription_manager.unsubscribe(from_lid, cid);
+    interest_manager.forget_client(cid, from_lid);
+    subscription_manager.subscribe(to_lid, cid);
+    //client answers with RequestLocationData(to_lid) for the full picture
+    outgoing_updates.push(
+        MsgToClientSet::Only(
+            MsgToClient::GiveControlling(eid,to_lid,tick),
+            cid,
+        )
+    );
+    Ok(())
+}
+
+/*
+Applies `diff` to the components of `eid`, journals it, and sends it to the clients that may see it.
+Err (changing nothing) if the diff doesn't fit the entity
+*/
+fn change_component(eid : EntityID,
+                    diff : ComponentDiff,
+                    tick : Tick,
+                    server_data : &ServerData,
+                    sr : &mut ServerResources,
+                    replicator : &ComponentReplicator,
+                    journal : &mut Journal<JournalEntry>,
+                    outgoing_updates : &mut Vec<MsgToClientSet>,
+                ) -> Result<(),()> {
+    sr.apply_component_diff(eid, diff.clone())?;
+    journal.append(&JournalEntry::ComponentDiff(eid, diff.clone()));
+    replicator.broadcast(eid, diff, tick, server_data.controller_of(eid), outgoing_updates);
+    Ok(())
+}
+
+//an entity reaches its own cell and the eight around it
+fn within_reach(a : DPoint2, b : DPoint2) -> bool {
+    (a.x - b.x).abs() <= 1 && (a.y - b.y).abs() <= 1
+}
+
+//where the entity `cid` controls stands, if that entity is `eid` and in `lid`
+fn controlled_point(cid : ClientID, eid : EntityID, lid : LocationID, server_data : &ServerData,
+                    sr : &mut ServerResources) -> Option<DPoint2> {
+    if Some(&(eid,lid)) != server_data.cid_to_controlling.get(&cid) {
+        return None;
+    }
+    sr.get_location(lid).point_of(eid)
+}
+
+/*
+Sets how many of `iid` lie at `pt` of `lid`, journals it, and places or removes the pile's object
+if it appeared or went away
+*/
+fn set_ground_pile(lid : LocationID,
+                   pt : DPoint2,
+                   iid : ItemID,
+                   count : u32,
+                   tick : Tick,
+                   server_data : &ServerData,
+                   sr : &mut ServerResources,
+                   subscription_manager : &SubscriptionManager,
+                   interest_manager : &mut InterestManager,
+                   journal : &mut Journal<JournalEntry>,
+                   outgoing_updates : &mut Vec<MsgToClientSet>,
+               ) {
+    let before = sr.get_ground_items().count(lid, pt, iid);
+    sr.set_ground_pile(lid, pt, iid, count);
+    journal.append(&JournalEntry::GroundPile(lid, pt, iid, count));
+    let diff = match (before, count) {
+        (0, 0) => return,
+        (0, _) => Diff::PlaceObject(items::item_oid(iid), pt),
+        (_, 0) => Diff::RemoveObject(items::item_oid(iid), pt),
+        _ => return,
+    };
+    if sr.apply_location_diff(lid, diff, journal).is_ok() {
+        interest_manager.broadcast(lid, diff, tick, sr.get_location(lid), subscription_manager,
+            &server_data.cid_to_controlling, outgoing_updates);
+    }
+}
+
+//replaces the inventory of `eid`. it stops holding items it no longer has
+fn set_inventory(eid : EntityID,
+                 inventory : Inventory,
+                 tick : Tick,
+                 server_data : &ServerData,
+                 sr : &mut ServerResources,
+                 replicator : &ComponentReplicator,
+                 journal : &mut Journal<JournalEntry>,
+                 outgoing_updates : &mut Vec<MsgToClientSet>,
+             ) -> Result<(),()> {
+    let dropped_equipped = match sr.get_entity(eid).equipped() {
+        Some(iid) => inventory.count_of(iid) == 0,
+        None => false,
+    };
+    change_component(eid, ComponentDiff::Set(Component::Inventory(inventory)), tick, server_data, sr,
+        replicator, journal, outgoing_updates)?;
+    if dropped_equipped {
+        change_component(eid, ComponentDiff::Remove(ComponentKind::Equipped), tick, server_data, sr,
+            replicator, journal, outgoing_updates)?;
+    }
+    Ok(())
+}
+
+/*
+The entity `cid` controls takes what it can carry of the pile of `iid` at `pt`.
+Fails (changing nothing) if it can't reach the pile, there is none, or it has no room for any of it
+*/
+fn pick_up(cid : ClientID,
+           lid : LocationID,
+           eid : EntityID,
+           pt : DPoint2,
+           iid : ItemID,
+           tick : Tick,
+           server_data : &ServerData,
+           sr : &mut ServerResources,
+           subscription_manager : &SubscriptionManager,
+           interest_manager : &mut InterestManager,
+           replicator : &ComponentReplicator,
+           journal : &mut Journal<JournalEntry>,
+           outgoing_updates : &mut Vec<MsgToClientSet>,
+       ) -> Result<(),()> {
+    let at = controlled_point(cid, eid, lid, server_data, sr).ok_or(())?;
+    if ! within_reach(at, pt) {
+        return Err(());
+    }
+    let on_ground = sr.get_ground_items().count(lid, pt, iid);
+    //entities from before inventories start with an empty one
+    let mut inventory = sr.get_entity(eid).inventory().cloned().unwrap_or_else(Inventory::new);
+    let taken = inventory.add(iid, on_ground);
+    if taken == 0 {
+        return Err(());
+    }
+    set_inventory(eid, inventory, tick, server_data, sr, replicator, journal, outgoing_updates)?;
+    set_ground_pile(lid, pt, iid, on_ground - taken, tick, server_data, sr, subscription_manager,
+        interest_manager, journal, outgoing_updates);
+    if let Some(def) = items::item_def(iid) {
+        println!("Entity {:?} picked up {} x{}", eid, def.name, taken);
+    }
+    Ok(())
+}
+
+/*
+The entity `cid` controls drops up to `count` of the stack in `slot` onto its own cell.
+Fails (changing nothing) if the slot is empty
+*/
+fn drop_items(cid : ClientID,
+              lid : LocationID,
+              eid : EntityID,
+              slot : usize,
+              count : u32,
+              tick : Tick,
+              server_data : &ServerData,
+              sr : &mut ServerResources,
+              subscription_manager : &SubscriptionManager,
+              interest_manager : &mut InterestManager,
+              replicator : &ComponentReplicator,
+              journal : &mut Journal<JournalEntry>,
+              outgoing_updates : &mut Vec<MsgToClientSet>,
+          ) -> Result<(),()> {
+    let at = controlled_point(cid, eid, lid, server_data, sr).ok_or(())?;
+    let mut inventory = sr.get_entity(eid).inventory().cloned().ok_or(())?;
+    let stack = inventory.take(slot, count).ok_or(())?;
+    set_inventory(eid, inventory, tick, server_data, sr, replicator, journal, outgoing_updates)?;
+    let on_ground = sr.get_ground_items().count(lid, at, stack.iid);
+    set_ground_pile(lid, at, stack.iid, on_ground + stack.count, tick, server_data, sr, subscription_manager,
+        interest_manager, journal, outgoing_updates);
+    Ok(())
+}
+
+/*
+The entity `cid` controls hands up to `count` of the stack in `slot` to `to`, as much as `to` has room for.
+Fails (changing nothing) if `to` isn't in reach, has no inventory or no room, or the slot is empty
+*/
+fn give_items(cid : ClientID,
+              lid : LocationID,
+              eid : EntityID,
+              slot : usize,
+              count : u32,
+              to : EntityID,
+              tick : Tick,
+              server_data : &ServerData,
+              sr : &mut ServerResources,
+              replicator : &ComponentReplicator,
+              journal : &mut Journal<JournalEntry>,
+              outgoing_updates : &mut Vec<MsgToClientSet>,
+          ) -> Result<(),()> {
+    let at = controlled_point(cid, eid, lid, server_data, sr).ok_or(())?;
+    let to_at = sr.get_location(lid).point_of(to).ok_or(())?;
+    if to == eid || ! within_reach(at, to_at) || ! sr.entity_exists(to) {
+        return Err(());
+    }
+    let mut giving = sr.get_entity(eid).inventory().cloned().ok_or(())?;
+    let mut receiving = sr.get_entity(to).inventory().cloned().ok_or(())?;
+    let stack = giving.slot(slot).ok_or(())?;
+    let moved = receiving.add(stack.iid, ::std::cmp::min(count, stack.count));
+    if moved == 0 {
+        return Err(());
+    }
+    giving.take(slot, moved);
+    set_inventory(eid, giving, tick, server_data, sr, replicator, journal, outgoing_updates)?;
+    set_inventory(to, receiving, tick, server_data, sr, replicator, journal, outgoing_updates)
+}
+
+/*
+The entity `cid` controls holds the item in `slot`, or nothing.
+Fails (changing nothing) if that item can't be held, or it already holds nothing
+*/
+fn equip(cid : ClientID,
+         eid : EntityID,
+         slot : Option<usize>,
+         tick : Tick,
+         server_data : &ServerData,
+         sr : &mut ServerResources,
+         replicator : &ComponentReplicator,
+         journal : &mut Journal<JournalEntry>,
+         outgoing_updates : &mut Vec<MsgToClientSet>,
+     ) -> Result<(),()> {
+    match server_data.cid_to_controlling.get(&cid) {
+        Some(&(c_eid, _)) if c_eid == eid => (),
+        _ => return Err(()),
+    }
+    let diff = match slot {
+        Some(slot) => {
+            let stack = sr.get_entity(eid).inventory().and_then(|inv| inv.slot(slot)).ok_or(())?;
+            if ! items::item_def(stack.iid).map_or(false, |def| def.equippable) {
+                return Err(());
+            }
+            ComponentDiff::Set(Component::Equipped(stack.iid))
+        },
+        None => ComponentDiff::Remove(ComponentKind::Equipped),
+    };
+    change_component(eid, diff, tick, server_data, sr, replicator, journal, outgoing_updates)
+}
+
+/*
+Call when `cid` leaves. It stops hearing about its location and its entities' components,
+its entity stops walking, and the logout policy decides what becomes of the entity
+*/
+fn client_logged_out(cid : ClientID,
+                     tick : Tick,
+                     server_data : &ServerData,
+                     sr : &mut ServerResources,
+                     subscription_manager : &mut SubscriptionManager,
+                     movement_manager : &mut MovementManager,
+                     interest_manager : &mut InterestManager,
+                     logout_manager : &mut LogoutManager,
+                     replicator : &mut ComponentReplicator,
+                     outgoing_updates : &mut Vec<MsgToClientSet>,
+                 ) {
+    replicator.forget_client(cid);
+    if let Some(&(eid,lid)) = server_data.cid_to_controlling.get(&cid) {
+        subscription_manager.unsubscribe(lid, cid);
+        interest_manager.forget_client(cid, lid);
+        movement_manager.cancel(eid);
+        logout_manager.logged_out(cid, tick);
+        if logout_manager.policy() == LogoutPolicy::Hide {
+            interest_manager.hide(eid);
+            interest_manager.refresh_subscribers(lid, sr.get_location(lid), tick, subscription_manager,
+                &server_data.cid_to_controlling, outgoing_updates);
+        }
+    }
+}
+
+//call when `cid` is back, before it is told what it controls. undoes whatever its logout did
+fn client_logged_in(cid : ClientID,
+                    tick : Tick,
+                    server_data : &ServerData,
+                    sr : &mut ServerResources,
+                    subscription_manager : &SubscriptionManager,
+                    interest_manager : &mut InterestManager,
+                    logout_manager : &mut LogoutManager,
+                    journal : &mut Journal<JournalEntry>,
+                    outgoing_updates : &mut Vec<MsgToClientSet>,
+                ) {
+    let (eid, lid) = match server_data.cid_to_controlling.get(&cid) {
+        Some(&x) => x,
+        None => return,
+    };
+    if let Some(spot) = logout_manager.logged_in(cid) {
+        journal.append(&JournalEntry::Parked(cid, None));
+        //someone may have taken its spot since
+        match sr.get_location(lid).free_point_near(spot) {
+            Some(pt) => {
+                let diff = Diff::PlaceInside(eid,pt);
+                sr.apply_location_diff(lid, diff, journal)
+                .expect("YOU SAID LOCATION WAS FREE");
+                interest_manager.broadcast(lid, diff, tick, sr.get_location(lid), subscription_manager,
+                    &server_data.cid_to_controlling, outgoing_updates);
+            },
+            None => println!("No room to bring back entity {:?} of client {:?} in LID {:?}", eid, cid, lid),
+        }
+    }
+    interest_manager.unhide(eid);
+    interest_manager.refresh_subscribers(lid, sr.get_location(lid), tick, subscription_manager,
+        &server_data.cid_to_controlling, outgoing_updates);
+}
+
+//takes the entity of logged out `cid` out of its location, remembering where it stood
+fn despawn_controlled(cid : ClientID,
+                      tick : Tick,
+                      server_data : &ServerData,
+                      sr : &mut ServerResources,
+                      subscription_manager : &SubscriptionManager,
+                      interest_manager : &mut InterestManager,
+                      logout_manager : &mut LogoutManager,
+                      journal : &mut Journal<JournalEntry>,
+                      outgoing_updates : &mut Vec<MsgToClientSet>,
+                  ) {
+    let (eid, lid) = match server_data.cid_to_controlling.get(&cid) {
+        Some(&x) => x,
+        None => return,
+    };
+    let pt = match sr.get_location(lid).point_of(eid) {
+        Some(pt) => pt,
+        //despawned already
+        None => return,
+    };
+    let diff = Diff::RemoveEntity(eid);
+    if sr.apply_location_diff(lid, diff, journal).is_err() {
+        return;
+    }
+    println!("Despawned entity {:?} of logged out client {:?}", eid, cid);
+    logout_manager.park(cid, pt);
+    journal.append(&JournalEntry::Parked(cid, Some(pt)));
+    interest_manager.broadcast(lid, diff, tick, sr.get_location(lid), subscription_manager,
+        &server_data.cid_to_controlling, outgoing_updates);
 }
 
 fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
@@ -111,6 +1001,12 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
                server_data : &mut ServerData,
                sr : &mut ServerResources,
                subscription_manager: &mut SubscriptionManager,
+               movement_manager : &mut MovementManager,
+               interest_manager : &mut InterestManager,
+               logout_manager : &mut LogoutManager,
+               replicator : &mut ComponentReplicator,
+               journal : &mut Journal<JournalEntry>,
+               tick : Tick,
            ) {
     //comment
     let mut outgoing_updates : Vec<MsgToClientSet> = vec![];
@@ -119,23 +1015,26 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
     if let Some(drained) = serv_in.impatient_drain() {
         for d in drained {
             match d.msg {
-                MsgToServer::ControlMoveTo(lid,eid,pt) => {
-                    if Some(&(eid,lid)) == server_data.cid_to_controlling.get(&d.cid) {
-                        println!("Ok you may move that!");
-                        let diff = Diff::MoveEntityTo(eid,pt);
-                        if sr.get_mut_location(lid).apply_diff(diff).is_ok() {
-                            outgoing_updates.push(
-                                MsgToClientSet::Subset (
-                                    MsgToClient::ApplyLocationDiff(lid,diff),
-                                    subscription_manager.get_subs_for(lid),
-                                )
-                            );
-                        } else {
-                            println!("CLIENT MOVE INHIBITED");
+                MsgToServer::ControlMoveTo(lid,eid,pt,seq) => {
+                    let reply = if Some(&(eid,lid)) == server_data.cid_to_controlling.get(&d.cid) {
+                        match sr.get_location(lid).path_for(eid, pt) {
+                            Some(path) => {
+                                println!("Ok you may move that! {} steps", path.len());
+                                movement_manager.order(eid, d.cid, seq, lid, pt, path);
+                                MsgToClient::ControlMoveAccepted(lid,eid,seq,tick)
+                            },
+                            None => {
+                                println!("CLIENT MOVE UNREACHABLE");
+                                movement_manager.cancel(eid);
+                                MsgToClient::ControlMoveRejected(lid,eid,pt,seq)
+                            },
                         }
                     } else {
                         println!("You don't have permission to ctrl move that!");
-                    }
+                        //the client may have moved it already, expecting a yes
+                        MsgToClient::ControlMoveRejected(lid,eid,pt,seq)
+                    };
+                    outgoing_updates.push(MsgToClientSet::Only(reply, d.cid));
                 },
                 MsgToServer::RequestObjectData(oid) => {
                     outgoing_updates.push(
@@ -145,10 +1044,15 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
                         )
                     );
                 },
-                MsgToServer::RequestEntityData(eid) => {
+                MsgToServer::RequestEntityComponents(eid) => {
+                    if ! sr.entity_exists(eid) {
+                        println!("Client {:?} asked for unknown EID {:?}", d.cid, eid);
+                        continue;
+                    }
+                    let owner = server_data.controller_of(eid);
                     outgoing_updates.push(
                         MsgToClientSet::Only(
-                            MsgToClient::GiveEntityData(eid, *sr.get_entity(eid)),
+                            replicator.snapshot_for(d.cid, eid, sr.get_entity(eid), owner),
                             d.cid,
                         )
                     );
@@ -156,11 +1060,22 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
                 MsgToServer::ClientHasDisconnected => {
                     println!("Client {:?} has disconnected!", &d.cid);
                     user_base.lock().unwrap().logout(d.cid);
-                    if let Some(&(_,old_lid)) = server_data.cid_to_controlling.get(&d.cid) {
-                        subscription_manager.unsubscribe(old_lid, d.cid);
-                    }
+                    client_logged_out(d.cid, tick, server_data, sr, subscription_manager, movement_manager,
+                        interest_manager, logout_manager, replicator, &mut outgoing_updates);
+                },
+                MsgToServer::RequestUniverse => {
+                    outgoing_updates.push(
+                        MsgToClientSet::Only(
+                            MsgToClient::GiveUniversePrimitive(*sr.get_universe().get_universe_primitive()),
+                            d.cid,
+                        )
+                    );
                 },
                 MsgToServer::RequestWorldData(wid) => {
+                    if ! sr.world_exists(wid) {
+                        println!("Client {:?} asked for unknown WID {:?}", d.cid, wid);
+                        continue;
+                    }
                     outgoing_updates.push(
                         MsgToClientSet::Only(
                             MsgToClient::GiveWorldPrimitive(
@@ -172,13 +1087,33 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
                     );
 
                 },
+                MsgToServer::RequestZoneLocation(wid, zone_id) => {
+                    match sr.location_for_zone(wid, zone_id) {
+                        Some((lid, is_new)) => {
+                            if is_new {
+                                zone_location_registered(lid, tick, server_data, sr, subscription_manager, interest_manager,
+                                    journal, &mut outgoing_updates);
+                            }
+                            outgoing_updates.push(
+                                MsgToClientSet::Only(
+                                    MsgToClient::GiveZoneLocation(wid, zone_id, lid),
+                                    d.cid,
+                                )
+                            );
+                        },
+                        None => println!("Client {:?} asked for world {:?} zone {:?}, which doesn't exist", d.cid, wid, zone_id),
+                    }
+                },
                 MsgToServer::RequestLocationData(lid) => {
+                    if ! sr.location_exists(lid) {
+                        println!("Client {:?} asked for unknown LID {:?}", d.cid, lid);
+                        continue;
+                    }
                     subscription_manager.subscribe(lid, d.cid);
-                    // sr.borrow_mut_location_loader().client_subscribe(d.cid, lid);
                     if let Some(&(_,old_lid)) = server_data.cid_to_controlling.get(&d.cid) {
                         if lid != old_lid {
-                            subscription_manager.unsubscribe(lid, d.cid);
-                            // sr.borrow_mut_location_loader().client_unsubscribe(d.cid, old_lid);
+                            subscription_manager.unsubscribe(old_lid, d.cid);
+                            interest_manager.forget_client(d.cid, old_lid);
                         }
                     }
                     outgoing_updates.push(
@@ -190,16 +1125,32 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
                             d.cid,
                         )
                     );
-                    for (eid, pt) in sr.get_location(lid).entity_iterator() {
-                        println!(">> informing client{:?} of eid {:?} {:?}", &d.cid, eid, pt);
+                    //portals and items aren't part of the generated location. the client needs them spelled out
+                    let mut placed : Vec<(ObjectID,DPoint2)> = sr.get_portals().entrances_in(lid).into_iter()
+                    .map(|cell| (portals::PORTAL_OID, cell))
+                    .collect();
+                    placed.extend(sr.get_ground_items().objects_in(lid));
+                    for (oid, cell) in placed {
                         outgoing_updates.push(
                             MsgToClientSet::Only(
-                                MsgToClient::ApplyLocationDiff(lid,Diff::PlaceInside(*eid,*pt)),
-                                // MsgToClient::GiveEntityData(*eid,lid,*pt),
+                                MsgToClient::ApplyLocationDiff(lid,Diff::PlaceObject(oid,cell),tick),
                                 d.cid,
                             )
                         );
                     }
+                    interest_manager.initial_view(d.cid, lid, sr.get_location(lid), tick,
+                        &server_data.cid_to_controlling, &mut outgoing_updates);
+                },
+                MsgToServer::RequestLocationSnapshot(lid) => {
+                    if ! subscription_manager.get_subs_for(lid).get(d.cid) {
+                        println!("Client {:?} asked for a snapshot of LID {:?} without subscribing", d.cid, lid);
+                        continue;
+                    }
+                    let snapshot = interest_manager.snapshot_for(d.cid, lid, sr.get_location(lid));
+                    outgoing_updates.extend(
+                        location_snapshot(lid, snapshot, tick).into_iter()
+                        .map(|msg| MsgToClientSet::Only(msg, d.cid))
+                    );
                 },
                 MsgToServer::RequestControlling => {
                     if server_data.cid_to_controlling.get(&d.cid) == None {
@@ -208,28 +1159,37 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
                         if ! locked_ub.client_is_setup(d.cid) {
                             println!("CLIENT {:?} having first-time setup", d.cid);
                             let player_eid = server_data.use_next_eid();
-                            sr.define_entity(player_eid, EntityData::new(1, 0.7));
+                            journal.append(&JournalEntry::NextEid(server_data.next_eid));
+                            let name = locked_ub.username_of(d.cid).unwrap_or_else(|| format!("Player {}", d.cid));
+                            let player_components = EntityComponents::from_components(vec![
+                                Component::Appearance(Appearance {aid : PLAYER_AID, width_meters : 0.7}),
+                                Component::Name(name),
+                                Component::Health(Health {current : PLAYER_MAX_HEALTH, max : PLAYER_MAX_HEALTH}),
+                                Component::Inventory(Inventory::new()),
+                            ]);
+                            journal.append(&JournalEntry::DefineComponents(player_eid, player_components.clone()));
+                            sr.define_entity(player_eid, player_components);
                             locked_ub.set_client_setup_true(d.cid);
-                            server_data.cid_to_controlling.insert(d.cid, (player_eid,START_LOCATION_LID));
+                            journal.append(&JournalEntry::ClientSetup(d.cid));
+                            server_data.set_controlling(d.cid, player_eid, START_LOCATION_LID);
+                            journal.append(&JournalEntry::Controlling(d.cid, player_eid, START_LOCATION_LID));
                             let free_pt : DPoint2 =
                                 sr.get_location(START_LOCATION_LID)
                                 .free_point()
                                 .expect("Oh no! start loc is full. cant spawn");
                             let mk_diff = Diff::PlaceInside(player_eid,free_pt);
-                            sr.get_mut_location(START_LOCATION_LID).apply_diff(mk_diff)
+                            sr.apply_location_diff(START_LOCATION_LID, mk_diff, journal)
                             .expect("YOU SAID LOCATION WAS FREE");
-                            outgoing_updates.push(
-                                MsgToClientSet::Subset (
-                                    MsgToClient::ApplyLocationDiff(START_LOCATION_LID,mk_diff),
-                                    subscription_manager.get_subs_for(START_LOCATION_LID),
-                                )
-                            );
+                            interest_manager.broadcast(START_LOCATION_LID, mk_diff, tick, sr.get_location(START_LOCATION_LID),
+                                subscription_manager, &server_data.cid_to_controlling, &mut outgoing_updates);
                         }
                     }
+                    client_logged_in(d.cid, tick, server_data, sr, subscription_manager, interest_manager,
+                        logout_manager, journal, &mut outgoing_updates);
                     if let Some(&(eid,lid)) = server_data.cid_to_controlling.get(&d.cid) {
                         outgoing_updates.push(
                             MsgToClientSet::Only(
-                                MsgToClient::GiveControlling(eid,lid),
+                                MsgToClient::GiveControlling(eid,lid,tick),
                                 d.cid,
                             )
                         );
@@ -237,6 +1197,29 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
                         panic!("WTFFFF");
                     }
                 },
+                MsgToServer::PickUp(lid,eid,pt,iid) => {
+                    if pick_up(d.cid, lid, eid, pt, iid, tick, server_data, sr, subscription_manager, interest_manager,
+                        replicator, journal, &mut outgoing_updates).is_err() {
+                        println!("Client {:?} can't pick up item {:?} at {:?}", d.cid, iid, pt);
+                    }
+                },
+                MsgToServer::DropItems(lid,eid,slot,count) => {
+                    if drop_items(d.cid, lid, eid, slot, count, tick, server_data, sr, subscription_manager,
+                        interest_manager, replicator, journal, &mut outgoing_updates).is_err() {
+                        println!("Client {:?} can't drop from slot {}", d.cid, slot);
+                    }
+                },
+                MsgToServer::GiveItems(lid,eid,slot,count,to) => {
+                    if give_items(d.cid, lid, eid, slot, count, to, tick, server_data, sr, replicator, journal,
+                        &mut outgoing_updates).is_err() {
+                        println!("Client {:?} can't give from slot {} to {:?}", d.cid, slot, to);
+                    }
+                },
+                MsgToServer::Equip(eid,slot) => {
+                    if equip(d.cid, eid, slot, tick, server_data, sr, replicator, journal, &mut outgoing_updates).is_err() {
+                        println!("Client {:?} can't equip slot {:?}", d.cid, slot);
+                    }
+                },
                 x => {
                     println!("SERVER CAN'T HANDLE {:?}", &x);
                     unimplemented!();
@@ -245,8 +1228,33 @@ fn update_step(serv_in : &Arc<ProtectedQueue<MsgFromClient>>,
         }
     }
 
-    //TODO game tick here
-
     // push all resultant game updates to clients
     serv_out.lock_pushall_notify(outgoing_updates.drain(..));
 }
+
+//advances the game by one tick: walks, despawns, then every system registered with `sr`
+fn simulate_tick(serv_out : &Arc<ProtectedQueue<MsgToClientSet>>,
+                 server_data : &mut ServerData,
+                 sr : &mut ServerResources,
+                 subscription_manager: &mut SubscriptionManager,
+                 movement_manager : &mut MovementManager,
+                 interest_manager : &mut InterestManager,
+                 logout_manager : &mut LogoutManager,
+                 journal : &mut Journal<JournalEntry>,
+                 ticker : &Ticker,
+             ) {
+    let tick = ticker.tick();
+    let mut outgoing_updates : Vec<MsgToClientSet> = vec![];
+    for step in movement_manager.due_steps(tick) {
+        take_step(step, tick, server_data, sr, subscription_manager, movement_manager, interest_manager, journal, &mut outgoing_updates);
+    }
+    for cid in logout_manager.due_despawns(tick) {
+        despawn_controlled(cid, tick, server_data, sr, subscription_manager, interest_manager,
+            logout_manager, journal, &mut outgoing_updates);
+    }
+    for (lid, diff) in sr.simulate_tick(ticker.period(), journal) {
+        interest_manager.broadcast(lid, diff, tick, sr.get_location(lid), subscription_manager,
+            &server_data.cid_to_controlling, &mut outgoing_updates);
+    }
+    serv_out.lock_pushall_notify(outgoing_updates.drain(..));
+}
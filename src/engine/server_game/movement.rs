@@ -0,0 +1,100 @@
+use std::collections::{HashMap,VecDeque};
+use ::identity::*;
+use ::points::DPoint2;
+use ::engine::game_state::TICKS_PER_MOVE_STEP;
+
+#[derive(Debug)]
+struct Walk {
+    cid : ClientID,
+    seq : MoveSeq,
+    lid : LocationID,
+    goal : DPoint2,
+    path : VecDeque<DPoint2>,
+}
+
+//one cell of a walk, due to be taken now
+#[derive(Debug,Copy,Clone)]
+pub struct Step {
+    pub eid : EntityID,
+    pub cid : ClientID,
+    pub seq : MoveSeq,
+    pub lid : LocationID,
+    pub to : DPoint2,
+    pub goal : DPoint2,
+}
+
+/*
+Entities walking somewhere at a client's order. Paths are computed when the order comes in;
+the server then takes one cell of every walk every TICKS_PER_MOVE_STEP ticks.
+*/
+#[derive(Debug)]
+pub struct MovementManager {
+    walks : HashMap<EntityID,Walk>,
+}
+
+impl MovementManager {
+    pub fn new() -> MovementManager {
+        MovementManager {
+            walks : HashMap::new(),
+        }
+    }
+
+    // replaces whatever walk `eid` was on
+    pub fn order(&mut self, eid : EntityID, cid : ClientID, seq : MoveSeq, lid : LocationID, goal : DPoint2, path : Vec<DPoint2>) {
+        if path.is_empty() {
+            self.cancel(eid);
+            return;
+        }
+        self.walks.insert(eid, Walk {
+            cid : cid,
+            seq : seq,
+            lid : lid,
+            goal : goal,
+            path : path.into_iter().collect(),
+        });
+    }
+
+    pub fn cancel(&mut self, eid : EntityID) {
+        self.walks.remove(&eid);
+    }
+
+    // takes the next cell of `eid`'s walk right away. for when a step had to be re-planned
+    pub fn due_now(&mut self, eid : EntityID) -> Option<DPoint2> {
+        let (next, finished) = match self.walks.get_mut(&eid) {
+            Some(walk) => (walk.path.pop_front(), walk.path.is_empty()),
+            None => return None,
+        };
+        if finished {
+            self.walks.remove(&eid);
+        }
+        next
+    }
+
+    // the next cell of every walk, if `tick` is a movement tick. finished walks are dropped
+    pub fn due_steps(&mut self, tick : Tick) -> Vec<Step> {
+        if tick % TICKS_PER_MOVE_STEP != 0 {
+            return vec![];
+        }
+        let mut steps = vec![];
+        let mut finished = vec![];
+        for (eid, walk) in self.walks.iter_mut() {
+            if let Some(to) = walk.path.pop_front() {
+                steps.push(Step {
+                    eid : *eid,
+                    cid : walk.cid,
+                    seq : walk.seq,
+                    lid : walk.lid,
+                    to : to,
+                    goal : walk.goal,
+                });
+            }
+            if walk.path.is_empty() {
+                finished.push(*eid);
+            }
+        }
+        for eid in finished {
+            self.walks.remove(&eid);
+        }
+        steps
+    }
+}
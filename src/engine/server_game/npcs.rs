@@ -0,0 +1,236 @@
+use std::collections::{HashMap,HashSet};
+use std::time::Duration;
+use rand::{Rng,SeedableRng,Isaac64Rng};
+use ::identity::*;
+use ::points::*;
+use ::engine::components::{Behavior,EntityComponents};
+use ::engine::game_state::locations::Location;
+use ::engine::game_state::pathfinding;
+use ::network::messaging::Diff;
+use super::catch_up::CatchUp;
+
+/*
+Entities the server moves itself, each as its Behavior component says.
+NpcBehaviors is a CatchUp system: NPCs walk every tick while their location is loaded, and make
+up for the time it was away when it loads again. Their steps are plain MoveEntityTo diffs,
+so they are saved, journaled and sent to clients like anyone else's.
+*/
+
+//average walking speed. players walk faster
+const NPC_CELLS_PER_SEC : f64 = 1.0;
+//a newly registered location gets up to this many NPCs
+const MAX_GENERATED_NPCS : u32 = 4;
+//how far from its spawn point a generated NPC's waypoints or home range reach, in cells
+const GENERATED_RANGE : i32 = 8;
+//random cells tried when looking for a free one
+const PLACEMENT_TRIES : u32 = 20;
+//keeps the NPC rng of a location apart from its other seeded rngs
+const NPC_SEED_SALT : u64 = 0x4e50_4353;
+
+const NEIGHBOURS : [(i32,i32); 8] = [(-1,-1), (0,-1), (1,-1), (-1,0), (1,0), (-1,1), (0,1), (1,1)];
+
+#[derive(Debug)]
+pub struct NpcBehaviors;
+
+impl CatchUp for NpcBehaviors {
+    fn step(&self, _lid : LocationID, loc : &Location, entities : &HashMap<EntityID,EntityComponents>,
+            step : Duration, rng : &mut Isaac64Rng) -> Vec<Diff> {
+        let mut diffs = vec![];
+        //cells stepped onto earlier in this step. `loc` doesn't know yet
+        let mut taken : HashSet<DPoint2> = HashSet::new();
+        let expected = secs(step) * NPC_CELLS_PER_SEC;
+        for (eid, start) in loc.entity_snapshot() {
+            let behavior = match entities.get(&eid).and_then(|c| c.behavior()) {
+                Some(b) => b,
+                None => continue,
+            };
+            //whole cells for sure, and a fraction of one by chance. short steps add up right
+            let mut moves = expected as u32;
+            if rng.gen::<f64>() < expected.fract() {
+                moves += 1;
+            }
+            let mut at = start;
+            for _ in 0..moves {
+                let next = {
+                    let free = |pt : DPoint2| loc.point_is_free(pt) && ! taken.contains(&pt);
+                    next_cell(eid, at, behavior, loc, entities, &free, rng)
+                };
+                match next {
+                    Some(to) => {
+                        taken.insert(to);
+                        diffs.push(Diff::MoveEntityTo(eid, to));
+                        at = to;
+                    },
+                    None => break,
+                }
+            }
+        }
+        diffs
+    }
+}
+
+fn secs(d : Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+fn dist(a : DPoint2, b : DPoint2) -> f32 {
+    a.continuous().dist_to(b.continuous())
+}
+
+fn free_neighbours<F>(at : DPoint2, free : &F) -> Vec<DPoint2>
+where F : Fn(DPoint2) -> bool {
+    NEIGHBOURS.iter()
+    .map(|&(dx, dy)| DPoint2::new(at.x + dx, at.y + dy))
+    .filter(|pt| free(*pt))
+    .collect()
+}
+
+//where the entity of `eid` at `at` steps next. None if it stays put
+fn next_cell<F>(eid : EntityID, at : DPoint2, behavior : &Behavior, loc : &Location,
+                entities : &HashMap<EntityID,EntityComponents>, free : &F, rng : &mut Isaac64Rng) -> Option<DPoint2>
+where F : Fn(DPoint2) -> bool {
+    match *behavior {
+        Behavior::Wander => rng.choose(&free_neighbours(at, free)).cloned(),
+        Behavior::FollowPath(ref waypoints) => {
+            if waypoints.is_empty() {
+                return None;
+            }
+            step_towards(at, path_target(waypoints, at), free)
+        },
+        Behavior::Flee {radius} => {
+            let threats : Vec<DPoint2> = loc.entities_within(
+                DPoint2::new(at.x - radius, at.y - radius),
+                DPoint2::new(at.x + radius, at.y + radius),
+            ).into_iter()
+            .filter(|&(other, pt)| other != eid && dist(at, pt) <= radius as f32 && is_player(other, entities))
+            .map(|(_, pt)| pt)
+            .collect();
+            if threats.is_empty() {
+                return rng.choose(&free_neighbours(at, free)).cloned();
+            }
+            let safety = |pt : DPoint2| threats.iter().map(|t| dist(pt, *t)).fold(::std::f32::MAX, f32::min);
+            let here = safety(at);
+            free_neighbours(at, free).into_iter()
+            .map(|pt| (safety(pt), pt))
+            .filter(|&(s, _)| s > here)
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .map(|(_, pt)| pt)
+        },
+        Behavior::StayNear {home, radius} => {
+            if dist(at, home) > radius as f32 {
+                //pushed out somehow (or spawned there). head back
+                return step_towards(at, home, free);
+            }
+            let options : Vec<DPoint2> = free_neighbours(at, free).into_iter()
+            .filter(|pt| dist(*pt, home) <= radius as f32)
+            .collect();
+            rng.choose(&options).cloned()
+        },
+    }
+}
+
+//players are the entities nobody but a client moves
+fn is_player(eid : EntityID, entities : &HashMap<EntityID,EntityComponents>) -> bool {
+    entities.get(&eid).map_or(false, |c| c.behavior().is_none())
+}
+
+//the first cell of a path to `goal`. if `goal` can't be reached, the free neighbour closest to it
+fn step_towards<F>(at : DPoint2, goal : DPoint2, free : &F) -> Option<DPoint2>
+where F : Fn(DPoint2) -> bool {
+    if let Some(path) = pathfinding::find_path(at, goal, |pt| free(pt)) {
+        return path.first().cloned();
+    }
+    let here = dist(at, goal);
+    free_neighbours(at, free).into_iter()
+    .map(|pt| (dist(pt, goal), pt))
+    .filter(|&(d, _)| d < here)
+    .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+    .map(|(_, pt)| pt)
+}
+
+/*
+The waypoint to head for from `at`: the one after the waypoint it stands on, else the end
+of the stretch of the (looping) path it is nearest to
+*/
+fn path_target(waypoints : &[DPoint2], at : DPoint2) -> DPoint2 {
+    let n = waypoints.len();
+    if let Some(i) = waypoints.iter().position(|pt| *pt == at) {
+        return waypoints[(i + 1) % n];
+    }
+    let mut best = (::std::f32::MAX, waypoints[0]);
+    for i in 0..n {
+        let (from, to) = (waypoints[i], waypoints[(i + 1) % n]);
+        let d = dist_to_stretch(at, from, to);
+        if d < best.0 {
+            best = (d, to);
+        }
+    }
+    best.1
+}
+
+fn dist_to_stretch(pt : DPoint2, from : DPoint2, to : DPoint2) -> f32 {
+    let (p, a, b) = (pt.continuous(), from.continuous(), to.continuous());
+    let (abx, aby) = (b.x - a.x, b.y - a.y);
+    let len_sq = abx * abx + aby * aby;
+    if len_sq == 0.0 {
+        return p.dist_to(a);
+    }
+    let t = (((p.x - a.x) * abx + (p.y - a.y) * aby) / len_sq).max(0.0).min(1.0);
+    p.dist_to(CPoint2::new(a.x + t * abx, a.y + t * aby))
+}
+
+//a random free cell, within `range` cells of `near` if given. None if none turned up
+fn random_free_cell(loc : &Location, near : Option<(DPoint2,i32)>, used : &HashSet<DPoint2>,
+                    rng : &mut Isaac64Rng) -> Option<DPoint2> {
+    for _ in 0..PLACEMENT_TRIES {
+        let pt = match near {
+            Some((c, range)) => DPoint2::new(c.x + rng.gen_range(-range, range + 1), c.y + rng.gen_range(-range, range + 1)),
+            None => DPoint2::new(rng.gen_range(0, loc.cells_wide()), rng.gen_range(0, loc.cells_high())),
+        };
+        if loc.point_is_free(pt) && ! used.contains(&pt) {
+            return Some(pt);
+        }
+    }
+    None
+}
+
+//the NPCs a location gets when it is first registered, and where. derived from its seed
+pub fn generated_npcs(loc : &Location) -> Vec<(DPoint2,Behavior)> {
+    let seed = loc.get_location_primitive().super_seed;
+    let mut rng = Isaac64Rng::from_seed(&[seed, NPC_SEED_SALT]);
+    let mut used = HashSet::new();
+    let mut npcs = vec![];
+    for _ in 0..rng.gen_range(0, MAX_GENERATED_NPCS + 1) {
+        let pt = match random_free_cell(loc, None, &used, &mut rng) {
+            Some(pt) => pt,
+            None => continue,
+        };
+        used.insert(pt);
+        let behavior = match rng.gen_range(0, 4) {
+            0 => Behavior::Wander,
+            1 => {
+                let mut waypoints = vec![pt];
+                for _ in 0..rng.gen_range(2, 4) {
+                    if let Some(wp) = random_free_cell(loc, Some((pt, GENERATED_RANGE)), &used, &mut rng) {
+                        waypoints.push(wp);
+                    }
+                }
+                Behavior::FollowPath(waypoints)
+            },
+            2 => Behavior::Flee {radius : GENERATED_RANGE / 2},
+            _ => Behavior::StayNear {home : pt, radius : GENERATED_RANGE / 2},
+        };
+        npcs.push((pt, behavior));
+    }
+    npcs
+}
+
+//what to call an NPC that behaves like so
+pub fn name_for(behavior : &Behavior) -> &'static str {
+    match *behavior {
+        Behavior::Wander => "Wanderer",
+        Behavior::FollowPath(_) => "Patroller",
+        Behavior::Flee {..} => "Skittish critter",
+        Behavior::StayNear {..} => "Homebody",
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use rand::{Rng,SeedableRng,Isaac64Rng};
+use ::identity::*;
+use ::points::DPoint2;
+use ::utils::traits::*;
+use ::engine::game_state::locations::Location;
+
+/*
+One-way jumps from a location cell to a cell of any location, in any world.
+A two-way portal is simply two entries. Stepping onto an entrance takes you to its exit.
+Entrances show up in their location as the object PORTAL_OID.
+*/
+#[derive(Debug,Serialize,Deserialize)]
+pub struct Portals {
+    #[serde(with = "::utils::serde_pairs")]
+    pub portals: HashMap<UniquePoint,UniquePoint>,
+    //true if changed since the last save
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl KnowsSavePrefix for Portals {
+    fn get_save_prefix() -> String {
+         "portals".to_owned()
+    }
+}
+
+pub use ::engine::objects::{PORTAL_OID,PORTAL_AID};
+
+//put files into this directory (inside the save dir) to create portals. see parse_portal_file
+pub const CREATE_PATH : &'static str = "portals_to_create/";
+
+//one in this many newly registered locations gets a portal to and from the start location
+const GENERATED_PORTAL_ODDS : u32 = 4;
+
+impl Portals {
+    pub fn new() -> Portals {
+        Portals {
+            portals: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn exit_of(&self, entrance: UniquePoint) -> Option<UniquePoint> {
+        self.portals.get(&entrance).cloned()
+    }
+
+    // cells of `lid` that are portal entrances
+    pub fn entrances_in(&self, lid: LocationID) -> Vec<DPoint2> {
+        self.portals.keys()
+        .filter(|e| e.lid == lid)
+        .map(|e| e.cell())
+        .collect()
+    }
+
+    // returns FALSE if `entrance` already leads somewhere
+    pub fn insert(&mut self, entrance: UniquePoint, exit: UniquePoint) -> bool {
+        if self.portals.contains_key(&entrance) {
+            return false;
+        }
+        self.portals.insert(entrance, exit);
+        self.dirty = true;
+        true
+    }
+
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    #[inline]
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+}
+
+/*
+One portal, as `<lid> <x> <y> <lid> <x> <y> [oneway]`.
+Portals are two-way unless the line ends with `oneway`. Returns (entrance, exit, two_way)
+*/
+pub fn parse_portal_line(line: &str) -> Result<(UniquePoint,UniquePoint,bool),String> {
+    let words : Vec<&str> = line.split_whitespace().collect();
+    let two_way = match words.get(6) {
+        None => true,
+        Some(&"oneway") => false,
+        Some(w) => return Err(format!("`{}` isn't `oneway`", w)),
+    };
+    let nums : Vec<i64> = words.iter().take(6).filter_map(|w| w.parse().ok()).collect();
+    if nums.len() != 6 || nums.iter().any(|n| *n < 0) {
+        return Err(format!("`{}` isn't `<lid> <x> <y> <lid> <x> <y> [oneway]`", line));
+    }
+    Ok((
+        UniquePoint::new(nums[0] as LocationID, DPoint2::new(nums[1] as i32, nums[2] as i32)),
+        UniquePoint::new(nums[3] as LocationID, DPoint2::new(nums[4] as i32, nums[5] as i32)),
+        two_way,
+    ))
+}
+
+/*
+A portal file holds one portal per line (see parse_portal_line). Lines starting with # are skipped.
+Returns (entrance, exit, two_way) for each valid line
+*/
+fn parse_portal_file(contents: &str) -> Vec<(UniquePoint,UniquePoint,bool)> {
+    let mut v = vec![];
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_portal_line(line) {
+            Ok(portal) => v.push(portal),
+            Err(e) => println!("Bad portal line: {}", e),
+        }
+    }
+    v
+}
+
+// reads and removes all portal files in `path`
+pub fn consume_portal_files(path: &Path) -> Vec<(UniquePoint,UniquePoint,bool)> {
+    let mut v = vec![];
+    let paths = match fs::read_dir(path) {
+        Ok(x) => x,
+        Err(_) => return v,
+    };
+    for path in paths {
+        if let Ok(okpath) = path {
+            if let Ok(mut file) = fs::File::open(&okpath.path()) {
+                let mut contents = String::new();
+                if file.read_to_string(&mut contents).is_ok() {
+                    v.extend(parse_portal_file(&contents));
+                }
+            }
+            let _ = fs::remove_file(&okpath.path());
+        }
+    }
+    v
+}
+
+/*
+Whether a newly registered location gets a portal to and from the start location, decided by its seed.
+Returns where in it, and where in the start location: free cells, so the portal can be stepped on.
+None too if either location has no free cell
+*/
+pub fn generated_portal(loc_seed: SuperSeed, loc: &Location, start: &Location) -> Option<(DPoint2,DPoint2)> {
+    let mut rng = Isaac64Rng::from_seed(&[loc_seed, PORTAL_OID]);
+    if ! rng.gen_weighted_bool(GENERATED_PORTAL_ODDS) {
+        return None;
+    }
+    let cell = DPoint2::new(rng.gen_range(0, loc.cells_wide()), rng.gen_range(0, loc.cells_high()));
+    let start_cell = DPoint2::new(rng.gen_range(0, start.cells_wide()), rng.gen_range(0, start.cells_high()));
+    Some((loc.free_point_near(cell)?, start.free_point_near(start_cell)?))
+}
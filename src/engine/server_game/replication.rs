@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use ::identity::*;
+use ::engine::components::{ComponentDiff,EntityComponents,Visibility};
+use ::network::messaging::{MsgToClient,MsgToClientSet};
+
+/*
+Keeps clients' copies of entity components up to date. A client asking about an entity gets
+a snapshot of what it may see of it, and from then on the diffs to that as they happen.
+Components visible to the owner only go to the client controlling the entity.
+*/
+pub struct ComponentReplicator {
+    //clients that were sent a snapshot of the entity
+    watchers : HashMap<EntityID,ClientIDSet>,
+}
+
+impl ComponentReplicator {
+    pub fn new() -> ComponentReplicator {
+        ComponentReplicator {
+            watchers : HashMap::new(),
+        }
+    }
+
+    //the snapshot of `eid` for `cid`, which now gets its diffs too
+    pub fn snapshot_for(&mut self, cid : ClientID, eid : EntityID, components : &EntityComponents,
+                        owner : Option<ClientID>) -> MsgToClient {
+        self.watchers.entry(eid).or_insert_with(ClientIDSet::new).set(cid, true);
+        MsgToClient::GiveEntityComponents(eid, components.visible_to(owner == Some(cid)))
+    }
+
+    //stop sending `cid` anything. call when it logs out
+    pub fn forget_client(&mut self, cid : ClientID) {
+        for set in self.watchers.values_mut() {
+            set.set(cid, false);
+        }
+        self.watchers.retain(|_, set| ! set.is_empty());
+    }
+
+    //sends `diff`, just applied to `eid`, to the watchers that may see it
+    pub fn broadcast(&self, eid : EntityID, diff : ComponentDiff, tick : Tick, owner : Option<ClientID>,
+                     outgoing_updates : &mut Vec<MsgToClientSet>) {
+        let watchers = match self.watchers.get(&eid) {
+            Some(set) => *set,
+            None => return,
+        };
+        let msg = MsgToClient::ApplyComponentDiff(eid, diff.clone(), tick);
+        match diff.kind().visibility() {
+            Visibility::Everyone => outgoing_updates.push(MsgToClientSet::Subset(msg, watchers)),
+            Visibility::Owner => if let Some(cid) = owner {
+                if watchers.get(cid) {
+                    outgoing_updates.push(MsgToClientSet::Only(msg, cid));
+                }
+            },
+            Visibility::Server => (),
+        }
+    }
+}
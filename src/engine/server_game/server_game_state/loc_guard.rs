@@ -1,10 +1,11 @@
+use std::collections::HashMap;
 use super::SaverLoader;
 use ::engine::game_state::locations::{Location,LocationPrimitive};
-use ::identity::{LocationID};
-use super::{WorldLoader,WorldPrimLoader};
+use ::identity::{LocationID,ObjectID};
+use ::points::DPoint2;
 use super::{Diff};
-use super::super::super::game_state::worlds::zones::Zone;
-use super::super::super::game_state::worlds::START_WORLD;
+use super::super::JournalEntry;
+use ::journal::Journal;
 use ::utils::traits::*;
 // use super::super::network::messaging::MsgToClient;
 
@@ -13,7 +14,10 @@ use ::utils::traits::*;
 #[derive(Debug)]
 pub struct LocationGuard {
     loc : Location,
+    //what turns the generated location into this one. compacted whenever it is saved
     diffs : Vec<Diff>,
+    //true if diffs were applied since the last save
+    dirty : bool,
 }
 
 
@@ -24,6 +28,35 @@ impl KnowsSavePrefix for Vec<Diff> {
 }
 
 impl LocationGuard {
+    //a guard over a freshly generated location. Dirty, as nothing of it is on disk yet
+    pub fn new(loc : Location) -> LocationGuard {
+        LocationGuard {
+            loc : loc,
+            diffs : vec![],
+            dirty : true,
+        }
+    }
+
+    //a guard over a location regenerated from its saved prim. Replays the saved diffs
+    pub fn from_saved(loc : Location, diffs : Vec<Diff>) -> LocationGuard {
+        let mut loc_guard = LocationGuard {
+            loc : loc,
+            diffs : vec![],
+            dirty : false,
+        };
+        for diff in diffs {
+            let _ = loc_guard.replay_diff(diff);
+        }
+        //replaying what is already on disk doesn't make it dirty
+        loc_guard.dirty = false;
+        loc_guard
+    }
+
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
     #[inline]
     pub fn get_location_primitive(&self) -> &LocationPrimitive {
         self.loc.get_location_primitive()
@@ -33,60 +66,145 @@ impl LocationGuard {
         &self.loc
     }
 
-    pub fn apply_diff(&mut self, diff : Diff) -> Result<(),()> {
-        if self.loc.apply_diff(diff).is_ok() {
-            self.diffs.push(diff);
-            Ok(())
-        } else {
-            Err(())
-        }
+    //the only way the server changes a location. Accepted diffs are journaled until the next save
+    pub fn apply_diff(&mut self, lid : LocationID, diff : Diff, journal : &mut Journal<JournalEntry>) -> Result<(),()> {
+        self.apply_diff_unjournaled(diff)?;
+        journal.append(&JournalEntry::LocationDiff(lid, diff));
+        Ok(())
     }
 
+    //like apply_diff, for changes the caller journals itself (see catch_up)
+    pub fn apply_diff_unjournaled(&mut self, diff : Diff) -> Result<(),()> {
+        self.loc.apply_diff(diff)?;
+        self.diffs.push(diff);
+        self.dirty = true;
+        Ok(())
+    }
+
+    //applies a diff that is already on disk or in the journal
+    pub fn replay_diff(&mut self, diff : Diff) -> Result<(),()> {
+        self.loc.apply_authoritative_diff(diff)?;
+        self.diffs.push(diff);
+        self.dirty = true;
+        Ok(())
+    }
 
-    pub fn save_to(&self, sl : &SaverLoader, lid : LocationID) {
+
+    /*
+    Folds the diffs down to what rebuilds the location as it is now: the net change to each
+    object, then where each entity stands. How they got there is dropped, so entities walking
+    about (NPCs above all) don't grow the log, however long the location stays loaded
+    */
+    fn compact(&mut self) {
+        let mut net : HashMap<(ObjectID,DPoint2),i32> = HashMap::new();
+        let mut order = vec![];
+        for diff in self.diffs.iter() {
+            let (key, change) = match *diff {
+                Diff::PlaceObject(oid, pt) => ((oid, pt), 1),
+                Diff::RemoveObject(oid, pt) => ((oid, pt), -1),
+                _ => continue,
+            };
+            *net.entry(key).or_insert_with(|| {order.push(key); 0}) += change;
+        }
+        let mut compacted = vec![];
+        for (oid, pt) in order {
+            match net[&(oid, pt)] {
+                n if n > 0 => compacted.push(Diff::PlaceObject(oid, pt)),
+                n if n < 0 => compacted.push(Diff::RemoveObject(oid, pt)),
+                _ => (),
+            }
+        }
+        for (eid, pt) in self.loc.entity_snapshot() {
+            compacted.push(Diff::PlaceInside(eid, pt));
+        }
+        self.diffs = compacted;
+    }
+
+    //returns the number of bytes written. Stays dirty if anything failed to save
+    pub fn save_to(&mut self, sl : &SaverLoader, lid : LocationID) -> usize {
+        self.compact();
         println!("saving loc lid:{:?} prim", lid);
-        sl.save_with_key(self.loc.get_location_primitive(), lid).is_ok();
+        let prim_res = sl.save_with_key(self.loc.get_location_primitive(), lid);
         println!("saving loc lid:{:?} diffs", lid);
-        sl.save_with_key(
+        let diffs_res = sl.save_with_key(
             & self.diffs,
             lid,
-        ).is_ok();
-    }
-
-    pub fn load_from(sl : &SaverLoader, lid : LocationID, world_loader: &mut WorldLoader, wpl: &mut WorldPrimLoader) -> LocationGuard {
-        match sl.load_with_key(lid) {
-            Ok(prim) => { //found prim
-                let diffs : Vec<Diff> = sl.load_with_key(lid)
-                    .expect("prim ok but diffs not??");
-                    //don't store diffs just yet. let loc_guard do that
-                    //TODO move server_game_state into its own module
-                let prim2 : LocationPrimitive = prim; //can't wait for type ascription
-                let wid = prim2.wid;
-                let w = world_loader.get_world(wid, wpl);
-                let z : Zone = w.get_zone(prim2.zone_id).clone();
-                let loc : Location = Location::generate_new(prim2, z);
-                let mut loc_guard = LocationGuard {
-                    loc : loc,
-                    diffs : vec![],
-                };
-                //apply all diffs in trn
-                for diff in diffs {
-                    loc_guard.apply_diff(diff);
-                }
-                loc_guard
+        );
+        match (prim_res, diffs_res) {
+            (Ok(prim_bytes), Ok(diffs_bytes)) => {
+                self.dirty = false;
+                prim_bytes + diffs_bytes
             },
-            Err(_) => { // couldn't find savefile!
-                if lid == super::START_LOCATION_LID { //ok must be a new game
-                    println!("Generating start location!");
-                    LocationGuard {
-                        // TODO check this doesnt make duplicates
-                        loc : Location::generate_new(*super::START_LOC_PRIM, START_WORLD.get_zone(0).clone()),
-                        diffs : vec![],
-                    }
-                } else { //nope! just missing savefile
-                    panic!("MISSING SAVEFILE??");
-                }
+            _ => {
+                println!("Failed to save loc lid:{:?}", lid);
+                0
             },
         }
     }
+
+    //like save_to, but only touches the disk if something changed
+    pub fn save_if_dirty(&mut self, sl : &SaverLoader, lid : LocationID) -> usize {
+        if self.dirty {
+            self.save_to(sl, lid)
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::engine::game_state::locations::START_LOC_PRIM;
+    use ::engine::game_state::worlds::START_WORLD;
+    use ::engine::objects::{TREE_OID,ROCK_OID};
+    use super::*;
+
+    //with a tree standing in for generated objects on its first free cell
+    fn start_location() -> Location {
+        let mut loc = Location::generate_new(*START_LOC_PRIM, START_WORLD.get_zone(0).clone());
+        let pt = loc.free_point().unwrap();
+        loc.apply_authoritative_diff(Diff::PlaceObject(TREE_OID, pt)).unwrap();
+        loc
+    }
+
+    #[test]
+    fn compacting_keeps_the_location_and_drops_the_walking() {
+        let mut guard = LocationGuard::new(start_location());
+        let (a, b, c) = {
+            let loc = guard.borrow_location();
+            let a = loc.free_point().unwrap();
+            let b = loc.free_point_near(DPoint2::new(a.x + 3, a.y)).unwrap();
+            let c = loc.free_point_near(DPoint2::new(a.x, a.y + 3)).unwrap();
+            (a, b, c)
+        };
+        let tree = *guard.borrow_location().oid_points(TREE_OID).unwrap().iter().next().unwrap();
+        assert!(tree != a);
+        guard.apply_diff_unjournaled(Diff::PlaceInside(1, a)).unwrap();
+        guard.apply_diff_unjournaled(Diff::PlaceInside(2, c)).unwrap();
+        for i in 0..500 {
+            let to = if i % 2 == 0 {b} else {a};
+            guard.apply_diff_unjournaled(Diff::MoveEntityTo(1, to)).unwrap();
+        }
+        guard.apply_diff_unjournaled(Diff::RemoveEntity(2)).unwrap();
+        //placed and taken away again: no trace left
+        guard.apply_diff_unjournaled(Diff::PlaceObject(ROCK_OID, c)).unwrap();
+        guard.apply_diff_unjournaled(Diff::RemoveObject(ROCK_OID, c)).unwrap();
+        guard.apply_diff_unjournaled(Diff::RemoveObject(TREE_OID, tree)).unwrap();
+        guard.apply_diff_unjournaled(Diff::PlaceObject(ROCK_OID, b)).unwrap();
+
+        guard.compact();
+        assert_eq!(guard.diffs.len(), 3);
+        let rebuilt = LocationGuard::from_saved(start_location(), guard.diffs.clone());
+        let (loc, again) = (guard.borrow_location(), rebuilt.borrow_location());
+        assert_eq!(again.entity_snapshot(), vec![(1, a)]);
+        assert_eq!(again.entity_snapshot(), loc.entity_snapshot());
+        for &pt in [a, b, c, tree].iter() {
+            let (mut x, mut y) = (loc.objects_at(pt), again.objects_at(pt));
+            x.sort();
+            y.sort();
+            assert_eq!(x, y, "objects at {:?}", pt);
+        }
+        assert!(! again.objects_at(tree).contains(&TREE_OID));
+        assert!(again.objects_at(b).contains(&ROCK_OID));
+    }
 }
@@ -1,50 +1,144 @@
 use ::identity::*;
 use rand::{Rng,Isaac64Rng};
-use std::collections::HashMap;
-use ::engine::game_state::locations::{Location,LocationPrimitive};
+use std::collections::{HashMap,HashSet};
+use std::hash::Hash;
+use std::fmt::Debug;
+use std::time::{Instant,Duration};
+use serde::Serialize;
+use ::engine::game_state::locations::{self,Location,LocationPrimitive,START_LOC_PRIM};
 use ::engine::game_state::worlds::{World,WorldPrimitive};
+use ::engine::game_state::universe::{Universe,UniversePrimitive};
+use rand::thread_rng;
 use saving::SaverLoader;
 use utils::traits::*;
 use ::identity::UniquePoint;
 use engine::objects::*;
 use engine::entities::*;
-use std::cell::RefCell;
-use std::rc::Rc;
-
-#[derive(Debug,Serialize,Deserialize)]
-struct Portals {
-    portals: HashMap<UniquePoint,UniquePoint>,
-}
-
-impl KnowsSavePrefix for Portals {
-    fn get_save_prefix() -> String {
-         "portals".to_owned()
-    }
-}
-
-
+use engine::components::{EntityComponents,ComponentDiff};
+use network::messaging::Diff;
+use super::server_game_state::START_LOCATION_LID;
+use super::server_game_state::loc_guard::LocationGuard;
+use super::subscription_manager::SubscriptionManager;
+use super::JournalEntry;
+use ::journal::Journal;
+use super::catch_up::{self,CatchUp,EvictionTimes};
+use super::location_registry::{self,LocationRegistry};
+use super::portals::Portals;
+use super::ground_items::GroundItems;
+
+
+/*
+Owns everything the server has loaded: locations, worlds, their primitives, objects and entities.
+Loads lazily from the save dir (or derives) on first use.
+Locations are in one of two tiers:
+    foreground: some client is subscribed. always stays resident
+    background: nobody is subscribed. evicted once it has been in background for `background_retention`
+Worlds and world prims with no resident location are evicted along with them.
+*/
 #[derive(Debug)]
 pub struct ServerResources {
-    locations: HashMap<LocationID, Location>,
+    locations: HashMap<LocationID, LocationGuard>,
+    //resident locations with no subscribers, and since when. the rest are foreground
+    last_backgrounded: HashMap<LocationID, Instant>,
+    //evicted locations, and when. consumed when they load again
+    last_simulated: EvictionTimes,
+    //what catching up changed, until journal_caught_up journals it
+    caught_up: Vec<JournalEntry>,
+    background_retention: Duration,
+    //stepped every tick in loaded locations, and over the time a location was evicted when it loads again
+    catch_ups: Vec<Box<CatchUp>>,
     location_prims: HashMap<LocationID, LocationPrimitive>,
     worlds: HashMap<WorldID, World>,
     world_prims: HashMap<WorldID, WorldPrimitive>,
     objects: HashMap<ObjectID, ObjectData>,
-    entities: HashMap<EntityID, EntityData>,
+    entities: HashMap<EntityID, EntityComponents>,
+    universe: Universe,
+    //true until the universe prim of a new game is saved
+    dirty_universe: bool,
+    registry: LocationRegistry,
+    portals: Portals,
+    ground_items: GroundItems,
+
+    //changed since last save. (locations track this themselves)
+    dirty_location_prims: HashSet<LocationID>,
+    dirty_world_prims: HashSet<WorldID>,
+    dirty_objects: HashSet<ObjectID>,
+    dirty_entities: HashSet<EntityID>,
 
     sl: SaverLoader,
     rng: Isaac64Rng,
 }
 
+//saves every dirty key that is still in `map`. returns bytes written. failed keys stay dirty
+fn save_dirty_of<K,V>(sl: &SaverLoader, dirty: &mut HashSet<K>, map: &HashMap<K,V>) -> usize
+where K: KnowsSaveSuffix + Hash + Eq + Copy + Debug,
+      V: Serialize + Debug + KnowsSavePrefix {
+    let mut bytes_written = 0;
+    let mut failed = vec![];
+    for k in dirty.drain() {
+        if let Some(v) = map.get(&k) {
+            match sl.save_with_key(v, k) {
+                Ok(bytes) => bytes_written += bytes,
+                Err(_) => {
+                    println!("Failed to save {}{}", V::get_save_prefix(), k.get_save_suffix());
+                    failed.push(k);
+                },
+            }
+        }
+    }
+    dirty.extend(failed);
+    bytes_written
+}
+
 impl ServerResources {
-    pub fn new(sl: SaverLoader, rng: Isaac64Rng) -> ServerResources {
+    pub fn new(sl: SaverLoader, rng: Isaac64Rng, background_retention: Duration) -> ServerResources {
+        let registry = match sl.load_without_key::<LocationRegistry>() {
+            Ok(x) => x,
+            Err(_) => {
+                println!("No location registry. Made fresh");
+                LocationRegistry::new()
+            },
+        };
+        let portals = match sl.load_without_key::<Portals>() {
+            Ok(x) => x,
+            Err(_) => Portals::new(),
+        };
+        let last_simulated = match sl.load_without_key::<EvictionTimes>() {
+            Ok(x) => x,
+            Err(_) => EvictionTimes::new(),
+        };
+        let ground_items = match sl.load_without_key::<GroundItems>() {
+            Ok(x) => x,
+            Err(_) => GroundItems::new(),
+        };
+        let (up, dirty_universe) = match sl.load_without_key::<UniversePrimitive>() {
+            Ok(x) => (x, false),
+            Err(_) => {
+                println!("No universe. Made fresh");
+                (UniversePrimitive::new(thread_rng().gen()), true)
+            },
+        };
         ServerResources {
+            universe: Universe::new(up),
+            dirty_universe: dirty_universe,
+            registry: registry,
+            portals: portals,
+            ground_items: ground_items,
             locations: HashMap::new(),
+            last_backgrounded: HashMap::new(),
+            last_simulated: last_simulated,
+            caught_up: vec![],
+            background_retention: background_retention,
+            catch_ups: vec![],
             location_prims: HashMap::new(),
             worlds: HashMap::new(),
             world_prims: HashMap::new(),
             objects: HashMap::new(),
             entities: HashMap::new(),
+            dirty_location_prims: HashSet::new(),
+            dirty_world_prims: HashSet::new(),
+            dirty_objects: HashSet::new(),
+            dirty_entities: HashSet::new(),
             rng: rng,
             sl: sl,
         }
@@ -67,10 +161,14 @@ impl ServerResources {
             self.world_prims.insert(wid, wp);
             return
         }
-        //make new!
-        let wp = WorldPrimitive::new(self.rng.gen(), self.rng.gen());
-        self.world_prims.insert(wid, wp);
-    }    
+        if let Some(wp) = self.universe.world_primitive(wid) {
+            //.3 derive it from the universe
+            self.world_prims.insert(wid, wp);
+            self.dirty_world_prims.insert(wid);
+            return
+        }
+        panic!("Unknown World creation requested!");
+    }
 
     fn world_populate(&mut self, wid: WorldID) {
         if self.worlds.contains_key(&wid) {
@@ -91,12 +189,26 @@ impl ServerResources {
             //.2
             self.location_prims.insert(lid, lp);
             return
+        } else if lid == START_LOCATION_LID {
+            //must be a new game
+            println!("Generating start location prim!");
+            self.location_prims.insert(lid, *START_LOC_PRIM);
+            self.dirty_location_prims.insert(lid);
+            return
+        } else if let Some((wid, zone_id)) = self.registry.zone_of(lid) {
+            //.3 derive it from its world
+            println!("Generating location prim for LID {:?} (world {:?} zone {:?})", lid, wid, zone_id);
+            let world_seed = self.get_world_primitive(wid).super_seed();
+            let lp = LocationPrimitive::new(wid, zone_id, 1.0, location_registry::location_seed(world_seed, zone_id));
+            self.location_prims.insert(lid, lp);
+            self.dirty_location_prims.insert(lid);
+            return
         }
         panic!("Unknown LocPrim creation requested!");
     }
 
     fn location_populate(&mut self, lid: LocationID) {
-        if self.worlds.contains_key(&lid) {
+        if self.locations.contains_key(&lid) {
             //.1
             return
         }
@@ -106,7 +218,28 @@ impl ServerResources {
         let w = self.worlds.get(&lp.wid).expect("you said..");
         let world_zone = w.get_zone(lp.zone_id);
         let l = Location::generate_new(lp.clone(), world_zone.clone());
-        self.locations.insert(lid, l);
+        let mut loc_guard = match self.sl.load_with_key::<Vec<Diff>,LocationID>(lid) {
+            //.2 diffs on top of the derived location
+            Ok(diffs) => LocationGuard::from_saved(l, diffs),
+            Err(_) => LocationGuard::new(l),
+        };
+        //systems stepping the location may look at what its entities are
+        for (eid, _) in loc_guard.borrow_location().entity_snapshot() {
+            if ! self.try_entity_populate(eid) {
+                println!("LID {:?} has unknown EID {:?}", lid, eid);
+            }
+        }
+        //nothing to catch up with. the eviction time is kept for a run that has something
+        let away = if self.catch_ups.is_empty() {None} else {self.consume_time_since_last_sim(lid)};
+        if let Some(away) = away {
+            let applied = catch_up::catch_up(&mut loc_guard, lid, away, &self.catch_ups, &self.entities, &mut self.rng);
+            self.caught_up.push(JournalEntry::CaughtUp(lid));
+            self.caught_up.extend(applied.into_iter().map(|diff| JournalEntry::LocationDiff(lid, diff)));
+        }
+        println!("Loaded LID {:?} to background", lid);
+        self.locations.insert(lid, loc_guard);
+        //foreground is decided by the next refresh_residency
+        self.last_backgrounded.insert(lid, Instant::now());
     }
 
     fn object_populate(&mut self, oid: ObjectID) {
@@ -121,16 +254,29 @@ impl ServerResources {
         panic!("Unknown Object creation requested!");
     }
 
-    fn entity_populate(&mut self, eid: EntityID) {
+    //false if `eid` was never defined
+    fn try_entity_populate(&mut self, eid: EntityID) -> bool {
         if self.entities.contains_key(&eid) {
             //.1
-            return
-        } else if let Ok(ed) = self.sl.load_with_key::<EntityData,EntityID>(eid) {
+            true
+        } else if let Ok(ec) = self.sl.load_with_key::<EntityComponents,EntityID>(eid) {
             //.2
-            self.entities.insert(eid, ed);
-            return
+            self.entities.insert(eid, ec);
+            true
+        } else if let Ok(ed) = self.sl.load_with_key::<EntityData,EntityID>(eid) {
+            //.2 saved before components. saved again as such
+            self.entities.insert(eid, EntityComponents::from_legacy(ed));
+            self.dirty_entities.insert(eid);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn entity_populate(&mut self, eid: EntityID) {
+        if ! self.try_entity_populate(eid) {
+            panic!("Unknown Entity creation requested!");
         }
-        panic!("Unknown Entity creation requested!");
     }
 
     ///////////////////////////// PUBLIC ///////////////////////
@@ -152,7 +298,89 @@ impl ServerResources {
 
     pub fn get_location(&mut self, lid: LocationID) -> &Location {
         self.location_populate(lid);
-        self.locations.get(&lid).expect("kkfam")
+        self.locations.get(&lid).expect("kkfam").borrow_location()
+    }
+
+    pub fn get_universe(&self) -> &Universe {
+        &self.universe
+    }
+
+    // true if `wid` is part of the universe. Worlds that don't exist can't be loaded
+    pub fn world_exists(&self, wid: WorldID) -> bool {
+        self.world_prims.contains_key(&wid)
+        || (wid as usize) < self.universe.num_worlds()
+    }
+
+    // true if `lid` was ever handed out. Locations that don't exist can't be loaded
+    pub fn location_exists(&self, lid: LocationID) -> bool {
+        lid == START_LOCATION_LID
+        || self.location_prims.contains_key(&lid)
+        || self.registry.zone_of(lid).is_some()
+    }
+
+    /*
+    The location of the given zone. registers it if nobody asked for it before.
+    Returns None if the world has no such zone, else the LocationID and TRUE if it was only just registered
+    */
+    pub fn location_for_zone(&mut self, wid: WorldID, zone_id: ZoneID) -> Option<(LocationID,bool)> {
+        if let Some(lid) = self.registry.lid_of(wid, zone_id) {
+            return Some((lid, false));
+        }
+        if ! self.world_exists(wid) {
+            return None;
+        }
+        if zone_id >= self.get_world(wid).num_zones() {
+            return None;
+        }
+        Some(self.registry.register(wid, zone_id))
+    }
+
+    /*
+    If `pt` in `lid` is the end of a WorldLink, where the link leads: the location of the zone
+    at the other end, the cell there, and TRUE if that location was only just registered
+    */
+    pub fn link_destination(&mut self, lid: LocationID, pt: ::points::DPoint2) -> Option<(LocationID,::points::DPoint2,bool)> {
+        let lp = *self.get_location_primitive(lid);
+        let coord = locations::cell_to_sample(pt)?;
+        let dest = self.get_world(lp.wid).link_from(lp.zone_id, coord);
+        if let Some((to_zone, to_coord)) = dest {
+            if let Some((to_lid, is_new)) = self.location_for_zone(lp.wid, to_zone) {
+                return Some((to_lid, locations::sample_to_cell(to_coord), is_new));
+            }
+        }
+        None
+    }
+
+    // the portal a newly registered `lid` gets to and from the start location, if any. see portals::generated_portal
+    pub fn generated_portal_for(&mut self, lid: LocationID) -> Option<(::points::DPoint2,::points::DPoint2)> {
+        self.location_populate(lid);
+        self.location_populate(START_LOCATION_LID);
+        let loc = self.locations.get(&lid).expect("you said..").borrow_location();
+        let start = self.locations.get(&START_LOCATION_LID).expect("you said..").borrow_location();
+        super::portals::generated_portal(loc.get_location_primitive().super_seed, loc, start)
+    }
+
+    pub fn get_portals(&self) -> &Portals {
+        &self.portals
+    }
+
+    // only records the portal. its objects are placed with location diffs
+    pub fn add_portal(&mut self, entrance: UniquePoint, exit: UniquePoint) -> bool {
+        self.portals.insert(entrance, exit)
+    }
+
+    pub fn get_ground_items(&self) -> &GroundItems {
+        &self.ground_items
+    }
+
+    // only records the count. the pile's object is placed and removed with location diffs
+    pub fn set_ground_pile(&mut self, lid: LocationID, pt: ::points::DPoint2, iid: ItemID, count: u32) {
+        self.ground_items.set(lid, pt, iid, count);
+    }
+
+    // for mappings read back from the journal
+    pub fn restore_zone_location(&mut self, wid: WorldID, zone_id: ZoneID, lid: LocationID) {
+        self.registry.restore(wid, zone_id, lid);
     }
 
     pub fn get_object(&mut self, oid: ObjectID) -> &ObjectData {
@@ -160,7 +388,11 @@ impl ServerResources {
         self.objects.get(&oid).unwrap()
     }
 
-    pub fn get_entity(&mut self, eid: EntityID) -> &EntityData {
+    pub fn entity_exists(&mut self, eid: EntityID) -> bool {
+        self.try_entity_populate(eid)
+    }
+
+    pub fn get_entity(&mut self, eid: EntityID) -> &EntityComponents {
         self.entity_populate(eid);
         self.entities.get(&eid).unwrap()
     }
@@ -170,6 +402,8 @@ impl ServerResources {
 
     pub fn get_mut_world_primitive(&mut self, wid: WorldID) -> &mut WorldPrimitive {
         self.world_prim_populate(wid);
+        //caller may change it. assume they do
+        self.dirty_world_prims.insert(wid);
         self.world_prims.get_mut(&wid).expect("kkfam")
     }
 
@@ -180,44 +414,374 @@ impl ServerResources {
 
     pub fn get_mut_location_primitive(&mut self, lid: LocationID) -> &mut LocationPrimitive {
         self.location_prim_populate(lid);
+        //caller may change it. assume they do
+        self.dirty_location_prims.insert(lid);
         self.location_prims.get_mut(&lid).expect("kkfam")
     }
 
-    pub fn get_mut_location(&mut self, lid: LocationID) -> &mut Location {
+    // locations are only changed through diffs, so that the guard can record and journal them
+    pub fn apply_location_diff(&mut self, lid: LocationID, diff: Diff, journal: &mut Journal<JournalEntry>) -> Result<(),()> {
+        self.location_populate(lid);
+        self.journal_caught_up(journal);
+        self.locations.get_mut(&lid).expect("kkfam").apply_diff(lid, diff, journal)
+    }
+
+    // for diffs read back from the journal. they must not be journaled again
+    pub fn replay_location_diff(&mut self, lid: LocationID, diff: Diff) -> Result<(),()> {
         self.location_populate(lid);
-        self.locations.get_mut(&lid).expect("kkfam")
+        self.locations.get_mut(&lid).expect("kkfam").replay_diff(diff)
+    }
+
+    /////////////////////////////////////////////////////////////////////
+
+    // moves resident locations between foreground and background to match subscriptions
+    pub fn refresh_residency(&mut self, subscription_manager: &SubscriptionManager) {
+        for lid in self.locations.keys() {
+            if subscription_manager.subscribers_exist_for(*lid) {
+                if self.last_backgrounded.remove(lid).is_some() {
+                    println!("Promoting LID {:?} to foreground", lid);
+                }
+            } else if ! self.last_backgrounded.contains_key(lid) {
+                println!("Demoting LID {:?} to background", lid);
+                self.last_backgrounded.insert(*lid, Instant::now());
+            }
+        }
+    }
+
+    /*
+    Evicts locations that were in background for longer than the retention window,
+    then the worlds no resident location is in.
+    Only evicts what is saved; call right after save_dirty. Returns the number of locations evicted
+    */
+    pub fn unload_overdue_backgrounds(&mut self) -> usize {
+        let overdue : Vec<LocationID> = self.last_backgrounded.iter()
+        .filter(|&(lid, t)| {
+            t.elapsed() > self.background_retention
+            && ! self.locations.get(lid).map(|g| g.is_dirty()).unwrap_or(false)
+            && ! self.dirty_location_prims.contains(lid)
+        })
+        .map(|(lid, _)| *lid)
+        .collect();
+        for lid in overdue.iter() {
+            println!("Unloading background LID {:?}", lid);
+            self.unload_lid(*lid);
+            //marking as "last simulated" around this time
+            self.last_simulated.evicted_now(*lid);
+        }
+        if ! overdue.is_empty() {
+            //the locations are on disk already. their eviction times go with them
+            self.save_eviction_times();
+            let in_use : HashSet<WorldID> = self.location_prims.values().map(|lp| lp.wid).collect();
+            let unused : Vec<WorldID> = self.world_prims.keys()
+                .filter(|wid| ! in_use.contains(wid) && ! self.dirty_world_prims.contains(wid))
+                .cloned()
+                .collect();
+            for wid in unused {
+                println!("Unloading WID {:?}", wid);
+                self.unload_wid(wid);
+            }
+        }
+        overdue.len()
+    }
+
+    // from now on, locations that load again after eviction are stepped through `system` too
+    pub fn register_catch_up(&mut self, system: Box<CatchUp>) {
+        self.catch_ups.push(system);
+    }
+
+    /*
+    Steps every registered system over one tick in each loaded location.
+    Returns the diffs that applied (and were journaled), for telling subscribers
+    */
+    pub fn simulate_tick(&mut self, step: Duration, journal: &mut Journal<JournalEntry>) -> Vec<(LocationID,Diff)> {
+        self.journal_caught_up(journal);
+        let mut applied = vec![];
+        for (lid, loc_guard) in self.locations.iter_mut() {
+            for system in self.catch_ups.iter() {
+                for diff in system.step(*lid, loc_guard.borrow_location(), &self.entities, step, &mut self.rng) {
+                    if loc_guard.apply_diff(*lid, diff, journal).is_ok() {
+                        applied.push((*lid, diff));
+                    }
+                }
+            }
+        }
+        applied
+    }
+
+    /*
+    Journals what catching up changed in locations loaded since the last call.
+    Locations load wherever they are first needed, often with no journal at hand, so this
+    must run before anything else is journaled that could depend on them (apply_location_diff
+    and simulate_tick see to that), and before each journal flush
+    */
+    pub fn journal_caught_up(&mut self, journal: &mut Journal<JournalEntry>) {
+        for entry in self.caught_up.drain(..) {
+            journal.append(&entry);
+        }
+    }
+
+    // how long ago `lid` was evicted, if it was. only answers once per eviction
+    pub fn consume_time_since_last_sim(&mut self, lid: LocationID) -> Option<Duration> {
+        self.last_simulated.consume(lid)
+    }
+
+    fn save_eviction_times(&mut self) -> usize {
+        if ! self.last_simulated.is_dirty() {
+            return 0;
+        }
+        match self.sl.save_without_key(&self.last_simulated) {
+            Ok(bytes) => {
+                self.last_simulated.mark_clean();
+                bytes
+            },
+            Err(_) => {
+                println!("Failed to save eviction times");
+                0
+            },
+        }
+    }
+
+    pub fn print_status(&self) {
+        println!("ServerResources status: {{", );
+        for lid in self.locations.keys() {
+            match self.last_backgrounded.get(lid) {
+                Some(t) => println!("\tBG {:?} time bg'd: {:?}", lid, t.elapsed()),
+                None => println!("\tFG {:?}", lid),
+            }
+        }
+        println!("\t{} worlds, {} objects, {} entities", self.worlds.len(), self.objects.len(), self.entities.len());
+        println!("}}");
     }
 
     /////////////////////////////////////////////////////////////////////
 
-    pub fn save_all(&mut self) {
-        for (lid,lp) in self.location_prims.iter() {
-            self.sl.save_with_key(lp, *lid);
+    // saves only what changed since the last call. returns the number of bytes written
+    pub fn save_dirty(&mut self) -> usize {
+        let mut bytes_written = 0;
+        if self.dirty_universe {
+            match self.sl.save_without_key(self.universe.get_universe_primitive()) {
+                Ok(bytes) => {
+                    bytes_written += bytes;
+                    self.dirty_universe = false;
+                },
+                Err(_) => println!("Failed to save universe prim"),
+            }
+        }
+        if self.registry.is_dirty() {
+            match self.sl.save_without_key(&self.registry) {
+                Ok(bytes) => {
+                    bytes_written += bytes;
+                    self.registry.mark_clean();
+                },
+                Err(_) => println!("Failed to save location registry"),
+            }
         }
-        for (wid,wp) in self.world_prims.iter() {
-            self.sl.save_with_key(wp, *wid);
+        if self.portals.is_dirty() {
+            match self.sl.save_without_key(&self.portals) {
+                Ok(bytes) => {
+                    bytes_written += bytes;
+                    self.portals.mark_clean();
+                },
+                Err(_) => println!("Failed to save portals"),
+            }
         }
+        bytes_written += self.save_eviction_times();
+        if self.ground_items.is_dirty() {
+            match self.sl.save_without_key(&self.ground_items) {
+                Ok(bytes) => {
+                    bytes_written += bytes;
+                    self.ground_items.mark_clean();
+                },
+                Err(_) => println!("Failed to save ground items"),
+            }
+        }
+        bytes_written += save_dirty_of(&self.sl, &mut self.dirty_location_prims, &self.location_prims);
+        bytes_written += save_dirty_of(&self.sl, &mut self.dirty_world_prims, &self.world_prims);
+        bytes_written += save_dirty_of(&self.sl, &mut self.dirty_objects, &self.objects);
+        bytes_written += save_dirty_of(&self.sl, &mut self.dirty_entities, &self.entities);
+        for (lid, loc_guard) in self.locations.iter_mut() {
+            bytes_written += loc_guard.save_if_dirty(&self.sl, *lid);
+        }
+        bytes_written
+    }
+
+    // true if anything changed since the last successful save_dirty
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_universe
+        || self.registry.is_dirty()
+        || self.portals.is_dirty()
+        || self.ground_items.is_dirty()
+        || self.last_simulated.is_dirty()
+        || !self.dirty_location_prims.is_empty()
+        || !self.dirty_world_prims.is_empty()
+        || !self.dirty_objects.is_empty()
+        || !self.dirty_entities.is_empty()
+        || self.locations.values().any(|loc_guard| loc_guard.is_dirty())
     }
 
     pub fn unload_lid(&mut self, lid: LocationID) {
         if let Some(lp) = self.location_prims.remove(&lid) {
-            self.sl.save_with_key(&lp, lid);
+            if self.dirty_location_prims.remove(&lid) {
+                let _ = self.sl.save_with_key(&lp, lid);
+            }
         }
-        let _ = self.locations.remove(&lid);
+        if let Some(mut loc_guard) = self.locations.remove(&lid) {
+            loc_guard.save_if_dirty(&self.sl, lid);
+        }
+        self.last_backgrounded.remove(&lid);
     }
 
     pub fn unload_wid(&mut self, wid: WorldID) {
         if let Some(wp) = self.world_prims.remove(&wid) {
-            self.sl.save_with_key(&wp, wid);
+            if self.dirty_world_prims.remove(&wid) {
+                let _ = self.sl.save_with_key(&wp, wid);
+            }
         }
         let _ = self.worlds.remove(&wid);
     }
 
     pub fn define_object(&mut self, oid: ObjectID, data: ObjectData) {
         self.objects.insert(oid, data);
+        self.dirty_objects.insert(oid);
+    }
+
+    pub fn define_entity(&mut self, eid: EntityID, components: EntityComponents) {
+        self.entities.insert(eid, components);
+        self.dirty_entities.insert(eid);
     }
 
-    pub fn define_entity(&mut self, eid: EntityID, data: EntityData) {
-        self.entities.insert(eid, data);
+    //Err (changing nothing) if the diff doesn't fit the entity
+    pub fn apply_component_diff(&mut self, eid: EntityID, diff: ComponentDiff) -> Result<(),()> {
+        self.entity_populate(eid);
+        self.entities.get_mut(&eid).unwrap().apply_diff(diff)?;
+        self.dirty_entities.insert(eid);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::{Arc,Mutex};
+    use rand::SeedableRng;
+    use super::*;
+    use std::path::PathBuf;
+    use ::points::DPoint2;
+    use super::super::{define_objects,replay_journal,create_portal_offline,ServerData,JOURNAL_NAME};
+    use super::super::npcs::NpcBehaviors;
+    use super::super::logout::{LogoutManager,LogoutPolicy,ParkedEntities};
+    use ::engine::components::{Component,Behavior};
+    use ::network::userbase::UserBase;
+
+    //as a server run has them. `seed` stands in for whatever else the run used its rng for
+    fn resources(sl : &SaverLoader, seed : u64) -> ServerResources {
+        let mut sr = inert_resources(sl);
+        sr.rng = Isaac64Rng::from_seed(&[seed]);
+        sr.register_catch_up(Box::new(NpcBehaviors));
+        sr
+    }
+
+    //nothing catches up
+    fn inert_resources(sl : &SaverLoader) -> ServerResources {
+        let mut sr = ServerResources::new(sl.clone(), Isaac64Rng::from_seed(&[0]), Duration::from_secs(0));
+        define_objects(&mut sr);
+        sr
+    }
+
+    //an empty save dir of its own
+    fn save_dir(name : &str) -> (PathBuf,SaverLoader) {
+        let dir = env::temp_dir().join(format!("{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let sl = SaverLoader::new(dir.to_str().unwrap(), false).unwrap();
+        (dir, sl)
     }
-}
\ No newline at end of file
+
+    //saves `lid` with wandering `npcs` in it, evicted a while back. returns where they stand
+    fn save_evicted_npcs(sl : &SaverLoader, lid : LocationID, npcs : &[EntityID]) -> Vec<(EntityID,DPoint2)> {
+        let mut sr = resources(sl, 1);
+        let (mut journal, _) = Journal::open(sl, JOURNAL_NAME).unwrap();
+        for &eid in npcs.iter() {
+            sr.define_entity(eid, EntityComponents::from_components(vec![Component::Behavior(Behavior::Wander)]));
+            let pt = sr.get_location(lid).free_point().unwrap();
+            sr.apply_location_diff(lid, Diff::PlaceInside(eid, pt), &mut journal).unwrap();
+        }
+        sr.last_simulated.evicted_secs_ago(lid, 120);
+        sr.save_dirty();
+        assert!(! sr.is_dirty());
+        journal.truncate().unwrap();
+        sr.get_location(lid).entity_snapshot()
+    }
+
+    #[test]
+    fn crash_after_catching_up_replays_what_was_live() {
+        let (dir, sl) = save_dir("catch_up_replay");
+        let lid = START_LOCATION_LID;
+        let npcs : Vec<EntityID> = (1..5).collect();
+        let saved = save_evicted_npcs(&sl, lid, &npcs);
+
+        //loads (catching up), accepts changes that build on that, and crashes before saving
+        let live = {
+            let mut sr = resources(&sl, 2);
+            let (mut journal, unsaved) = Journal::open(&sl, JOURNAL_NAME).unwrap();
+            assert!(unsaved.is_empty());
+            let (at, to) = {
+                let loc = sr.get_location(lid);
+                assert!(loc.entity_snapshot() != saved, "nothing caught up");
+                let at = loc.point_of(npcs[0]).unwrap();
+                (at, loc.free_point_near(at).unwrap())
+            };
+            assert!(at != to);
+            sr.apply_location_diff(lid, Diff::MoveEntityTo(npcs[0], to), &mut journal).unwrap();
+            for _ in 0..5 {
+                sr.simulate_tick(Duration::from_secs(1), &mut journal);
+            }
+            sr.journal_caught_up(&mut journal);
+            journal.flush().unwrap();
+            sr.get_location(lid).entity_snapshot()
+        };
+
+        let mut sr = resources(&sl, 3);
+        let (_journal, unsaved) = Journal::open(&sl, JOURNAL_NAME).unwrap();
+        let userbase = Arc::new(Mutex::new(UserBase::new()));
+        let mut server_data = ServerData {
+            next_eid : 0,
+            cid_to_controlling : HashMap::new(),
+            dirty : false,
+        };
+        let mut logout_manager = LogoutManager::new(LogoutPolicy::Keep, ParkedEntities::new());
+        replay_journal(unsaved, &userbase, &mut server_data, &mut sr, &mut logout_manager);
+        //caught up once, for good: replaying didn't do it again
+        assert!(sr.caught_up.is_empty());
+        assert_eq!(sr.consume_time_since_last_sim(lid), None);
+        assert_eq!(sr.get_location(lid).entity_snapshot(), live);
+
+        drop(sr);
+        drop(sl);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn offline_portal_changes_nothing_else() {
+        let (dir, sl) = save_dir("offline_portal");
+        let lid = START_LOCATION_LID;
+        let saved = save_evicted_npcs(&sl, lid, &[1, 2, 3]);
+        let (entrance, exit) = {
+            let cell = inert_resources(&sl).get_location(lid).free_point().unwrap();
+            (UniquePoint::new(lid, cell), UniquePoint::new(lid, DPoint2::new(cell.x, cell.y + 1)))
+        };
+        create_portal_offline(&sl, entrance, exit, false).unwrap();
+
+        let mut sr = inert_resources(&sl);
+        assert_eq!(sr.get_portals().exit_of(entrance), Some(exit));
+        assert!(sr.get_location(lid).objects_at(entrance.cell()).contains(&PORTAL_OID));
+        assert_eq!(sr.get_location(lid).entity_snapshot(), saved);
+        //still there for the server to catch up with
+        assert!(sr.consume_time_since_last_sim(lid).is_some());
+
+        drop(sr);
+        drop(sl);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
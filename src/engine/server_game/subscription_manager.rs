@@ -31,6 +31,15 @@ impl SubscriptionManager {
 		}
 	}
 
+	pub fn subscribers_exist_for(&self, lid: LocationID) -> bool {
+		self.subs.contains_key(&lid)
+	}
+
+	//every location someone is subscribed to
+	pub fn subscribed_lids(&self) -> Vec<LocationID> {
+		self.subs.keys().cloned().collect()
+	}
+
 	pub fn iter_subs_for(&self, lid: LocationID) -> ClientIDSetIntoIterator {
 		match self.subs.get(&lid) {
 			Some(s) => s.iter_set_pos(),
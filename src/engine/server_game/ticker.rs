@@ -0,0 +1,113 @@
+use std::time::{Instant,Duration,SystemTime,UNIX_EPOCH};
+use std::thread;
+use ::identity::Tick;
+use ::utils::traits::*;
+
+/*
+Paces the server's fixed timestep. Every tick simulates the same span of game time,
+however long its work took. A tick that runs late is followed by ones that run back to back
+until the loop is on schedule again; if it falls too far behind, the missed time is dropped.
+Tick numbers start from the wall clock, or just past the last tick the previous run saved
+(see LastTick) if that is later, so they keep rising across restarts even if the clock goes back.
+*/
+
+//falling further behind than this many ticks drops the missed time instead of catching up
+pub const MAX_TICKS_BEHIND : u32 = 16;
+
+//the tick the server was on at its last autosave. later ticks are journaled (JournalEntry::Tick)
+#[derive(Debug,Serialize,Deserialize)]
+pub struct LastTick {
+    pub tick : Tick,
+}
+
+impl KnowsSavePrefix for LastTick {
+    fn get_save_prefix() -> String {
+        "last_tick".to_owned()
+    }
+}
+
+#[derive(Debug)]
+pub struct Ticker {
+    tick : Tick,
+    period : Duration,
+    tick_started_at : Instant,
+    next_tick_at : Instant,
+    overruns : u64,
+}
+
+impl Ticker {
+    //`last_tick` is the latest tick a previous run got to, if any
+    pub fn new(ticks_per_sec : u64, last_tick : Option<Tick>) -> Ticker {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH)
+        .expect("system clock is before 1970!");
+        let now = Instant::now();
+        let period = Duration::from_millis(1000/ticks_per_sec);
+        let from_clock = since_epoch.as_secs() * ticks_per_sec;
+        Ticker {
+            tick : match last_tick {
+                Some(t) if t >= from_clock => t + 1,
+                _ => from_clock,
+            },
+            period : period,
+            tick_started_at : now,
+            next_tick_at : now + period,
+            overruns : 0,
+        }
+    }
+
+    //the tick being simulated right now
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    //game time that passes in one tick
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    //ticks whose work took longer than a tick, since startup
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    //call when the current tick's work is done. returns once the next tick is due
+    pub fn wait_for_next(&mut self) {
+        let worked = self.tick_started_at.elapsed();
+        if worked > self.period {
+            self.overruns += 1;
+            println!("Tick {} overran: took {:?} of {:?} ({} overruns so far)",
+                self.tick, worked, self.period, self.overruns);
+        }
+        let now = Instant::now();
+        if now < self.next_tick_at {
+            thread::sleep(self.next_tick_at - now);
+        } else if now - self.next_tick_at > self.period * MAX_TICKS_BEHIND {
+            println!("Tick {} is {:?} behind schedule. Dropping the missed time",
+                self.tick, now - self.next_tick_at);
+            self.next_tick_at = now;
+        }
+        self.tick += 1;
+        self.tick_started_at = Instant::now();
+        self.next_tick_at += self.period;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_after_a_later_saved_tick() {
+        let from_clock = Ticker::new(32, None).tick();
+        //the clock went back since that was saved
+        let saved = from_clock + 1000;
+        assert_eq!(Ticker::new(32, Some(saved)).tick(), saved + 1);
+    }
+
+    #[test]
+    fn clock_wins_over_an_older_saved_tick() {
+        let from_clock = Ticker::new(32, None).tick();
+        let resumed = Ticker::new(32, Some(from_clock - 1000)).tick();
+        assert!(resumed >= from_clock && resumed < from_clock + 32);
+    }
+}
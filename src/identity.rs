@@ -14,6 +14,8 @@ pub type LocationID = u32;
 pub type ClientID = u16;
 pub type AssetID = u16;
 pub type ObjectID = u64;
+//index into items::ITEM_DEFS
+pub type ItemID = u32;
 pub type CompleteOID = (WorldID,ObjectID);
 pub type WorldID = u32;
 pub type ZoneID = usize;
@@ -32,12 +34,30 @@ impl KnowsSaveSuffix for ObjectID {
 }
 
 pub type SuperSeed = u64;
+//number of a server simulation tick. see server_game::ticker
+pub type Tick = u64;
+//numbers a client's move orders, so replies can be matched to them
+pub type MoveSeq = u32;
 
 //////////////////////////////////////////////////////////////////////////////////
 #[derive(Debug,Copy,Clone,Serialize,Deserialize,PartialEq,Eq,Hash)]
 pub struct UniquePoint {
-    lid: LocationID,
-    c_pt: CPoint2
+    pub lid: LocationID,
+    pub c_pt: CPoint2
+}
+
+impl UniquePoint {
+    pub fn new(lid: LocationID, cell: DPoint2) -> UniquePoint {
+        UniquePoint {
+            lid: lid,
+            c_pt: cell.continuous(),
+        }
+    }
+
+    //the location cell this point is in
+    pub fn cell(&self) -> DPoint2 {
+        self.c_pt.discrete()
+    }
 }
 
 
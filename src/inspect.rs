@@ -0,0 +1,192 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use bincode;
+use std::fs::File;
+use std::io::prelude::Read;
+use saving::SaverLoader;
+use utils::traits::KnowsSavePrefix;
+use network::userbase::UserBase;
+use network::messaging::Diff;
+use engine::server_game::ServerData;
+use engine::server_game::location_registry::LocationRegistry;
+use engine::server_game::portals::Portals;
+use engine::server_game::logout::ParkedEntities;
+use engine::server_game::ground_items::GroundItems;
+use engine::server_game::catch_up::EvictionTimes;
+use engine::server_game::ticker::LastTick;
+use engine::game_state::universe::UniversePrimitive;
+use engine::game_state::locations::LocationPrimitive;
+use engine::game_state::worlds::WorldPrimitive;
+use engine::entities::{EntityData,EntityDataSet};
+use engine::components::EntityComponents;
+use engine::objects::{ObjectData,ObjectDataSet};
+
+/*
+Tooling for looking into save dirs. Every file in a save dir is one bincode-serialized value.
+Which type it holds follows from its name: either the type's KnowsSavePrefix (optionally
+followed by a `_<key>` suffix) or, for older saves, a fixed `.lel` file name.
+*/
+
+enum KeyPattern {
+    //eg "loc_prim" matches "loc_prim" and "loc_prim_3"
+    Prefix(String),
+    //eg "user_base.lel"
+    Exact(&'static str),
+}
+
+pub struct SaveKind {
+    pub type_name : &'static str,
+    //false for legacy files the game no longer reads
+    pub authoritative : bool,
+    pattern : KeyPattern,
+    decode : fn(&[u8]) -> Result<String,String>,
+    encode : fn(&str) -> Result<Vec<u8>,String>,
+}
+
+impl SaveKind {
+    //how well this kind matches the key. None if not at all. longer is better
+    fn match_len(&self, save_key : &str) -> Option<usize> {
+        match self.pattern {
+            KeyPattern::Exact(name) => {
+                if save_key == name {Some(name.len())} else {None}
+            },
+            KeyPattern::Prefix(ref prefix) => {
+                if save_key == prefix {
+                    Some(prefix.len())
+                } else if save_key.starts_with(prefix.as_str())
+                && save_key[prefix.len()..].starts_with("_")
+                && save_key[prefix.len()+1..].parse::<u64>().is_ok() {
+                    Some(prefix.len())
+                } else {
+                    None
+                }
+            },
+        }
+    }
+
+    pub fn decode(&self, bytes : &[u8]) -> Result<String,String> {
+        (self.decode)(bytes)
+    }
+
+    pub fn encode(&self, json : &str) -> Result<Vec<u8>,String> {
+        (self.encode)(json)
+    }
+}
+
+fn decode_as<X>(bytes : &[u8]) -> Result<String,String>
+where X: Serialize + DeserializeOwned {
+    let x : X = bincode::deserialize(bytes).map_err(|e| format!("bad bincode: {}", e))?;
+    serde_json::to_string_pretty(&x).map_err(|e| format!("can't represent as json: {}", e))
+}
+
+fn encode_as<X>(json : &str) -> Result<Vec<u8>,String>
+where X: Serialize + DeserializeOwned {
+    let x : X = serde_json::from_str(json).map_err(|e| format!("bad json: {}", e))?;
+    bincode::serialize(&x, bincode::Infinite).map_err(|e| format!("can't encode: {}", e))
+}
+
+fn keyed<X>(type_name : &'static str) -> SaveKind
+where X: Serialize + DeserializeOwned + KnowsSavePrefix {
+    SaveKind {
+        type_name : type_name,
+        authoritative : true,
+        pattern : KeyPattern::Prefix(X::get_save_prefix()),
+        decode : decode_as::<X>,
+        encode : encode_as::<X>,
+    }
+}
+
+fn legacy<X>(type_name : &'static str, file_name : &'static str) -> SaveKind
+where X: Serialize + DeserializeOwned {
+    SaveKind {
+        type_name : type_name,
+        authoritative : false,
+        pattern : KeyPattern::Exact(file_name),
+        decode : decode_as::<X>,
+        encode : encode_as::<X>,
+    }
+}
+
+//every type that is saved through a SaverLoader. Add new save types here!
+pub fn save_kinds() -> Vec<SaveKind> {
+    vec![
+        keyed::<UserBase>("UserBase"),
+        keyed::<ServerData>("ServerData"),
+        keyed::<LocationRegistry>("LocationRegistry"),
+        keyed::<Portals>("Portals"),
+        keyed::<ParkedEntities>("ParkedEntities"),
+        keyed::<GroundItems>("GroundItems"),
+        keyed::<EvictionTimes>("EvictionTimes"),
+        keyed::<LastTick>("LastTick"),
+        keyed::<UniversePrimitive>("UniversePrimitive"),
+        keyed::<LocationPrimitive>("LocationPrimitive"),
+        keyed::<Vec<Diff>>("Vec<Diff>"),
+        keyed::<WorldPrimitive>("WorldPrimitive"),
+        keyed::<EntityData>("EntityData"),
+        keyed::<EntityComponents>("EntityComponents"),
+        keyed::<ObjectData>("ObjectData"),
+        legacy::<UserBase>("UserBase", UserBase::SAVE_PATH),
+        legacy::<EntityDataSet>("EntityDataSet", "entity_data_set.lel"),
+        legacy::<ObjectDataSet>("ObjectDataSet", "object_data_set.lel"),
+    ]
+}
+
+pub fn save_kind_for<'a>(kinds : &'a [SaveKind], save_key : &str) -> Option<&'a SaveKind> {
+    let mut best : Option<(usize,&SaveKind)> = None;
+    for kind in kinds.iter() {
+        if let Some(len) = kind.match_len(save_key) {
+            if best.map(|b| b.0 < len).unwrap_or(true) {
+                best = Some((len, kind));
+            }
+        }
+    }
+    best.map(|b| b.1)
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+//prints every save key in the dir, what it holds and whether it decodes
+pub fn list(sl : &SaverLoader) {
+    let kinds = save_kinds();
+    let keys = sl.list_save_keys().expect("Couldn't read save dir");
+    println!("{} save keys in {:?}", keys.len(), sl.save_dir());
+    for key in keys {
+        let bytes = match sl.load_raw(&key) {
+            Ok(b) => b,
+            Err(e) => {
+                println!("\t{:<28} unreadable: {}", key, e);
+                continue;
+            },
+        };
+        match save_kind_for(&kinds, &key) {
+            Some(kind) => match kind.decode(&bytes) {
+                Ok(_) => println!("\t{:<28} {} ({} bytes)", key, kind.type_name, bytes.len()),
+                Err(e) => println!("\t{:<28} {} ({} bytes) UNDECODABLE: {}", key, kind.type_name, bytes.len(), e),
+            },
+            None => println!("\t{:<28} unknown ({} bytes)", key, bytes.len()),
+        }
+    }
+}
+
+//prints the save under `save_key` as json
+pub fn decode_key(sl : &SaverLoader, save_key : &str) -> Result<String,String> {
+    let kinds = save_kinds();
+    let kind = save_kind_for(&kinds, save_key)
+        .ok_or_else(|| format!("don't know what type `{}` holds", save_key))?;
+    let bytes = sl.load_raw(save_key).map_err(|e| format!("couldn't read `{}`: {}", save_key, e))?;
+    kind.decode(&bytes)
+}
+
+//reads json from `json_path` and saves it under `save_key`. Nothing is written unless it encodes
+pub fn encode_key(sl : &SaverLoader, save_key : &str, json_path : &str) -> Result<usize,String> {
+    let kinds = save_kinds();
+    let kind = save_kind_for(&kinds, save_key)
+        .ok_or_else(|| format!("don't know what type `{}` holds", save_key))?;
+    let mut json = String::new();
+    File::open(json_path)
+        .and_then(|mut f| f.read_to_string(&mut json))
+        .map_err(|e| format!("couldn't read `{}`: {}", json_path, e))?;
+    let bytes = kind.encode(&json)?;
+    sl.save_raw(save_key, &bytes).map_err(|e| format!("couldn't write `{}`: {}", save_key, e))
+}
@@ -0,0 +1,98 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::fs::{File,OpenOptions};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use bincode;
+use byteorder::{ReadBytesExt,WriteBytesExt,BigEndian};
+use saving::SaverLoader;
+
+/*
+Append-only log of changes made since the last full save, so that a crash loses at most
+one tick's worth of accepted changes.
+On disk it's a sequence of frames: a big-endian u32 length followed by that many bytes
+of one bincode-serialized entry. A torn final frame (crash mid-write) is dropped on open.
+*/
+pub struct Journal<E> {
+    path : PathBuf,
+    file : File,
+    pending : Vec<u8>,
+    phantom : PhantomData<E>,
+}
+
+impl<E> Journal<E> where E: Serialize + DeserializeOwned {
+
+    //opens (or creates) the journal. Also returns the entries it already held, oldest first
+    pub fn open(sl : &SaverLoader, name : &str) -> Result<(Journal<E>,Vec<E>),io::Error> {
+        let path = sl.relative_path(name);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(&path)?;
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer)?;
+
+        let mut entries = vec![];
+        let mut good_len : usize = 0;
+        while buffer.len() - good_len >= 4 {
+            let frame_len = (&buffer[good_len..good_len+4]).read_u32::<BigEndian>()? as usize;
+            let frame_start = good_len + 4;
+            if buffer.len() - frame_start < frame_len {
+                break; //torn frame
+            }
+            match bincode::deserialize(&buffer[frame_start..frame_start+frame_len]) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => break,
+            }
+            good_len = frame_start + frame_len;
+        }
+        if good_len < buffer.len() {
+            println!("Journal {:?} had {} bytes of torn entries. Dropping them", &path, buffer.len() - good_len);
+            file.set_len(good_len as u64)?;
+        }
+        file.seek(SeekFrom::Start(good_len as u64))?;
+        let journal = Journal {
+            path : path,
+            file : file,
+            pending : vec![],
+            phantom : PhantomData,
+        };
+        Ok((journal, entries))
+    }
+
+    //buffered until the next flush
+    pub fn append(&mut self, entry : &E) {
+        let bytes = bincode::serialize(entry, bincode::Infinite)
+            .expect("couldn't serialize journal entry!");
+        self.pending.write_u32::<BigEndian>(bytes.len() as u32)
+            .expect("writing to a vec can't fail");
+        self.pending.extend_from_slice(&bytes);
+    }
+
+    //true if entries were appended since the last flush
+    pub fn has_pending(&self) -> bool {
+        ! self.pending.is_empty()
+    }
+
+    //writes buffered entries through to disk. returns bytes written
+    pub fn flush(&mut self) -> Result<usize,io::Error> {
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+        self.file.write_all(&self.pending)?;
+        self.file.sync_data()?;
+        let bytes_written = self.pending.len();
+        self.pending.clear();
+        Ok(bytes_written)
+    }
+
+    //call once everything journaled is safely in a full save
+    pub fn truncate(&mut self) -> Result<(),io::Error> {
+        self.pending.clear();
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_data()?;
+        println!("Truncated journal {:?}", &self.path);
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 use std::sync::{Arc,Mutex};
 use std::thread;
+use std::path::Path;
+use std::process;
 
 extern crate array_init;
 #[macro_use]
@@ -17,6 +19,8 @@ extern crate rand;
 extern crate bidir_map;
 extern crate serde;
 extern crate bincode;
+extern crate serde_json;
+extern crate byteorder;
 extern crate gfx_device_gl;
 
 mod network;
@@ -26,12 +30,17 @@ mod saving;
 mod identity;
 mod utils;
 mod points;
+mod backups;
+mod inspect;
+mod journal;
+mod archive;
+mod bench;
 
 use identity::{ClientID};
 use network::{ProtectedQueue};
 use network::userbase::UserBase;
 use network::messaging::{MsgToClientSet,MsgFromClient,MsgToClient,MsgToServer};
-use setup::RunMode;
+use setup::{RunMode,Config};
 use saving::SaverLoader;
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////
@@ -70,10 +79,11 @@ fn main() {
                 client_in,
                 client_out,
             ).expect("Failed to spawn client");
-            let sl = SaverLoader::new(&config.save_dir().expect("NO SL DIR"));
+            let sl = open_save_dir(&config);
 
             //this call consumes the thread. It begins the client-side game loop
-            engine::client_engine(client_in2, client_out2, c_id, sl);
+            engine::client_engine(client_in2, client_out2, c_id, sl.clone());
+            sl.release_lock();
         }
 
         &RunMode::Server => {
@@ -84,7 +94,7 @@ fn main() {
             let server_out : Arc<ProtectedQueue<MsgToClientSet>> = Arc::new(ProtectedQueue::new());
             let server_out2 = server_out.clone();
 
-            let sl = SaverLoader::new(&config.save_dir().expect("NO SL DIR"));
+            let sl = open_save_dir(&config);
 
             let mut raw_userbase = load_user_base(&sl);
 
@@ -103,10 +113,8 @@ fn main() {
                 sl.clone(),
             ).expect("FAILED TO SPAWN SERVER");
 
-            let sl = SaverLoader::new(&config.save_dir().expect("NO SL DIR"));
-
             //consumes this thread to begin the game loop of the global game state aka `server game loop`
-            engine::server_engine(server_in2, server_out2, userbase2, sl);
+            engine::server_engine(server_in2, server_out2, userbase2, sl, config.server_settings());
         }
 
         &RunMode::SinglePlayer => {
@@ -126,7 +134,7 @@ fn main() {
             let client_out2 = client_out.clone();
 
 
-            let sl = SaverLoader::new(&config.save_dir().expect("NO SL DIR"));
+            let sl = open_save_dir(&config);
             let sl2 = sl.subdir_saver_loader("client_sl_dir/");
 
             let mut raw_userbase = load_user_base(&sl);
@@ -136,24 +144,116 @@ fn main() {
             println!("single login {:?}", cid);
             let userbase : Arc<Mutex<UserBase>> = Arc::new(Mutex::new(raw_userbase));
             network::spawn_coupler(server_in, server_out, client_in, client_out, cid);
+            let settings = config.server_settings();
             thread::spawn(move || {
-                engine::server_engine(server_in2, server_out2, userbase, sl);
+                engine::server_engine(server_in2, server_out2, userbase, sl, settings);
             });
             //consumes this thread to create client-side aka `local` game loop & engine
             //main thread == client thread. So if piston exists, everything exits
-            engine::client_engine(client_in2, client_out2, cid, sl2)
+            engine::client_engine(client_in2, client_out2, cid, sl2.clone());
+            //server thread's clone never drops. let go of the dir explicitly
+            sl2.release_lock();
+        }
+
+        &RunMode::Restore => {
+            //NOTE the server using this save dir must be stopped!
+            let backup_dir = config.backup_dir().expect("Need to specify save path or backup dir!");
+            match config.backup_name() {
+                Some(name) => {
+                    let save_dir = config.save_dir().expect("Need to specify save path!");
+                    if let Some(holder) = saving::lock_holder(Path::new(&save_dir)) {
+                        if ! config.steal_lock() {
+                            println!("Refusing to restore. Save dir is in use by ({}). \
+                                Stop that process, or run again with --steal_lock if it is gone.",
+                                holder.trim().replace("\n", ", "));
+                            process::exit(1);
+                        }
+                    }
+                    backups::restore(&backup_dir, &name, Path::new(&save_dir))
+                    .expect("Restore failed");
+                },
+                None => backups::print_backups(&backup_dir),
+            }
+        }
+
+        &RunMode::Inspect => {
+            //only reading is fine while a server has the dir open
+            let sl = SaverLoader::new_unlocked(&config.save_dir().expect("NO SL DIR"));
+            match (config.save_key(), config.json_path()) {
+                (None, _) => inspect::list(&sl),
+                (Some(key), None) => {
+                    match inspect::decode_key(&sl, &key) {
+                        Ok(json) => println!("{}", json),
+                        Err(e) => println!("Couldn't decode: {}", e),
+                    }
+                },
+                (Some(key), Some(json_path)) => {
+                    let sl = open_save_dir(&config);
+                    match inspect::encode_key(&sl, &key, &json_path) {
+                        Ok(bytes) => println!("Wrote {} bytes to `{}`", bytes, key),
+                        Err(e) => println!("Couldn't encode: {}", e),
+                    }
+                },
+            }
+        }
+
+        &RunMode::Export => {
+            let sl = open_save_dir(&config);
+            let archive_path = config.archive_path().expect("Need to specify archive!");
+            match archive::export(&sl, &archive_path) {
+                Ok(n) => println!("Exported {} save keys to {}", n, archive_path),
+                Err(e) => println!("Export failed: {}", e),
+            }
+        }
+
+        &RunMode::Import => {
+            let sl = open_save_dir(&config);
+            let archive_path = config.archive_path().expect("Need to specify archive!");
+            match archive::import(&sl, &archive_path) {
+                Ok(n) => println!("Imported {} save keys from {}", n, archive_path),
+                Err(e) => println!("Import failed: {}", e),
+            }
+        }
+
+        &RunMode::Bench => bench::run(),
+
+        &RunMode::Portal => {
+            //NOTE a running server takes portal files instead. see portals::CREATE_PATH
+            let sl = open_save_dir(&config);
+            let line = config.portal_line().expect("Need to specify portal!");
+            let created = engine::server_game::portals::parse_portal_line(&line)
+            .and_then(|(entrance, exit, two_way)| {
+                engine::server_game::create_portal_offline(&sl, entrance, exit, two_way)
+            });
+            match created {
+                Ok(()) => println!("Created portal `{}`", line),
+                Err(e) => println!("Portal creation failed: {}", e),
+            }
         }
     }
 }
 
+//takes the save dir for this process alone. exits if another process has it
+fn open_save_dir(config : &Config) -> SaverLoader {
+    let save_dir = config.save_dir().expect("NO SL DIR");
+    match SaverLoader::new(&save_dir, config.steal_lock()) {
+        Ok(sl) => sl,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        },
+    }
+}
+
 fn load_user_base(sl : &SaverLoader) -> UserBase {
     if let Ok(mut loaded) = sl.load_without_key::<UserBase>() {
         println!("loaded userbase file! {:?}", &loaded);
         loaded.log_everyone_out();
         loaded
     } else {
-        let u = UserBase::new();
+        let mut u = UserBase::new();
         sl.save_without_key(&u).expect("Save went bad!");
+        u.mark_clean();
         println!("Created fresh userbase save");
         u
     }
@@ -7,7 +7,7 @@ use std::time;
 use super::bound_string;
 use super::UserBaseError;
 
-use super::SingleStream;
+use super::{SingleStream,MAX_MESSAGE_BYTES};
 
 pub fn client_enter(stream : TcpStream,
                     client_in : Arc<ProtectedQueue<MsgToClient>>,
@@ -69,7 +69,7 @@ pub fn client_instigate_handshake(stream : &mut TcpStream) -> ClientID {
 
 fn client_incoming(mut stream : TcpStream, client_in : Arc<ProtectedQueue<MsgToClient>>) {
     println!("Listening for incoming messages");
-    let mut buf = [0; 1024];
+    let mut buf = vec![0; MAX_MESSAGE_BYTES];
     loop {
         //blocks until something is there
         if let Ok(msg) = stream.single_read(&mut buf) {
@@ -3,44 +3,109 @@ use super::{BoundedString,UserBaseError};
 use ::identity::*;
 use ::points::*;
 use ::engine::game_state::locations::LocationPrimitive;
-use ::engine::entities::{EntityData};
+use ::engine::components::{Component,ComponentDiff};
 use ::engine::objects::{ObjectData};
 use ::engine::game_state::worlds::WorldPrimitive;
+use ::engine::game_state::universe::UniversePrimitive;
 
 //change applied to a SINGLE location
 #[derive(Clone,Copy,Serialize,Deserialize,Debug)]
 pub enum Diff {
     MoveEntityTo(EntityID,DPoint2),
     PlaceInside(EntityID,DPoint2),
+    RemoveEntity(EntityID),
+    PlaceObject(ObjectID,DPoint2),
+    RemoveObject(ObjectID,DPoint2),
+    //the entity left the client's area of interest. it is still there; the client just can't see it
+    OutOfInterest(EntityID),
 }
 
 //PRIMITIVE
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum MsgToServer {
     CreateEntity(EntityID,DPoint2),
-    ControlMoveTo(LocationID,EntityID,DPoint2),
+    ControlMoveTo(LocationID,EntityID,DPoint2,MoveSeq),
     ClientHasDisconnected,
     ClientLogin(BoundedString,BoundedString),
-    RequestEntityData(EntityID),
+    RequestEntityComponents(EntityID),
     RequestObjectData(ObjectID),
     RequestControlling,
     RequestLocationData(LocationID),
     RequestWorldData(WorldID),
+    RequestZoneLocation(WorldID,ZoneID),
+    RequestUniverse,
+    //client's entities disagree with the last LocationDigest
+    RequestLocationSnapshot(LocationID),
+    //its entity takes what it can carry of the pile of the item at the cell. the cell must be in reach
+    PickUp(LocationID,EntityID,DPoint2,ItemID),
+    //its entity drops up to this many from the inventory slot, where it stands
+    DropItems(LocationID,EntityID,usize,u32),
+    //its entity hands up to this many from the inventory slot to the entity in reach
+    GiveItems(LocationID,EntityID,usize,u32,EntityID),
+    //its entity holds the item in the inventory slot. None holds nothing
+    Equip(EntityID,Option<usize>),
 }
 
-//PRIMITIVE
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum MsgToClient {
-    GiveEntityData(EntityID,EntityData),
+    //the components of the entity the client may see. their diffs follow as ApplyComponentDiff
+    GiveEntityComponents(EntityID,Vec<Component>),
     GiveObjectData(ObjectID,ObjectData),
-    ApplyLocationDiff(LocationID,Diff),
-    GiveControlling(EntityID,LocationID),
+    //the server tick the change happened in
+    ApplyLocationDiff(LocationID,Diff,Tick),
+    GiveControlling(EntityID,LocationID,Tick),
+    ApplyComponentDiff(EntityID,ComponentDiff,Tick),
     GiveLocationPrimitive(LocationID,LocationPrimitive),
     GiveWorldPrimitive(WorldID,WorldPrimitive),
+    GiveZoneLocation(WorldID,ZoneID,LocationID),
+    GiveUniversePrimitive(UniversePrimitive),
+    //the entity is on its way. its steps follow as ApplyLocationDiff
+    ControlMoveAccepted(LocationID,EntityID,MoveSeq,Tick),
+    //the entity can't get to that cell (anymore). it stays where it is
+    ControlMoveRejected(LocationID,EntityID,DPoint2,MoveSeq),
+    //Location::entity_digest as the server has it. sent periodically to subscribers
+    LocationDigest(LocationID,u64,Tick),
+    //every entity in the location, or the first SNAPSHOT_CHUNK of them. replaces what the client had
+    LocationSnapshot(LocationID,Vec<(EntityID,DPoint2)>,Tick),
+    //the next entities of the LocationSnapshot just sent. adds to it
+    LocationSnapshotMore(LocationID,Vec<(EntityID,DPoint2)>,Tick),
     LoginSuccessful(ClientID),
     LoginFailure(UserBaseError),
 }
 
+impl MsgToClient {
+    //the server tick of messages that change game state
+    pub fn tick(&self) -> Option<Tick> {
+        match *self {
+            MsgToClient::ApplyLocationDiff(_,_,tick) |
+            MsgToClient::GiveControlling(_,_,tick) |
+            MsgToClient::ApplyComponentDiff(_,_,tick) |
+            MsgToClient::ControlMoveAccepted(_,_,_,tick) |
+            MsgToClient::LocationSnapshot(_,_,tick) |
+            MsgToClient::LocationSnapshotMore(_,_,tick) => Some(tick),
+            _ => None,
+        }
+    }
+}
+
+//entities per snapshot message. at 16 bytes each a chunk stays well under MAX_MESSAGE_BYTES
+pub const SNAPSHOT_CHUNK : usize = 2048;
+
+//`snapshot` as a LocationSnapshot followed by as many LocationSnapshotMore as it takes
+pub fn location_snapshot(lid : LocationID, snapshot : Vec<(EntityID,DPoint2)>, tick : Tick) -> Vec<MsgToClient> {
+    if snapshot.len() <= SNAPSHOT_CHUNK {
+        return vec![MsgToClient::LocationSnapshot(lid, snapshot, tick)];
+    }
+    snapshot.chunks(SNAPSHOT_CHUNK)
+    .enumerate()
+    .map(|(i, chunk)| if i == 0 {
+        MsgToClient::LocationSnapshot(lid, chunk.to_vec(), tick)
+    } else {
+        MsgToClient::LocationSnapshotMore(lid, chunk.to_vec(), tick)
+    })
+    .collect()
+}
+
 //WRAPS MsgToServer
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub struct MsgFromClient {
@@ -54,3 +119,30 @@ pub enum MsgToClientSet {
     All(MsgToClient),
     Subset(MsgToClient,ClientIDSet),
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+    use ::network::MAX_MESSAGE_BYTES;
+
+    #[test]
+    fn big_snapshots_go_in_chunks_the_client_can_read() {
+        let snapshot : Vec<(EntityID,DPoint2)> = (0..10000).map(|i| (i as EntityID, DPoint2::new(i % 100, i / 100))).collect();
+        let msgs = location_snapshot(3, snapshot.clone(), 7);
+        assert_eq!(msgs.len(), 5);
+        let mut got = vec![];
+        for (i, msg) in msgs.iter().enumerate() {
+            let bytes = bincode::serialize(msg, bincode::Infinite).unwrap();
+            assert!(bytes.len() <= MAX_MESSAGE_BYTES);
+            match *msg {
+                MsgToClient::LocationSnapshot(3, ref chunk, 7) if i == 0 => got.extend(chunk.iter().cloned()),
+                MsgToClient::LocationSnapshotMore(3, ref chunk, 7) if i > 0 => got.extend(chunk.iter().cloned()),
+                ref x => panic!("unexpected {:?}", x),
+            }
+        }
+        assert_eq!(got, snapshot);
+        assert_eq!(location_snapshot(3, vec![], 7).len(), 1);
+    }
+}
@@ -29,6 +29,22 @@ use self::userbase::{UserBase,UserBaseError};
 // use super::engine::game_state::{Point};
 use self::messaging::*;
 
+//largest message either end will read or write. location snapshots are the biggest, so they go in chunks
+pub const MAX_MESSAGE_BYTES : usize = 1 << 16;
+
+//a write that failed because the message is too large. the stream itself is fine
+pub fn is_oversized(e : &io::Error) -> bool {
+    e.kind() == ErrorKind::InvalidInput
+}
+
+fn check_size(bytes : &[u8]) -> Result<(), io::Error> {
+    if bytes.len() > MAX_MESSAGE_BYTES {
+        return Err(io::Error::new(ErrorKind::InvalidInput,
+            format!("message of {} bytes is over MAX_MESSAGE_BYTES", bytes.len())))
+    }
+    Ok(())
+}
+
 pub fn get_user_string() -> String {
     let mut s = String::new();
     let _ = stdout().flush();
@@ -223,6 +239,9 @@ impl SingleStream for TcpStream {
         }
         let num : usize = (&*buf).read_u32::<BigEndian>().unwrap() as usize;
         println!("Received header. will now wait for {} bytes", num);
+        if num > buf.len() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "message too large for buffer"))
+        }
         let msg_slice = &mut buf[..num];
         self.read_exact(msg_slice)?;
         if let Ok(got) = bincode::deserialize(msg_slice) {
@@ -238,6 +257,7 @@ impl SingleStream for TcpStream {
         // let stringy = serde_json::to_string(&s).expect("serde outgoing json ONLY");
         // let bytes = stringy.as_bytes();
         let bytes = bincode::serialize(&s, bincode::Infinite).expect("went kk lel");
+        check_size(&bytes)?;
         let mut num : [u8;4] = [0;4];
         // println!("Writing {} bytes message `{}`", bytes.len(), &stringy);
         (&mut num[..]).write_u32::<BigEndian>(bytes.len() as u32)?;
@@ -248,6 +268,7 @@ impl SingleStream for TcpStream {
 
     fn single_write_bytes(&mut self, bytes : &[u8]) -> Result<(), io::Error> {
         println!("STARTING single_write_bytes");
+        check_size(bytes)?;
         let mut num : [u8;4] = [0;4];
         println!("Writing {} bytes message [{}]", bytes.len(), bytes_to_hex(&bytes));
         (&mut num[..]).write_u32::<BigEndian>(bytes.len() as u32)?;
@@ -6,7 +6,7 @@ use std::net::{TcpStream,TcpListener};
 use super::{ProtectedQueue,MsgFromClient,MsgToClientSet,MsgToServer,MsgToClient,UserBase};
 use super::ClientID;
 use bincode;
-use super::SingleStream;
+use super::{SingleStream,is_oversized};
 use super::super::saving::SaverLoader;
 use std::time;
 
@@ -165,8 +165,10 @@ fn serve_outgoing(streams : Arc<Mutex<HashMap<ClientID,TcpStream>>>,
                     MsgToClientSet::Only(msg, cid) => {
                         if let Some(stream) = locked_streams.get_mut(&cid){
                             println!("server outgoing write of {:?} to {:?}", &msg, &cid);
-                            if let Err(_) = stream.single_write(msg) {
-                                streams_to_remove.push(cid);
+                            match stream.single_write(msg) {
+                                Err(ref e) if is_oversized(e) => println!("NOT SENT to {:?}: {}", cid, e),
+                                Err(_) => streams_to_remove.push(cid),
+                                Ok(()) => (),
                             }
                         }
                     },
@@ -174,8 +176,10 @@ fn serve_outgoing(streams : Arc<Mutex<HashMap<ClientID,TcpStream>>>,
                         println!("server outgoing write of {:?} to ALL", &msg);
                         let msg_bytes = bincode::serialize(&msg, bincode::Infinite).expect("ech");
                         for (cid, stream) in locked_streams.iter_mut() {
-                            if let Err(_) = stream.single_write_bytes(&msg_bytes) {
-                                streams_to_remove.push(*cid);
+                            match stream.single_write_bytes(&msg_bytes) {
+                                Err(ref e) if is_oversized(e) => println!("NOT SENT to {:?}: {}", *cid, e),
+                                Err(_) => streams_to_remove.push(*cid),
+                                Ok(()) => (),
                             }
                         }
                     },
@@ -186,8 +190,10 @@ fn serve_outgoing(streams : Arc<Mutex<HashMap<ClientID,TcpStream>>>,
                         for cid in cid_set.iter_set_pos() {
                             if let Some(stream) = locked_streams.get_mut(&cid){
                                 println!("YEEE {:?}", cid);
-                                if let Err(_) = stream.single_write_bytes(&msg_bytes) {
-                                    streams_to_remove.push(cid);
+                                match stream.single_write_bytes(&msg_bytes) {
+                                    Err(ref e) if is_oversized(e) => println!("NOT SENT to {:?}: {}", cid, e),
+                                    Err(_) => streams_to_remove.push(cid),
+                                    Ok(()) => (),
                                 }
                             }
                         }
@@ -12,11 +12,15 @@ use utils::traits::*;
 #[derive(Serialize,Deserialize,Debug)]
 pub struct UserBase {
     cid_to_username : HashMap<ClientID, BoundedString>,
+    #[serde(with = "::utils::serde_pairs")]
     username_to_cid : HashMap<BoundedString, ClientID>,
     cid_to_password : HashMap<ClientID, BoundedString>,
     first_time_setup_pending : HashSet<ClientID>,
     logged_in : HashSet<ClientID>,
     next_avail_cid : ClientID,
+    //true if something worth saving changed since the last save
+    #[serde(skip)]
+    dirty : bool,
 }
 
 impl KnowsSavePrefix for UserBase {
@@ -39,6 +43,7 @@ impl UserBase {
             first_time_setup_pending : HashSet::new(),
             logged_in : HashSet::new(),
             next_avail_cid : 1, //0 reserved for server
+            dirty : true,
         }
     }
 
@@ -96,6 +101,7 @@ impl UserBase {
             self.cid_to_username.insert(cid, username);
             self.cid_to_password.insert(cid, password);
             self.first_time_setup_pending.insert(cid);
+            self.dirty = true;
             true
         }
     }
@@ -105,7 +111,22 @@ impl UserBase {
     }
 
     pub fn set_client_setup_true(&mut self, cid : ClientID) {
-        self.first_time_setup_pending.remove(&cid);
+        if self.first_time_setup_pending.remove(&cid) {
+            self.dirty = true;
+        }
+    }
+
+    //logins and logouts don't count. They aren't persisted anyway
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+
+    pub fn username_of(&self, cid : ClientID) -> Option<String> {
+        self.cid_to_username.get(&cid).map(|u| bounded_printable(*u))
     }
 
     pub fn client_is_setup(&self, cid : ClientID) -> bool {
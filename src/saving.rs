@@ -7,21 +7,149 @@ use serde::de::DeserializeOwned;
 use bincode;
 use std::path::{Path,PathBuf};
 use std::io::{ErrorKind,Error};
-use std::fs::create_dir;
-use std::fmt::Debug;
+use std::fs::{self,create_dir};
+use std::fmt::{self,Debug,Display,Formatter};
+use std::fs::OpenOptions;
+use std::env;
+use std::process;
+use std::sync::Arc;
 use ::network::userbase::UserBase;
 use utils::traits::{KnowsSaveSuffix,KnowsSavePrefix};
 
+//lives in the save dir while a process has it open. holds that process' PID and host
+pub const LOCK_FILE_NAME : &'static str = "save.lock";
+
 #[derive(Clone,Debug)]
 pub struct SaverLoader {
     save_dir : Box<PathBuf>,
+    //shared by all clones (and subdir SaverLoaders). None for unlocked tool access
+    lock : Option<Arc<SaveDirLock>>,
+}
+
+#[derive(Debug)]
+pub enum SaveDirError {
+    //someone else has the dir open. contains the lock file and its contents
+    Locked(PathBuf, String),
+    Io(io::Error),
+}
+
+impl Display for SaveDirError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &SaveDirError::Locked(ref path, ref holder) => write!(f,
+                "Save dir is in use by another process ({}). \
+                If that process is gone, delete {:?} or run again with --steal_lock.",
+                holder.trim().replace("\n", ", "), path,
+            ),
+            &SaveDirError::Io(ref e) => write!(f, "Couldn't lock save dir: {}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SaveDirLock {
+    path : PathBuf,
+}
+
+impl Drop for SaveDirLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn host_name() -> String {
+    if let Ok(h) = env::var("HOSTNAME").or_else(|_| env::var("COMPUTERNAME")) {
+        return h;
+    }
+    let mut h = String::new();
+    if File::open("/etc/hostname").and_then(|mut f| f.read_to_string(&mut h)).is_ok() {
+        h.trim().to_owned()
+    } else {
+        "unknown".to_owned()
+    }
+}
+
+fn lock_contents() -> String {
+    format!("pid={}\nhost={}\n", process::id(), host_name())
+}
+
+fn lock_field<'a>(contents : &'a str, field : &str) -> Option<&'a str> {
+    contents.lines()
+    .filter_map(|line| {
+        let mut kv = line.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(k), Some(v)) if k == field => Some(v),
+            _ => None,
+        }
+    }).next()
+}
+
+// true if the lock was taken on this host by a process that no longer runs.
+// can only tell where /proc exists. Elsewhere, stale locks need --steal_lock
+fn lock_is_stale(contents : &str) -> bool {
+    if ! Path::new("/proc/self").exists() {
+        return false;
+    }
+    match (lock_field(contents, "pid"), lock_field(contents, "host")) {
+        (Some(pid), Some(host)) => {
+            host == host_name()
+            && ! Path::new(&format!("/proc/{}", pid)).exists()
+        },
+        _ => false,
+    }
+}
+
+//contents of the lock file of `save_dir`, if some process holds it
+pub fn lock_holder(save_dir : &Path) -> Option<String> {
+    let mut contents = String::new();
+    match File::open(save_dir.join(LOCK_FILE_NAME)) {
+        Ok(mut f) => {
+            let _ = f.read_to_string(&mut contents);
+            Some(contents)
+        },
+        Err(_) => None,
+    }
 }
 
 impl SaverLoader {
-    pub fn new(save_dir : &str) -> SaverLoader {
+    /*
+    Opens the save dir for exclusive use by this process. Fails if another process has it open.
+    `steal_lock` overrides that; only for when the other process is known to be gone.
+    */
+    pub fn new(save_dir : &str, steal_lock : bool) -> Result<SaverLoader,SaveDirError> {
+        let mut me = SaverLoader::new_unlocked(save_dir);
+        let lock_path = me.relative_path(LOCK_FILE_NAME);
+        if let Some(holder) = lock_holder(me.save_dir()) {
+            if steal_lock {
+                println!("Stealing save dir lock from ({})", holder.trim().replace("\n", ", "));
+            } else if lock_is_stale(&holder) {
+                println!("Reclaiming stale save dir lock from ({})", holder.trim().replace("\n", ", "));
+            } else {
+                return Err(SaveDirError::Locked(lock_path, holder));
+            }
+            fs::remove_file(&lock_path).map_err(SaveDirError::Io)?;
+        }
+        match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+            Ok(mut f) => {
+                f.write_all(lock_contents().as_bytes()).map_err(SaveDirError::Io)?;
+            },
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+                //someone beat us to it
+                let holder = lock_holder(me.save_dir()).unwrap_or_default();
+                return Err(SaveDirError::Locked(lock_path, holder));
+            },
+            Err(e) => return Err(SaveDirError::Io(e)),
+        }
+        me.lock = Some(Arc::new(SaveDirLock {path : lock_path}));
+        Ok(me)
+    }
+
+    //for read-only tooling that may look at a dir in use. Never take this for writing!
+    pub fn new_unlocked(save_dir : &str) -> SaverLoader {
         let p = Path::new(save_dir);
         let me = SaverLoader {
-            save_dir : Box::new(p.to_path_buf())
+            save_dir : Box::new(p.to_path_buf()),
+            lock : None,
         };
         me.ensure_folder_exists("./");
         // me.ensure_folder_exists("locations/");
@@ -35,9 +163,24 @@ impl SaverLoader {
         pb.push(folder);
         SaverLoader {
             save_dir: pb,
+            lock: self.lock.clone(),
+        }
+    }
+
+    /*
+    Removes the lock file now, rather than when the last clone drops.
+    Call on the way out when other threads may still hold clones.
+    */
+    pub fn release_lock(&self) {
+        if let Some(ref lock) = self.lock {
+            let _ = fs::remove_file(&lock.path);
         }
     }
 
+    pub fn save_dir(&self) -> &Path {
+        self.save_dir.as_path()
+    }
+
     pub fn relative_path<'a>(&self, rel : &'a str) -> PathBuf {
         self.save_dir.clone().join(Path::new(rel))
     }
@@ -55,23 +198,23 @@ impl SaverLoader {
         }
     }
 
-    fn save_specific<X>(&self, x : &X, file : &str) -> Result<(), io::Error>
+    //returns the number of bytes written
+    fn save_specific<X>(&self, x : &X, file : &str) -> Result<usize, io::Error>
     where X : Serialize + Debug {
         let absolute_path = self.save_dir.join(Path::new(file));
         let mut f = File::create(absolute_path)?;
-        f.write_all(
-            & bincode::serialize(x, bincode::Infinite)
-            .expect("couldn't serialize for saving.rs!")
-        )?;
-        Ok(())
+        let bytes = bincode::serialize(x, bincode::Infinite)
+            .expect("couldn't serialize for saving.rs!");
+        f.write_all(&bytes)?;
+        Ok(bytes.len())
     }
 
-    pub fn save_without_key<X>(&self, x: &X) -> Result<(),io::Error>
+    pub fn save_without_key<X>(&self, x: &X) -> Result<usize,io::Error>
     where X: Serialize + Debug + KnowsSavePrefix {
         self.save_specific(x, &X::get_save_prefix())
     }
 
-    pub fn save_with_key<X,K>(&self, x: &X, key: K) -> Result<(),io::Error>
+    pub fn save_with_key<X,K>(&self, x: &X, key: K) -> Result<usize,io::Error>
     where X: Serialize + Debug + KnowsSavePrefix,
           K: KnowsSaveSuffix {
         self.save_specific(x, &format!("{}{}", X::get_save_prefix(), key.get_save_suffix()))
@@ -101,4 +244,34 @@ impl SaverLoader {
           K: KnowsSaveSuffix {
         self.load_specific(&format!("{}{}", X::get_save_prefix(), key.get_save_suffix()))
     }
+
+    ////////////////// RAW ACCESS (for tools. the game should use the above) //////////////////
+
+    //names of all files directly inside the save dir, sorted. eg "userbase", "loc_prim_0"
+    pub fn list_save_keys(&self) -> Result<Vec<String>,io::Error> {
+        let mut keys = vec![];
+        for entry in fs::read_dir(self.save_dir.as_path())? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                if let Some(name) = entry.file_name().to_str() {
+                    keys.push(name.to_owned());
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    pub fn load_raw(&self, save_key : &str) -> Result<Vec<u8>,io::Error> {
+        let mut f = File::open(self.save_dir.join(Path::new(save_key)))?;
+        let mut buffer = vec![];
+        f.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    pub fn save_raw(&self, save_key : &str, bytes : &[u8]) -> Result<usize,io::Error> {
+        let mut f = File::create(self.save_dir.join(Path::new(save_key)))?;
+        f.write_all(bytes)?;
+        Ok(bytes.len())
+    }
 }
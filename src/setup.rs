@@ -1,8 +1,19 @@
 
+use std::time::Duration;
+use std::path::PathBuf;
+use backups::BackupPolicy;
+use engine::server_game::logout::LogoutPolicy;
+
 pub enum RunMode {
     ClientPlayer,
     Server,
     SinglePlayer,
+    Restore,
+    Inspect,
+    Export,
+    Import,
+    Bench,
+    Portal,
 }
 
 
@@ -12,6 +23,38 @@ pub struct Config{
     run_mode : RunMode,
     port : Option<u16>,
     host : Option<String>,
+    server_settings : ServerSettings,
+    backup_dir : Option<PathBuf>,
+    backup_name : Option<String>,
+    save_key : Option<String>,
+    json_path : Option<String>,
+    steal_lock : bool,
+    archive_path : Option<String>,
+    portal_line : Option<String>,
+}
+
+//knobs for the server's game loop
+#[derive(Debug,Clone)]
+pub struct ServerSettings {
+    //how often dirty state is written to the save dir
+    pub autosave_interval : Duration,
+    //how often clients are flooded with state to resynchronize them
+    pub resync_interval : Duration,
+    //how long a location without subscribers stays loaded before it is evicted
+    pub location_retention : Duration,
+    //how far around its entity a client hears about other entities
+    pub interest_radius_meters : f64,
+    //what becomes of a logged out client's entity
+    pub logout_policy : LogoutPolicy,
+    //None if periodic backups are off
+    pub backups : Option<BackupPolicy>,
+}
+
+impl ServerSettings {
+    pub const DEFAULT_AUTOSAVE_SECS : u64 = 3;
+    pub const DEFAULT_RESYNC_SECS : u64 = 3;
+    pub const DEFAULT_RETENTION_SECS : u64 = 10;
+    pub const DEFAULT_INTEREST_METERS : f64 = 40.0;
 }
 
 impl Config {
@@ -19,6 +62,14 @@ impl Config {
     pub fn port(&self) -> Option<u16> {self.port}
     pub fn host(&self) -> Option<String> {self.host.clone()}
     pub fn save_dir(&self) -> Option<String> {self.maybe_save_dir.to_owned()}
+    pub fn server_settings(&self) -> ServerSettings {self.server_settings.clone()}
+    pub fn backup_dir(&self) -> Option<PathBuf> {self.backup_dir.clone()}
+    pub fn backup_name(&self) -> Option<String> {self.backup_name.clone()}
+    pub fn save_key(&self) -> Option<String> {self.save_key.clone()}
+    pub fn json_path(&self) -> Option<String> {self.json_path.clone()}
+    pub fn steal_lock(&self) -> bool {self.steal_lock}
+    pub fn archive_path(&self) -> Option<String> {self.archive_path.clone()}
+    pub fn portal_line(&self) -> Option<String> {self.portal_line.clone()}
 }
 
 pub fn configure() -> Config {
@@ -27,10 +78,25 @@ pub fn configure() -> Config {
             (author: "NAME <email>")
             (about: "decript.")
 
-            (@arg RUN_MODE: +required +takes_value "either `client`, `server` or `single`")
+            (@arg RUN_MODE: +required +takes_value "either `client`, `server`, `single`, `restore`, `inspect`, `export`, `import`, `bench` or `portal`")
             (@arg IP: -i --ip +takes_value "weefwfe")
             (@arg PORT: -p --port +takes_value "weefwfe")
             (@arg SAVE_PATH: -s --save_path +takes_value "The path to the dir this game's data. Will load from there and save to there.")
+            (@arg STEAL_LOCK: --steal_lock "Open the save dir even if another process holds its lock. Only for locks left by crashed processes!")
+            (@arg AUTOSAVE: --autosave +takes_value "Seconds between the server's incremental saves. Defaults to 3")
+            (@arg RETENTION: --retention +takes_value "Seconds an unwatched location stays loaded on the server. Defaults to 10")
+            (@arg INTEREST: --interest +takes_value "Meters around its entity a client sees other entities. Defaults to 40")
+            (@arg LOGOUT: --logout +takes_value "What happens to a player's entity when they log out: `keep`, `hide`, or `despawn:<secs>` after a grace period. Defaults to keep")
+            (@arg RESYNC: --resync +takes_value "Seconds between the server's state floods to clients. Defaults to 3")
+            (@arg BACKUP_EVERY: --backup_every +takes_value "Minutes between backups of the save dir. 0 turns them off. Defaults to 60")
+            (@arg KEEP_HOURLY: --keep_hourly +takes_value "Number of most recent hours to keep a backup of. Defaults to 24")
+            (@arg KEEP_DAILY: --keep_daily +takes_value "Number of most recent days to keep a backup of. Defaults to 7")
+            (@arg BACKUP_DIR: --backup_dir +takes_value "Where backups go. Defaults to <save_path>_backups")
+            (@arg BACKUP: --backup +takes_value "For `restore`: the backup to swap in, eg backup_1510000000. Lists backups if omitted")
+            (@arg KEY: -k --key +takes_value "For `inspect`: the save key to print as json, eg loc_diffs_0. Lists keys if omitted")
+            (@arg JSON: -j --json +takes_value "For `inspect`: a json file to encode and save under --key instead")
+            (@arg ARCHIVE: -a --archive +takes_value "For `export` and `import`: the world archive file to write or read")
+            (@arg PORTAL: --portal +takes_value "For `portal`: the portal to create, as \"<lid> <x> <y> <lid> <x> <y> [oneway]\"")
         ).get_matches();
 
 
@@ -38,9 +104,39 @@ pub fn configure() -> Config {
         "client" => RunMode::ClientPlayer,
         "server" => RunMode::Server,
         "single" => RunMode::SinglePlayer,
+        "restore" => RunMode::Restore,
+        "inspect" => RunMode::Inspect,
+        "export" => RunMode::Export,
+        "import" => RunMode::Import,
+        "bench" => RunMode::Bench,
+        "portal" => RunMode::Portal,
         _ => panic!("NEED TO USE A VALID RUNMODE! SEE --help"),
     };
 
+    let backup_dir : Option<PathBuf> = match matches.value_of("BACKUP_DIR") {
+        Some(s) => Some(PathBuf::from(s)),
+        None => matches.value_of("SAVE_PATH").map(BackupPolicy::default_dir_for),
+    };
+    let backup_every_mins : u64 = match matches.value_of("BACKUP_EVERY") {
+        Some(s) => s.parse().expect("--backup_every needs a number of minutes"),
+        None => BackupPolicy::DEFAULT_INTERVAL_MINS,
+    };
+    let backups = match backup_dir {
+        Some(ref dir) if backup_every_mins > 0 => Some(BackupPolicy {
+            dir : dir.clone(),
+            interval : Duration::from_secs(backup_every_mins * 60),
+            keep_hourly : match matches.value_of("KEEP_HOURLY") {
+                Some(s) => s.parse().expect("--keep_hourly needs a number"),
+                None => BackupPolicy::DEFAULT_KEEP_HOURLY,
+            },
+            keep_daily : match matches.value_of("KEEP_DAILY") {
+                Some(s) => s.parse().expect("--keep_daily needs a number"),
+                None => BackupPolicy::DEFAULT_KEEP_DAILY,
+            },
+        }),
+        _ => None,
+    };
+
     Config{
         run_mode : run_mode,
         maybe_save_dir : match matches.value_of("SAVE_PATH") {
@@ -61,5 +157,36 @@ pub fn configure() -> Config {
             Some(s) => Some(s.to_owned()),
             None => None,
         },
+
+        server_settings : ServerSettings {
+            autosave_interval : Duration::from_secs(match matches.value_of("AUTOSAVE") {
+                Some(s) => s.parse().expect("--autosave needs a number of seconds"),
+                None => ServerSettings::DEFAULT_AUTOSAVE_SECS,
+            }),
+            resync_interval : Duration::from_secs(match matches.value_of("RESYNC") {
+                Some(s) => s.parse().expect("--resync needs a number of seconds"),
+                None => ServerSettings::DEFAULT_RESYNC_SECS,
+            }),
+            location_retention : Duration::from_secs(match matches.value_of("RETENTION") {
+                Some(s) => s.parse().expect("--retention needs a number of seconds"),
+                None => ServerSettings::DEFAULT_RETENTION_SECS,
+            }),
+            interest_radius_meters : match matches.value_of("INTEREST") {
+                Some(s) => s.parse().expect("--interest needs a number of meters"),
+                None => ServerSettings::DEFAULT_INTEREST_METERS,
+            },
+            logout_policy : match matches.value_of("LOGOUT") {
+                Some(s) => LogoutPolicy::parse(s).unwrap_or_else(|e| panic!("Bad --logout: {}", e)),
+                None => LogoutPolicy::Keep,
+            },
+            backups : backups,
+        },
+        backup_dir : backup_dir,
+        backup_name : matches.value_of("BACKUP").map(|s| s.to_owned()),
+        save_key : matches.value_of("KEY").map(|s| s.to_owned()),
+        json_path : matches.value_of("JSON").map(|s| s.to_owned()),
+        steal_lock : matches.is_present("STEAL_LOCK"),
+        archive_path : matches.value_of("ARCHIVE").map(|s| s.to_owned()),
+        portal_line : matches.value_of("PORTAL").map(|s| s.to_owned()),
     }
 }
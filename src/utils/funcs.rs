@@ -7,3 +7,13 @@ pub fn sigmoid(x : f32, amplifier : f32) -> f32 {
 pub fn sig_0_pt5(x : f32, amplifier : f32) -> f32 {
     sigmoid(x * 2.0 - 1.0, amplifier) * 0.5 + 0.5
 }
+
+//FNV-1a. Stable across platforms and versions, unlike std's hasher. Not cryptographic!
+pub fn fnv1a_64(bytes : &[u8]) -> u64 {
+    let mut hash : u64 = 0xcbf29ce484222325;
+    for b in bytes.iter() {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -34,14 +34,14 @@ impl<'a, T> Iterator for TotalGridIterator<'a, T> {
 
     fn next(&mut self) -> Option<(DPoint2,&'a T)> {
         let old = self.next_element;
-        self.next_element += 1;
-        if old == self.elements.len()-1 {
+        if old >= self.elements.len() {
             None
         } else {
+            self.next_element += 1;
             Some(
                 (
-                    cell_from_index(self.width, self.next_element as i32),
-                    self.elements.get(self.next_element).expect("FUARK"),
+                    cell_from_index(self.width, old as i32),
+                    &self.elements[old],
                 )
             )
         }
@@ -83,23 +83,32 @@ impl<T> TotalGrid<T> {
     }
 
     pub fn get_mut(&mut self, x: usize, y: usize) -> &mut T {
-        &mut self.elements[x*y]
+        &mut self.elements[y*self.width as usize + x]
     }
 
     pub fn get(&self, x: usize, y: usize) -> &T {
-        &self.elements[x*y]
+        &self.elements[y*self.width as usize + x]
     }
 
     pub fn maybe_get(&self, x: usize, y: usize) -> Option<&T> {
-        self.elements.get(x*y)
+        if x >= self.width as usize {return None}
+        self.elements.get(y*self.width as usize + x)
     }
 
     pub fn maybe_get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
-        self.elements.get_mut(x*y)
+        if x >= self.width as usize {return None}
+        self.elements.get_mut(y*self.width as usize + x)
     }
 
     pub fn put(&mut self, x: usize, y: usize, element: T) {
-        self.elements[x*y] = element;
+        self.elements[y*self.width as usize + x] = element;
+    }
+}
+
+//elements are left out; there are usually far too many to read
+impl<T> ::std::fmt::Debug for TotalGrid<T> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(),::std::fmt::Error> {
+        write!(f, "TotalGrid({}x{})", self.get_width(), self.get_height())
     }
 }
 
@@ -3,3 +3,4 @@ pub mod funcs;
 pub mod noise;
 pub mod traits;
 pub mod quadtree;
+pub mod serde_pairs;
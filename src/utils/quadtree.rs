@@ -1,24 +1,544 @@
+use std::mem;
+use std::fmt;
 use ::points::*;
 
-struct QuadTree<T> {
-    branches: QuadOpt<T>,
-    tl: CPoint2,
-    br: CPoint2,
+/*
+A region quadtree over cells, holding items at points. Any number of items may share a point.
+A node holds its items in a plain list until there are more than LEAF_CAPACITY of them, then
+splits into four quadrants (single cells never split). Quadrants that empty out enough are
+merged back together.
+The tree covers a square whose side is a power of two. Inserting outside it grows the square.
+*/
+
+const LEAF_CAPACITY : usize = 8;
+
+pub struct QuadTree<T> {
+    branches : QuadOpt<T>,
+    tl : DPoint2,
+    side : i32,
+    len : usize,
 }
 
 enum QuadOpt<T> {
-    Zero,
-    One(T),
+    Leaf(Vec<(DPoint2,T)>),
+    //top left, top right, bottom left, bottom right
     Four(Box<[QuadOpt<T>; 4]>),
 }
 
+impl<T> QuadOpt<T> {
+    fn empty_four() -> QuadOpt<T> {
+        QuadOpt::Four(Box::new([
+            QuadOpt::Leaf(vec![]),
+            QuadOpt::Leaf(vec![]),
+            QuadOpt::Leaf(vec![]),
+            QuadOpt::Leaf(vec![]),
+        ]))
+    }
+
+    fn leaf_len(&self) -> Option<usize> {
+        match *self {
+            QuadOpt::Leaf(ref v) => Some(v.len()),
+            QuadOpt::Four(_) => None,
+        }
+    }
+}
+
+//which quadrant of the node at `tl` with `side` holds `pt`, and where that quadrant starts
+fn quadrant(tl : DPoint2, side : i32, pt : DPoint2) -> (usize, DPoint2) {
+    let half = side / 2;
+    let right = pt.x >= tl.x + half;
+    let below = pt.y >= tl.y + half;
+    let index = (right as usize) + 2 * (below as usize);
+    (index, quadrant_tl(tl, half, index))
+}
+
+fn quadrant_tl(tl : DPoint2, half : i32, index : usize) -> DPoint2 {
+    DPoint2::new(
+        tl.x + if index & 1 == 1 {half} else {0},
+        tl.y + if index & 2 == 2 {half} else {0},
+    )
+}
+
+//squared distance from `pt` to the nearest cell of the node at `tl` with `side`
+fn dist_sq_to_node(tl : DPoint2, side : i32, pt : DPoint2) -> i64 {
+    let axis = |p : i32, lo : i32| -> i64 {
+        if p < lo {
+            (lo - p) as i64
+        } else if p > lo + side - 1 {
+            (p - (lo + side - 1)) as i64
+        } else {
+            0
+        }
+    };
+    let (dx, dy) = (axis(pt.x, tl.x), axis(pt.y, tl.y));
+    dx * dx + dy * dy
+}
+
+fn dist_sq(a : DPoint2, b : DPoint2) -> i64 {
+    let (dx, dy) = ((a.x - b.x) as i64, (a.y - b.y) as i64);
+    dx * dx + dy * dy
+}
+
+fn insert_into<T>(node : &mut QuadOpt<T>, tl : DPoint2, side : i32, pt : DPoint2, item : T) {
+    let overfull = match *node {
+        QuadOpt::Four(ref mut kids) => {
+            let (i, kid_tl) = quadrant(tl, side, pt);
+            insert_into(&mut kids[i], kid_tl, side / 2, pt, item);
+            return;
+        },
+        QuadOpt::Leaf(ref mut v) => {
+            v.push((pt, item));
+            v.len() > LEAF_CAPACITY && side > 1
+        },
+    };
+    if overfull {
+        let items = match mem::replace(node, QuadOpt::empty_four()) {
+            QuadOpt::Leaf(v) => v,
+            QuadOpt::Four(_) => unreachable!(),
+        };
+        for (p, it) in items {
+            insert_into(node, tl, side, p, it);
+        }
+    }
+}
+
+fn remove_from<T : PartialEq>(node : &mut QuadOpt<T>, tl : DPoint2, side : i32, pt : DPoint2, item : &T) -> Option<T> {
+    let removed = match *node {
+        QuadOpt::Leaf(ref mut v) => {
+            return v.iter().position(|&(p, ref it)| p == pt && it == item)
+            .map(|i| v.swap_remove(i).1);
+        },
+        QuadOpt::Four(ref mut kids) => {
+            let (i, kid_tl) = quadrant(tl, side, pt);
+            remove_from(&mut kids[i], kid_tl, side / 2, pt, item)
+        },
+    };
+    if removed.is_some() {
+        merge_if_sparse(node);
+    }
+    removed
+}
+
+//turns four leaves back into one if it would fit
+fn merge_if_sparse<T>(node : &mut QuadOpt<T>) {
+    let sparse = match *node {
+        QuadOpt::Four(ref kids) => {
+            let lens : Option<Vec<usize>> = kids.iter().map(|k| k.leaf_len()).collect();
+            match lens {
+                Some(lens) => lens.iter().sum::<usize>() <= LEAF_CAPACITY,
+                None => false,
+            }
+        },
+        QuadOpt::Leaf(_) => false,
+    };
+    if sparse {
+        if let QuadOpt::Four(kids) = mem::replace(node, QuadOpt::Leaf(vec![])) {
+            let kids : Box<[QuadOpt<T>]> = kids;
+            let mut merged = vec![];
+            for kid in kids.into_vec() {
+                if let QuadOpt::Leaf(v) = kid {
+                    merged.extend(v);
+                }
+            }
+            *node = QuadOpt::Leaf(merged);
+        }
+    }
+}
+
+fn range_of<'a, T>(node : &'a QuadOpt<T>, tl : DPoint2, side : i32,
+                   q_tl : DPoint2, q_br : DPoint2, found : &mut Vec<(DPoint2,&'a T)>) {
+    //q_br is inclusive here
+    if tl.x > q_br.x || tl.y > q_br.y || tl.x + side - 1 < q_tl.x || tl.y + side - 1 < q_tl.y {
+        return;
+    }
+    match *node {
+        QuadOpt::Leaf(ref v) => {
+            for &(p, ref it) in v.iter() {
+                if p.x >= q_tl.x && p.x <= q_br.x && p.y >= q_tl.y && p.y <= q_br.y {
+                    found.push((p, it));
+                }
+            }
+        },
+        QuadOpt::Four(ref kids) => {
+            let half = side / 2;
+            for (i, kid) in kids.iter().enumerate() {
+                range_of(kid, quadrant_tl(tl, half, i), half, q_tl, q_br, found);
+            }
+        },
+    }
+}
+
+fn nearest_in<'a, T, F>(node : &'a QuadOpt<T>, tl : DPoint2, side : i32, pt : DPoint2,
+                        accept : &F, best : &mut Option<(i64,DPoint2,&'a T)>)
+where F : Fn(&T) -> bool {
+    if let Some((best_dist, _, _)) = *best {
+        if dist_sq_to_node(tl, side, pt) > best_dist {
+            return;
+        }
+    }
+    match *node {
+        QuadOpt::Leaf(ref v) => {
+            for &(p, ref it) in v.iter() {
+                let d = dist_sq(p, pt);
+                let closer = match *best {
+                    Some((best_dist, _, _)) => d < best_dist,
+                    None => true,
+                };
+                if closer && accept(it) {
+                    *best = Some((d, p, it));
+                }
+            }
+        },
+        QuadOpt::Four(ref kids) => {
+            //closest quadrants first, so the rest are more likely pruned
+            let half = side / 2;
+            let mut order : Vec<(i64,usize)> = (0..4)
+            .map(|i| (dist_sq_to_node(quadrant_tl(tl, half, i), half, pt), i))
+            .collect();
+            order.sort();
+            for &(_, i) in order.iter() {
+                nearest_in(&kids[i], quadrant_tl(tl, half, i), half, pt, accept, best);
+            }
+        },
+    }
+}
+
 impl<T> QuadTree<T> {
-    pub fn new(tl: CPoint2, br: CPoint2) -> Self {
+    //an empty tree covering at least the cells from `tl` up to (excluding) `br`
+    pub fn new(tl : DPoint2, br : DPoint2) -> Self {
+        let needed = ::std::cmp::max(br.x - tl.x, br.y - tl.y);
+        let mut side = 1;
+        while side < needed {
+            side *= 2;
+        }
         QuadTree {
-            branches: QuadOpt::Zero,
-            tl: tl,
-            br: br,
+            branches : QuadOpt::Leaf(vec![]),
+            tl : tl,
+            side : side,
+            len : 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn covers(&self, pt : DPoint2) -> bool {
+        pt.x >= self.tl.x && pt.y >= self.tl.y
+        && pt.x < self.tl.x + self.side && pt.y < self.tl.y + self.side
+    }
+
+    //doubles the covered square, extending it towards `pt`
+    fn grow_towards(&mut self, pt : DPoint2) {
+        let left = pt.x < self.tl.x;
+        let up = pt.y < self.tl.y;
+        //where the old square ends up within the new one
+        let index = (left as usize) + 2 * (up as usize);
+        let old_tl = self.tl;
+        self.tl = DPoint2::new(
+            if left {old_tl.x - self.side} else {old_tl.x},
+            if up {old_tl.y - self.side} else {old_tl.y},
+        );
+        self.side *= 2;
+        if self.branches.leaf_len() == Some(0) {
+            return;
+        }
+        let old = mem::replace(&mut self.branches, QuadOpt::empty_four());
+        if let QuadOpt::Four(ref mut kids) = self.branches {
+            kids[index] = old;
+        }
+    }
+
+    pub fn insert(&mut self, pt : DPoint2, item : T) {
+        while ! self.covers(pt) {
+            self.grow_towards(pt);
+        }
+        insert_into(&mut self.branches, self.tl, self.side, pt, item);
+        self.len += 1;
+    }
+
+    //everything in the square from `tl` to `br`, both inclusive
+    pub fn range(&self, tl : DPoint2, br : DPoint2) -> Vec<(DPoint2,&T)> {
+        let mut found = vec![];
+        range_of(&self.branches, self.tl, self.side, tl, br, &mut found);
+        found
+    }
+
+    //everything no more than `radius` cells from `center` along either axis
+    pub fn within(&self, center : DPoint2, radius : i32) -> Vec<(DPoint2,&T)> {
+        self.range(
+            DPoint2::new(center.x - radius, center.y - radius),
+            DPoint2::new(center.x + radius, center.y + radius),
+        )
+    }
+
+    //everything at exactly `pt`
+    pub fn at<'a>(&'a self, pt : DPoint2) -> Box<Iterator<Item=&'a T> + 'a> {
+        let mut node = &self.branches;
+        let (mut tl, mut side) = (self.tl, self.side);
+        if ! self.covers(pt) {
+            return Box::new(None.into_iter());
+        }
+        loop {
+            match *node {
+                QuadOpt::Leaf(ref v) => {
+                    return Box::new(
+                        v.iter().filter(move |&&(p, _)| p == pt).map(|&(_, ref it)| it)
+                    );
+                },
+                QuadOpt::Four(ref kids) => {
+                    let (i, kid_tl) = quadrant(tl, side, pt);
+                    node = &kids[i];
+                    tl = kid_tl;
+                    side /= 2;
+                },
+            }
+        }
+    }
+
+    //the closest item to `pt` (by straight line) that `accept` likes, and where it is
+    pub fn nearest_where<F>(&self, pt : DPoint2, accept : F) -> Option<(DPoint2,&T)>
+    where F : Fn(&T) -> bool {
+        let mut best = None;
+        nearest_in(&self.branches, self.tl, self.side, pt, &accept, &mut best);
+        best.map(|(_, p, it)| (p, it))
+    }
+
+    pub fn nearest(&self, pt : DPoint2) -> Option<(DPoint2,&T)> {
+        self.nearest_where(pt, |_| true)
+    }
+}
+
+impl<T : PartialEq> QuadTree<T> {
+    //takes `item` out from `pt`. None if it wasn't there
+    pub fn remove(&mut self, pt : DPoint2, item : &T) -> Option<T> {
+        if ! self.covers(pt) {
+            return None;
+        }
+        let removed = remove_from(&mut self.branches, self.tl, self.side, pt, item);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    //moves `item` from `from` to `to`. false (changing nothing) if it wasn't at `from`
+    pub fn move_item(&mut self, from : DPoint2, to : DPoint2, item : &T) -> bool {
+        match self.remove(from, item) {
+            Some(it) => {
+                self.insert(to, it);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+impl<T> fmt::Debug for QuadTree<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(),fmt::Error> {
+        write!(f, "QuadTree({} items in {}x{} from {:?})", self.len, self.side, self.side, self.tl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use rand::{Rng,SeedableRng,Isaac64Rng};
+    use super::*;
+
+    fn pt(x : i32, y : i32) -> DPoint2 {
+        DPoint2::new(x, y)
+    }
+
+    fn is_leaf<T>(tree : &QuadTree<T>) -> bool {
+        tree.branches.leaf_len().is_some()
+    }
+
+    fn sorted(mut v : Vec<(DPoint2,u32)>) -> Vec<(DPoint2,u32)> {
+        v.sort_by_key(|&(p, it)| (p.x, p.y, it));
+        v
+    }
+
+    fn tree_range(tree : &QuadTree<u32>, tl : DPoint2, br : DPoint2) -> Vec<(DPoint2,u32)> {
+        sorted(tree.range(tl, br).into_iter().map(|(p, it)| (p, *it)).collect())
+    }
+
+    #[test]
+    fn grows_towards_negative_coordinates() {
+        let mut tree = QuadTree::new(pt(0, 0), pt(4, 4));
+        tree.insert(pt(1, 1), 1);
+        tree.insert(pt(-1, -1), 2);
+        assert!(tree.tl.x <= -1 && tree.tl.y <= -1);
+        tree.insert(pt(-100, 50), 3);
+        tree.insert(pt(70, -300), 4);
+        assert_eq!(tree.len(), 4);
+        for &(p, it) in [(pt(1, 1), 1), (pt(-1, -1), 2), (pt(-100, 50), 3), (pt(70, -300), 4)].iter() {
+            assert_eq!(tree.at(p).cloned().collect::<Vec<_>>(), vec![it]);
+        }
+        assert_eq!(tree_range(&tree, pt(-100, -1), pt(1, 50)), vec![(pt(-100, 50), 3), (pt(-1, -1), 2), (pt(1, 1), 1)]);
+        assert_eq!(tree.nearest(pt(-90, 40)), Some((pt(-100, 50), &3)));
+    }
+
+    #[test]
+    fn splits_past_leaf_capacity_and_merges_back() {
+        let mut tree = QuadTree::new(pt(0, 0), pt(16, 16));
+        for i in 0..LEAF_CAPACITY as i32 {
+            tree.insert(pt(i, i), i as u32);
+        }
+        assert!(is_leaf(&tree));
+        tree.insert(pt(15, 0), 99);
+        assert!(! is_leaf(&tree));
+        assert_eq!(tree.len(), LEAF_CAPACITY + 1);
+        assert_eq!(tree.remove(pt(15, 0), &99), Some(99));
+        assert!(is_leaf(&tree));
+        assert_eq!(tree_range(&tree, pt(0, 0), pt(15, 15)).len(), LEAF_CAPACITY);
+    }
+
+    #[test]
+    fn single_cells_never_split() {
+        let mut tree = QuadTree::new(pt(0, 0), pt(8, 8));
+        let crowd = LEAF_CAPACITY as u32 * 3;
+        for i in 0..crowd {
+            tree.insert(pt(3, 5), i);
+        }
+        assert_eq!(tree.at(pt(3, 5)).count(), crowd as usize);
+        for i in 0..crowd {
+            assert_eq!(tree.remove(pt(3, 5), &i), Some(i));
+        }
+        assert!(tree.is_empty());
+        assert!(is_leaf(&tree));
+    }
+
+    #[test]
+    fn removes_one_duplicate_at_a_time() {
+        let mut tree = QuadTree::new(pt(0, 0), pt(8, 8));
+        for _ in 0..3 {
+            tree.insert(pt(2, 2), 7);
+        }
+        tree.insert(pt(2, 2), 8);
+        assert_eq!(tree.remove(pt(2, 2), &7), Some(7));
+        assert_eq!(tree.at(pt(2, 2)).filter(|it| **it == 7).count(), 2);
+        assert_eq!(tree.remove(pt(2, 2), &7), Some(7));
+        assert_eq!(tree.remove(pt(2, 2), &7), Some(7));
+        assert_eq!(tree.remove(pt(2, 2), &7), None);
+        assert_eq!(tree.at(pt(2, 2)).cloned().collect::<Vec<_>>(), vec![8]);
+        assert_eq!(tree.len(), 1);
+        //not where it is, or nowhere the tree covers
+        assert_eq!(tree.remove(pt(2, 3), &8), None);
+        assert_eq!(tree.remove(pt(-50, 2), &8), None);
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn move_item() {
+        let mut tree = QuadTree::new(pt(0, 0), pt(8, 8));
+        tree.insert(pt(1, 1), 1);
+        tree.insert(pt(1, 1), 2);
+        assert!(tree.move_item(pt(1, 1), pt(6, 7), &1));
+        assert_eq!(tree.at(pt(1, 1)).cloned().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(tree.at(pt(6, 7)).cloned().collect::<Vec<_>>(), vec![1]);
+        //outside the tree grows it
+        assert!(tree.move_item(pt(6, 7), pt(-20, 30), &1));
+        assert_eq!(tree.at(pt(-20, 30)).cloned().collect::<Vec<_>>(), vec![1]);
+        //not at `from`: changes nothing
+        assert!(! tree.move_item(pt(6, 7), pt(0, 0), &1));
+        assert!(! tree.move_item(pt(1, 1), pt(0, 0), &3));
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.at(pt(0, 0)).count(), 0);
+    }
+
+    #[test]
+    fn range_bounds_are_inclusive() {
+        let mut tree = QuadTree::new(pt(0, 0), pt(8, 8));
+        for &(p, it) in [(pt(0, 0), 1), (pt(5, 5), 2), (pt(5, 6), 3), (pt(6, 5), 4), (pt(-1, 0), 5)].iter() {
+            tree.insert(p, it);
+        }
+        assert_eq!(tree_range(&tree, pt(0, 0), pt(5, 5)), vec![(pt(0, 0), 1), (pt(5, 5), 2)]);
+        assert_eq!(tree_range(&tree, pt(5, 5), pt(5, 5)), vec![(pt(5, 5), 2)]);
+        assert_eq!(tree_range(&tree, pt(5, 5), pt(6, 6)).len(), 3);
+        assert_eq!(tree_range(&tree, pt(6, 6), pt(5, 5)), vec![]);
+        assert_eq!(tree.within(pt(5, 5), 1).len(), 3);
+        assert_eq!(tree.within(pt(0, 0), 1).len(), 2);
+    }
+
+    #[test]
+    fn nearest_where_skips_rejects_and_prunes() {
+        let mut tree = QuadTree::new(pt(0, 0), pt(256, 256));
+        let mut n = 0;
+        for x in 0..32 {
+            for y in 0..32 {
+                tree.insert(pt(x * 8, y * 8), n);
+                n += 1;
+            }
+        }
+        let looked_at = Cell::new(0);
+        let found = tree.nearest_where(pt(1, 1), |_| {looked_at.set(looked_at.get() + 1); true});
+        assert_eq!(found, Some((pt(0, 0), &0)));
+        //far away quadrants were never visited
+        assert!(looked_at.get() < n as usize / 10, "looked at {} of {}", looked_at.get(), n);
+
+        //the closest ones don't count: the next closest does
+        assert_eq!(tree.nearest_where(pt(1, 1), |it| *it != 0), Some((pt(0, 8), &1)));
+        assert_eq!(tree.nearest_where(pt(1, 1), |it| *it > n - 2), Some((pt(248, 248), &(n - 1))));
+        assert_eq!(tree.nearest_where(pt(1, 1), |_| false), None);
+        assert_eq!(QuadTree::<u32>::new(pt(0, 0), pt(4, 4)).nearest(pt(0, 0)), None);
+    }
+
+    #[test]
+    fn agrees_with_brute_force() {
+        let mut rng = Isaac64Rng::from_seed(&[46]);
+        let mut tree = QuadTree::new(pt(0, 0), pt(16, 16));
+        let mut all : Vec<(DPoint2,u32)> = vec![];
+        let random_pt = |rng : &mut Isaac64Rng| pt(rng.gen_range(-40, 40), rng.gen_range(-40, 40));
+        for round in 0..2000 {
+            match rng.gen_range(0, 10) {
+                0...4 => {
+                    //few distinct items, so points often hold duplicates
+                    let (p, it) = (random_pt(&mut rng), rng.gen_range(0, 5));
+                    tree.insert(p, it);
+                    all.push((p, it));
+                },
+                5...7 if ! all.is_empty() => {
+                    let i = rng.gen_range(0, all.len());
+                    let (p, it) = all.swap_remove(i);
+                    assert_eq!(tree.remove(p, &it), Some(it));
+                },
+                8 if ! all.is_empty() => {
+                    let i = rng.gen_range(0, all.len());
+                    let to = random_pt(&mut rng);
+                    assert!(tree.move_item(all[i].0, to, &all[i].1));
+                    all[i].0 = to;
+                },
+                _ => {
+                    let (p, it) = (random_pt(&mut rng), rng.gen_range(0, 5));
+                    let expected = all.iter().position(|x| *x == (p, it)).map(|i| all.swap_remove(i).1);
+                    assert_eq!(tree.remove(p, &it), expected);
+                },
+            }
+            assert_eq!(tree.len(), all.len());
+
+            let (a, b) = (random_pt(&mut rng), random_pt(&mut rng));
+            let (tl, br) = (pt(a.x.min(b.x), a.y.min(b.y)), pt(a.x.max(b.x), a.y.max(b.y)));
+            let expected = sorted(all.iter().cloned()
+                .filter(|&(p, _)| p.x >= tl.x && p.x <= br.x && p.y >= tl.y && p.y <= br.y)
+                .collect());
+            assert_eq!(tree_range(&tree, tl, br), expected, "round {}", round);
+
+            let q = random_pt(&mut rng);
+            let wanted = rng.gen_range(0, 5);
+            for &only in [None, Some(wanted)].iter() {
+                let ok = |it : &u32| only.map_or(true, |w| *it == w);
+                let closest = all.iter().filter(|&&(_, it)| ok(&it)).map(|&(p, _)| dist_sq(p, q)).min();
+                let found = tree.nearest_where(q, |it| ok(it));
+                assert_eq!(found.map(|(p, _)| dist_sq(p, q)), closest, "round {}", round);
+                if let Some((p, it)) = found {
+                    assert!(ok(it) && all.contains(&(p, *it)));
+                }
+            }
         }
     }
-    //TODO
 }
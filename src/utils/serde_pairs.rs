@@ -0,0 +1,25 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use serde::{Serialize,Serializer,Deserialize,Deserializer};
+
+/*
+For #[serde(with = "::utils::serde_pairs")] on a HashMap field.
+Stores the map as a sequence of (key,value) pairs, so keys don't have to be strings
+(json can't have array or struct keys). Bincode lays out a sequence of pairs exactly
+like a map, so existing save files still load.
+*/
+
+pub fn serialize<K,V,S>(map : &HashMap<K,V>, serializer : S) -> Result<S::Ok, S::Error>
+where K: Serialize + Hash + Eq,
+      V: Serialize,
+      S: Serializer {
+    serializer.collect_seq(map.iter())
+}
+
+pub fn deserialize<'de,K,V,D>(deserializer : D) -> Result<HashMap<K,V>, D::Error>
+where K: Deserialize<'de> + Hash + Eq,
+      V: Deserialize<'de>,
+      D: Deserializer<'de> {
+    let pairs : Vec<(K,V)> = Deserialize::deserialize(deserializer)?;
+    Ok(pairs.into_iter().collect())
+}